@@ -0,0 +1,275 @@
+// Allow and deny lists of peers and entities, kept in the node's private
+// AccessListsDoc so they survive restarts
+use crate::docs::{DocError, DocId, Docs, Namespace, ACCESS_LISTS_DOC};
+use crate::entity::{EntityId, ENTITY_LABEL_PREFIX};
+use crate::policy::Rejection;
+use libp2p::core::Endpoint;
+use libp2p::swarm::{
+    dummy, ConnectionDenied, ConnectionId, FromSwarm, NetworkBehaviour, THandler, THandlerInEvent, THandlerOutEvent,
+    ToSwarm,
+};
+use libp2p::{Multiaddr, PeerId};
+use std::collections::BTreeSet;
+use std::fmt;
+use std::str::FromStr;
+use std::task::{Context, Poll};
+
+const PEER_PREFIX: &str = "peer/";
+const ENTITY_PREFIX: &str = "entity/";
+const EXCLUSIVE_PREFIX: &str = "exclusive/";
+
+/// Which list
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum List {
+    Allow,
+    Deny,
+}
+
+impl List {
+    fn key(self) -> &'static str {
+        match self {
+            List::Allow => "allow/",
+            List::Deny => "deny/",
+        }
+    }
+}
+
+/// What a list entry is about
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Subject {
+    Peer(PeerId),
+    /// The entity's document and its RelationshipDoc
+    Entity(EntityId),
+}
+
+impl fmt::Display for Subject {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Subject::Peer(peer) => write!(f, "{PEER_PREFIX}{peer}"),
+            Subject::Entity(entity) => write!(f, "{ENTITY_PREFIX}{entity}"),
+        }
+    }
+}
+
+impl FromStr for Subject {
+    type Err = DocError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Some(peer) = s.strip_prefix(PEER_PREFIX) {
+            return peer.parse().map(Subject::Peer).map_err(|e| DocError::InvalidId(format!("{e}")));
+        }
+        match s.strip_prefix(ENTITY_PREFIX) {
+            Some(entity) => entity.parse().map(Subject::Entity),
+            None => Err(DocError::InvalidId(format!("'{s}' is neither {PEER_PREFIX}<id> nor {ENTITY_PREFIX}<id>"))),
+        }
+    }
+}
+
+/// The kind of subject an exclusive allow list applies to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Scope {
+    Peers,
+    Entities,
+}
+
+impl Scope {
+    fn key(self) -> String {
+        match self {
+            Scope::Peers => format!("{EXCLUSIVE_PREFIX}peers"),
+            Scope::Entities => format!("{EXCLUSIVE_PREFIX}entities"),
+        }
+    }
+}
+
+/// Who we talk to and what we sync. Denied subjects are always refused.
+/// Allowed peers skip the policy's trust, age and rate rules, and when a
+/// scope is exclusive, subjects of that kind not on the allow list are
+/// refused too.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct AccessLists {
+    allow: BTreeSet<Subject>,
+    deny: BTreeSet<Subject>,
+    exclusive_peers: bool,
+    exclusive_entities: bool,
+}
+
+impl AccessLists {
+    /// The lists as stored in our AccessListsDoc, empty if there is none
+    pub fn load(docs: &Docs) -> AccessLists {
+        let mut lists = AccessLists::default();
+        let Some(doc) = docs.doc(&docs.owned_id(ACCESS_LISTS_DOC)) else { return lists };
+        for list in [List::Allow, List::Deny] {
+            let subjects = doc.list(list.key()).into_iter().filter_map(|(key, _)| key[list.key().len()..].parse::<Subject>().ok());
+            lists.subjects_mut(list).extend(subjects);
+        }
+        lists.exclusive_peers = doc.get(&Scope::Peers.key()).is_some();
+        lists.exclusive_entities = doc.get(&Scope::Entities.key()).is_some();
+        lists
+    }
+
+    /// Put `subject` on `list`, or take it off, and save the change
+    pub fn set(&mut self, docs: &mut Docs, list: List, subject: Subject, listed: bool) -> Result<(), DocError> {
+        let doc = docs.create_owned(ACCESS_LISTS_DOC)?;
+        let key = format!("{}{subject}", list.key());
+        if listed {
+            docs.set(&doc, &key, Vec::new())?;
+            self.subjects_mut(list).insert(subject);
+        } else {
+            if docs.get(&doc, &key)?.is_some() {
+                docs.delete(&doc, &key)?;
+            }
+            self.subjects_mut(list).remove(&subject);
+        }
+        Ok(())
+    }
+
+    /// Make the allow list the only subjects of `scope` accepted, or not,
+    /// and save the change
+    pub fn set_exclusive(&mut self, docs: &mut Docs, scope: Scope, exclusive: bool) -> Result<(), DocError> {
+        let doc = docs.create_owned(ACCESS_LISTS_DOC)?;
+        if exclusive {
+            docs.set(&doc, &scope.key(), Vec::new())?;
+        } else if docs.get(&doc, &scope.key())?.is_some() {
+            docs.delete(&doc, &scope.key())?;
+        }
+        match scope {
+            Scope::Peers => self.exclusive_peers = exclusive,
+            Scope::Entities => self.exclusive_entities = exclusive,
+        }
+        Ok(())
+    }
+
+    /// Subjects on `list`, in order
+    pub fn subjects(&self, list: List) -> &BTreeSet<Subject> {
+        match list {
+            List::Allow => &self.allow,
+            List::Deny => &self.deny,
+        }
+    }
+
+    pub fn is_exclusive(&self, scope: Scope) -> bool {
+        match scope {
+            Scope::Peers => self.exclusive_peers,
+            Scope::Entities => self.exclusive_entities,
+        }
+    }
+
+    /// Every peer we refuse to connect to by name. Exclusive allow lists
+    /// are enforced by `Behaviour`, as they can't be listed.
+    pub fn denied_peers(&self) -> impl Iterator<Item = PeerId> + '_ {
+        self.deny.iter().filter_map(|subject| match subject {
+            Subject::Peer(peer) => Some(*peer),
+            Subject::Entity(_) => None,
+        })
+    }
+
+    /// Whether we may talk to `peer` at all
+    pub fn peer_allowed(&self, peer: &PeerId) -> bool {
+        self.allowed(&Subject::Peer(*peer), self.exclusive_peers)
+    }
+
+    /// Whether `peer` is on the allow list, so the policy's rules are skipped
+    pub fn peer_trusted(&self, peer: &PeerId) -> bool {
+        self.allow.contains(&Subject::Peer(*peer)) && self.peer_allowed(peer)
+    }
+
+    /// Whether we may sync the document `id`. Entity documents and
+    /// RelationshipDocs are judged by their entity; other documents by
+    /// nothing here.
+    pub fn doc_allowed(&self, docs: &Docs, id: &DocId) -> bool {
+        let entity = match docs.doc(id).map(|doc| doc.namespace()) {
+            Some(Namespace::Owned { label, .. }) if label.starts_with(ENTITY_LABEL_PREFIX) => *id,
+            Some(Namespace::Relationships { entity }) => *entity,
+            _ => return true,
+        };
+        self.allowed(&Subject::Entity(entity), self.exclusive_entities)
+    }
+
+    fn allowed(&self, subject: &Subject, exclusive: bool) -> bool {
+        !self.deny.contains(subject) && (!exclusive || self.allow.contains(subject))
+    }
+
+    fn subjects_mut(&mut self, list: List) -> &mut BTreeSet<Subject> {
+        match list {
+            List::Allow => &mut self.allow,
+            List::Deny => &mut self.deny,
+        }
+    }
+}
+
+/// Refuses connections to and from peers an exclusive allow list leaves
+/// out, before they are established. Peers denied by name are left to
+/// `allow_block_list`.
+#[derive(Debug, Default)]
+pub struct Behaviour {
+    lists: AccessLists,
+}
+
+impl Behaviour {
+    pub fn new(lists: AccessLists) -> Self {
+        Behaviour { lists }
+    }
+
+    /// Enforce `lists` from now on. Connections already up are left to the
+    /// caller to close.
+    pub fn set_lists(&mut self, lists: AccessLists) {
+        self.lists = lists;
+    }
+
+    fn enforce(&self, peer: &PeerId) -> Result<(), ConnectionDenied> {
+        if self.lists.is_exclusive(Scope::Peers) && !self.lists.peer_allowed(peer) {
+            return Err(ConnectionDenied::new(Rejection::NotAllowed));
+        }
+        Ok(())
+    }
+}
+
+impl NetworkBehaviour for Behaviour {
+    type ConnectionHandler = dummy::ConnectionHandler;
+    type ToSwarm = void::Void;
+
+    fn handle_established_inbound_connection(
+        &mut self,
+        _: ConnectionId,
+        peer: PeerId,
+        _: &Multiaddr,
+        _: &Multiaddr,
+    ) -> Result<THandler<Self>, ConnectionDenied> {
+        self.enforce(&peer)?;
+        Ok(dummy::ConnectionHandler)
+    }
+
+    fn handle_pending_outbound_connection(
+        &mut self,
+        _: ConnectionId,
+        peer: Option<PeerId>,
+        _: &[Multiaddr],
+        _: Endpoint,
+    ) -> Result<Vec<Multiaddr>, ConnectionDenied> {
+        if let Some(peer) = peer {
+            self.enforce(&peer)?;
+        }
+        Ok(Vec::new())
+    }
+
+    fn handle_established_outbound_connection(
+        &mut self,
+        _: ConnectionId,
+        peer: PeerId,
+        _: &Multiaddr,
+        _: Endpoint,
+    ) -> Result<THandler<Self>, ConnectionDenied> {
+        self.enforce(&peer)?;
+        Ok(dummy::ConnectionHandler)
+    }
+
+    fn on_swarm_event(&mut self, _: FromSwarm) {}
+
+    fn on_connection_handler_event(&mut self, _: PeerId, _: ConnectionId, event: THandlerOutEvent<Self>) {
+        void::unreachable(event)
+    }
+
+    fn poll(&mut self, _: &mut Context<'_>) -> Poll<ToSwarm<Self::ToSwarm, THandlerInEvent<Self>>> {
+        Poll::Pending
+    }
+}
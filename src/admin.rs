@@ -0,0 +1,124 @@
+// Admin commands for a running node, read a line at a time from a local
+// console such as the binary's stdin. Nothing here is reachable by peers.
+use crate::access_list::{List, Scope, Subject};
+use crate::node::{NodeError, NodeHandle};
+use libp2p::PeerId;
+use std::{error::Error, fmt, io};
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, AsyncWrite, AsyncWriteExt};
+
+pub const HELP: &str = "\
+commands:
+  lists                                show the allow and deny lists
+  allow|disallow <subject>             put a subject on the allow list, or take it off
+  deny|undeny <subject>                put a subject on the deny list, or take it off
+  exclusive <peers|entities> <on|off>  accept only what the allow list names
+  defederate <peer> [subtree] [reason] cut a peer off, with everyone it vouches for
+  restore <peer>                       lift a defederation
+subjects are peer/<peer id> or entity/<entity id>";
+
+#[derive(Debug)]
+pub enum AdminError {
+    /// The command or its arguments didn't parse
+    Usage(String),
+    Node(NodeError),
+}
+
+impl fmt::Display for AdminError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AdminError::Usage(e) => write!(f, "{e}"),
+            AdminError::Node(e) => write!(f, "{e}"),
+        }
+    }
+}
+
+impl Error for AdminError {}
+
+impl From<NodeError> for AdminError {
+    fn from(e: NodeError) -> Self {
+        AdminError::Node(e)
+    }
+}
+
+/// Answer each line of `input` as a command on `output`, until input ends
+pub async fn serve<R, W>(node: NodeHandle, input: R, mut output: W) -> io::Result<()>
+where
+    R: AsyncBufRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    let mut lines = input.lines();
+    while let Some(line) = lines.next_line().await? {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let answer = match execute(&node, &line).await {
+            Ok(answer) => answer,
+            Err(e) => format!("error: {e}"),
+        };
+        output.write_all(format!("{answer}\n").as_bytes()).await?;
+        output.flush().await?;
+    }
+    Ok(())
+}
+
+/// Run one command against the node, returning what to show for it
+pub async fn execute(node: &NodeHandle, line: &str) -> Result<String, AdminError> {
+    let words: Vec<&str> = line.split_whitespace().collect();
+    match words.as_slice() {
+        ["help"] => Ok(HELP.to_string()),
+        ["lists"] => {
+            let lists = node.access_lists().await?;
+            let mut shown = Vec::new();
+            for (list, name) in [(List::Allow, "allow"), (List::Deny, "deny")] {
+                shown.extend(lists.subjects(list).iter().map(|subject| format!("{name} {subject}")));
+            }
+            for (scope, name) in [(Scope::Peers, "peers"), (Scope::Entities, "entities")] {
+                if lists.is_exclusive(scope) {
+                    shown.push(format!("exclusive {name}"));
+                }
+            }
+            Ok(if shown.is_empty() { "lists are empty".to_string() } else { shown.join("\n") })
+        }
+        [command @ ("allow" | "disallow" | "deny" | "undeny"), subject] => {
+            let subject: Subject = subject.parse().map_err(|e| AdminError::Usage(format!("{e}")))?;
+            let (list, listed) = match *command {
+                "allow" => (List::Allow, true),
+                "disallow" => (List::Allow, false),
+                "deny" => (List::Deny, true),
+                _ => (List::Deny, false),
+            };
+            node.set_access(list, subject, listed).await?;
+            Ok("ok".to_string())
+        }
+        ["exclusive", scope, state] => {
+            let scope = match *scope {
+                "peers" => Scope::Peers,
+                "entities" => Scope::Entities,
+                other => return Err(AdminError::Usage(format!("unknown scope '{other}'"))),
+            };
+            let exclusive = match *state {
+                "on" => true,
+                "off" => false,
+                other => return Err(AdminError::Usage(format!("expected on or off, not '{other}'"))),
+            };
+            node.set_exclusive(scope, exclusive).await?;
+            Ok("ok".to_string())
+        }
+        ["defederate", peer, rest @ ..] => {
+            let peer = parse_peer(peer)?;
+            let subtree = rest.first() == Some(&"subtree");
+            let reason = rest[usize::from(subtree)..].join(" ");
+            let cut_off = node.defederate(peer, subtree, &reason).await?;
+            Ok(format!("cut off {} peer(s)", cut_off.len()))
+        }
+        ["restore", peer] => {
+            let restored = node.restore_peer(parse_peer(peer)?).await?;
+            Ok(format!("restored {} peer(s)", restored.len()))
+        }
+        _ => Err(AdminError::Usage(format!("unknown command '{}', try help", line.trim()))),
+    }
+}
+
+fn parse_peer(peer: &str) -> Result<PeerId, AdminError> {
+    peer.parse().map_err(|e| AdminError::Usage(format!("invalid peer id '{peer}': {e}")))
+}
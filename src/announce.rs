@@ -0,0 +1,86 @@
+// Signed join announcements carrying a node's TicketReadDoc ticket
+use crate::codec::to_cbor;
+use crate::docs::{AuthorId, DocError, DocId, Namespace, TICKET_READ_DOC};
+use crate::message::now_millis;
+use crate::ticket::DocTicket;
+use libp2p::identity::{self, ed25519};
+use libp2p::PeerId;
+use serde::{Deserialize, Serialize};
+use std::{collections::HashSet, sync::Arc};
+
+pub const JOIN_TOPIC: &str = "raggy-join";
+
+const ANNOUNCE_DOMAIN: &[u8] = b"raggy/join-announce/v1";
+
+/// What a node publishes on the join topic when it starts, and again whenever
+/// a new peer subscribes, so that everyone can find its TicketReadDoc
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct JoinAnnouncement {
+    pub peer_id: PeerId,
+    pub name: String,
+    /// Read ticket for the node's TicketReadDoc
+    pub ticket: DocTicket,
+    /// Milliseconds since the Unix epoch
+    pub timestamp: u64,
+    #[serde(with = "serde_bytes")]
+    pub public_key: Vec<u8>,
+    #[serde(with = "serde_bytes")]
+    pub signature: Vec<u8>,
+}
+
+impl JoinAnnouncement {
+    pub fn new(keypair: &ed25519::Keypair, name: &str, ticket: DocTicket) -> Self {
+        let public_key = keypair.public();
+        let mut announcement = JoinAnnouncement {
+            peer_id: PeerId::from_public_key(&identity::PublicKey::from(public_key.clone())),
+            name: name.to_string(),
+            ticket,
+            timestamp: now_millis(),
+            public_key: public_key.to_bytes().to_vec(),
+            signature: Vec::new(),
+        };
+        announcement.signature = keypair.sign(&announcement.signing_bytes());
+        announcement
+    }
+
+    /// Check the signature, that the key belongs to `peer_id`, and that the
+    /// ticket really is for that node's TicketReadDoc
+    pub fn verify(&self) -> Result<(), DocError> {
+        let public_key = ed25519::PublicKey::try_from_bytes(&self.public_key).map_err(|_| DocError::InvalidSignature)?;
+        if PeerId::from_public_key(&identity::PublicKey::from(public_key.clone())) != self.peer_id {
+            return Err(DocError::InvalidSignature);
+        }
+        if !public_key.verify(&self.signing_bytes(), &self.signature) {
+            return Err(DocError::InvalidSignature);
+        }
+        let owner = AuthorId::from_bytes(public_key.to_bytes());
+        let namespace = Namespace::Owned { owner, label: TICKET_READ_DOC.to_string() };
+        if self.ticket.namespace != namespace || self.ticket.doc != DocId::owned(&owner, TICKET_READ_DOC) {
+            return Err(DocError::InvalidTicket("not the announcer's TicketReadDoc".to_string()));
+        }
+        Ok(())
+    }
+
+    fn signing_bytes(&self) -> Vec<u8> {
+        let fields = (&self.peer_id, &self.name, &self.ticket, self.timestamp, &self.public_key);
+        let mut bytes = ANNOUNCE_DOMAIN.to_vec();
+        // Encoding plain data into a Vec doesn't fail
+        bytes.extend(to_cbor(&fields).unwrap_or_default());
+        bytes
+    }
+}
+
+/// Decides which announced tickets a node joins by itself. Filters run on the
+/// node's event loop, so they should return quickly.
+pub type InterestFilter = Arc<dyn Fn(&JoinAnnouncement) -> bool + Send + Sync>;
+
+/// Join every announced ticket
+pub fn join_all() -> InterestFilter {
+    Arc::new(|_: &JoinAnnouncement| true)
+}
+
+/// Join the tickets announced by `peers` only
+pub fn join_peers(peers: impl IntoIterator<Item = PeerId>) -> InterestFilter {
+    let peers: HashSet<PeerId> = peers.into_iter().collect();
+    Arc::new(move |announcement: &JoinAnnouncement| peers.contains(&announcement.peer_id))
+}
@@ -0,0 +1,273 @@
+// Chunked, resumable blob transfer over /raggy/blob/1.0.0
+use crate::codec::{from_cbor, to_cbor};
+use crate::store::{BlobStore, Cid, StoreError};
+use libp2p::{
+    request_response::{self, ProtocolSupport},
+    StreamProtocol,
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    error::Error,
+    fmt,
+    fs::{self, File, OpenOptions},
+    io::{self, Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+pub const BLOB_PROTOCOL: StreamProtocol = StreamProtocol::new("/raggy/blob/1.0.0");
+pub const BLOB_TIMEOUT: Duration = Duration::from_secs(60);
+pub const CHUNK_SIZE: usize = 256 * 1024;
+
+/// BLAKE3 hash of a blob's full contents
+pub type BlobHash = [u8; 32];
+
+pub fn blob_hash(data: &[u8]) -> BlobHash {
+    *blake3::hash(data).as_bytes()
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum BlobRequest {
+    Manifest { hash: BlobHash },
+    Chunk { hash: BlobHash, index: u32 },
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum BlobResponse {
+    Manifest(Manifest),
+    Chunk {
+        index: u32,
+        #[serde(with = "serde_bytes")]
+        data: Vec<u8>,
+    },
+    NotFound,
+}
+
+/// Size of a blob and the hash of each of its chunks
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Manifest {
+    pub size: u64,
+    pub chunk_hashes: Vec<BlobHash>,
+}
+
+impl Manifest {
+    pub fn for_blob(data: &[u8]) -> Self {
+        Manifest {
+            size: data.len() as u64,
+            chunk_hashes: data.chunks(CHUNK_SIZE).map(blob_hash).collect(),
+        }
+    }
+
+    fn is_consistent(&self) -> bool {
+        self.chunk_hashes.len() as u64 == self.size.div_ceil(CHUNK_SIZE as u64)
+    }
+
+    fn chunk_len(&self, index: usize) -> u64 {
+        self.size.saturating_sub(index as u64 * CHUNK_SIZE as u64).min(CHUNK_SIZE as u64)
+    }
+}
+
+pub type Behaviour = request_response::cbor::Behaviour<BlobRequest, BlobResponse>;
+
+pub fn new_behaviour() -> Behaviour {
+    request_response::cbor::Behaviour::new(
+        [(BLOB_PROTOCOL, ProtocolSupport::Full)],
+        request_response::Config::default().with_request_timeout(BLOB_TIMEOUT),
+    )
+}
+
+/// Answer a request from `store`, reading only the manifest or chunk asked for
+pub fn serve(store: &BlobStore, request: &BlobRequest) -> Result<BlobResponse, StoreError> {
+    let response = match request {
+        BlobRequest::Manifest { hash } => store.manifest(&Cid::from_digest(*hash))?.map(BlobResponse::Manifest),
+        BlobRequest::Chunk { hash, index } => store
+            .read_chunk(&Cid::from_digest(*hash), *index)?
+            .map(|data| BlobResponse::Chunk { index: *index, data }),
+    };
+    Ok(response.unwrap_or(BlobResponse::NotFound))
+}
+
+#[derive(Debug, Clone)]
+pub enum BlobError {
+    NotFound,
+    InvalidManifest,
+    ChunkHashMismatch { index: u32 },
+    HashMismatch,
+    UnexpectedResponse,
+    /// Reading or writing the partial download failed
+    Io(String),
+}
+
+impl fmt::Display for BlobError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BlobError::NotFound => write!(f, "blob not found on peer"),
+            BlobError::InvalidManifest => write!(f, "peer sent an inconsistent manifest"),
+            BlobError::ChunkHashMismatch { index } => write!(f, "chunk {index} failed hash verification"),
+            BlobError::HashMismatch => write!(f, "assembled blob does not match its hash"),
+            BlobError::UnexpectedResponse => write!(f, "unexpected response from peer"),
+            BlobError::Io(e) => write!(f, "partial download i/o error: {e}"),
+        }
+    }
+}
+
+impl Error for BlobError {}
+
+impl From<io::Error> for BlobError {
+    fn from(e: io::Error) -> Self {
+        BlobError::Io(e.to_string())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BlobProgress {
+    pub received_bytes: u64,
+    pub total_bytes: u64,
+    pub chunks_received: u32,
+    pub chunks_total: u32,
+}
+
+/// State of one blob download, kept on disk under a directory so a later
+/// fetch resumes from the first missing chunk, even after a restart. The
+/// manifest is saved as `<cid>.manifest` and chunks are written in place
+/// into `<cid>.partial`, so the blob is never held in memory while it arrives.
+#[derive(Debug)]
+pub struct Download {
+    hash: BlobHash,
+    dir: PathBuf,
+    manifest: Option<Manifest>,
+    received: Vec<bool>,
+}
+
+impl Download {
+    /// Start or resume the download of `hash` in `dir`. Chunks an earlier
+    /// attempt wrote count as received once they verify against the manifest.
+    pub fn open(dir: &Path, hash: BlobHash) -> Result<Self, BlobError> {
+        fs::create_dir_all(dir)?;
+        let mut download = Download { hash, dir: dir.to_path_buf(), manifest: None, received: Vec::new() };
+        let manifest = match fs::read(download.manifest_path()) {
+            Ok(bytes) => from_cbor::<Manifest>(&bytes).ok().filter(Manifest::is_consistent),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => None,
+            Err(e) => return Err(e.into()),
+        };
+        let Some(manifest) = manifest else { return Ok(download) };
+        download.received = vec![false; manifest.chunk_hashes.len()];
+        match File::open(download.data_path()) {
+            Ok(mut file) => {
+                for (index, expected) in manifest.chunk_hashes.iter().enumerate() {
+                    let mut chunk = Vec::with_capacity(CHUNK_SIZE);
+                    file.seek(SeekFrom::Start(index as u64 * CHUNK_SIZE as u64))?;
+                    (&mut file).take(CHUNK_SIZE as u64).read_to_end(&mut chunk)?;
+                    download.received[index] = blob_hash(&chunk) == *expected;
+                }
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e.into()),
+        }
+        download.manifest = Some(manifest);
+        Ok(download)
+    }
+
+    pub fn hash(&self) -> BlobHash {
+        self.hash
+    }
+
+    fn data_path(&self) -> PathBuf {
+        self.dir.join(format!("{}.partial", Cid::from_digest(self.hash)))
+    }
+
+    fn manifest_path(&self) -> PathBuf {
+        self.dir.join(format!("{}.manifest", Cid::from_digest(self.hash)))
+    }
+
+    /// Next request to send, or `None` once every chunk has arrived
+    pub fn next_request(&self) -> Option<BlobRequest> {
+        if self.manifest.is_none() {
+            return Some(BlobRequest::Manifest { hash: self.hash });
+        }
+        self.received
+            .iter()
+            .position(|received| !received)
+            .map(|index| BlobRequest::Chunk { hash: self.hash, index: index as u32 })
+    }
+
+    pub fn on_response(&mut self, response: BlobResponse) -> Result<BlobProgress, BlobError> {
+        match response {
+            BlobResponse::NotFound => return Err(BlobError::NotFound),
+            BlobResponse::Manifest(manifest) if self.manifest.is_none() => {
+                if !manifest.is_consistent() {
+                    return Err(BlobError::InvalidManifest);
+                }
+                let bytes = to_cbor(&manifest).map_err(|e| BlobError::Io(e.to_string()))?;
+                File::create(self.data_path())?.set_len(manifest.size)?;
+                fs::write(self.manifest_path(), bytes)?;
+                self.received = vec![false; manifest.chunk_hashes.len()];
+                self.manifest = Some(manifest);
+            }
+            BlobResponse::Chunk { index, data } => {
+                let expected = self
+                    .manifest
+                    .as_ref()
+                    .and_then(|manifest| manifest.chunk_hashes.get(index as usize))
+                    .ok_or(BlobError::UnexpectedResponse)?;
+                if blob_hash(&data) != *expected {
+                    return Err(BlobError::ChunkHashMismatch { index });
+                }
+                let mut file = OpenOptions::new().write(true).open(self.data_path())?;
+                file.seek(SeekFrom::Start(index as u64 * CHUNK_SIZE as u64))?;
+                file.write_all(&data)?;
+                self.received[index as usize] = true;
+            }
+            BlobResponse::Manifest(_) => return Err(BlobError::UnexpectedResponse),
+        }
+        Ok(self.progress())
+    }
+
+    pub fn progress(&self) -> BlobProgress {
+        let manifest = self.manifest.as_ref();
+        let received = self.received.iter().enumerate().filter(|(_, received)| **received).map(|(index, _)| index);
+        BlobProgress {
+            received_bytes: manifest.map_or(0, |m| received.clone().map(|index| m.chunk_len(index)).sum()),
+            total_bytes: manifest.map_or(0, |m| m.size),
+            chunks_received: received.count() as u32,
+            chunks_total: self.received.len() as u32,
+        }
+    }
+
+    pub fn is_complete(&self) -> bool {
+        self.manifest.is_some() && self.received.iter().all(|received| *received)
+    }
+
+    /// Read the assembled blob and check it against the hash we asked for.
+    /// A mismatch means the manifest itself was forged, so the download is
+    /// reset.
+    pub fn finish(&mut self) -> Result<Vec<u8>, BlobError> {
+        if !self.is_complete() {
+            return Err(BlobError::UnexpectedResponse);
+        }
+        let data = fs::read(self.data_path())?;
+        if blob_hash(&data) != self.hash {
+            self.reset()?;
+            return Err(BlobError::HashMismatch);
+        }
+        Ok(data)
+    }
+
+    /// Delete what was downloaded, once the blob is safely in the store
+    pub fn discard(mut self) -> Result<(), BlobError> {
+        self.reset()
+    }
+
+    fn reset(&mut self) -> Result<(), BlobError> {
+        for path in [self.data_path(), self.manifest_path()] {
+            match fs::remove_file(path) {
+                Ok(()) => {}
+                Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+                Err(e) => return Err(e.into()),
+            }
+        }
+        self.manifest = None;
+        self.received.clear();
+        Ok(())
+    }
+}
@@ -0,0 +1,24 @@
+// CBOR helpers shared by everything we put on the wire or on disk
+use serde::{de::DeserializeOwned, Serialize};
+use std::{error::Error, fmt};
+
+#[derive(Debug)]
+pub struct CodecError(String);
+
+impl fmt::Display for CodecError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "cbor: {}", self.0)
+    }
+}
+
+impl Error for CodecError {}
+
+pub fn to_cbor<T: Serialize>(value: &T) -> Result<Vec<u8>, CodecError> {
+    let mut bytes = Vec::new();
+    ciborium::into_writer(value, &mut bytes).map_err(|e| CodecError(e.to_string()))?;
+    Ok(bytes)
+}
+
+pub fn from_cbor<T: DeserializeOwned>(bytes: &[u8]) -> Result<T, CodecError> {
+    ciborium::from_reader(bytes).map_err(|e| CodecError(e.to_string()))
+}
@@ -0,0 +1,851 @@
+// Replicated multi-writer key/value documents
+use crate::codec::{from_cbor, to_cbor};
+use crate::message::now_millis;
+use crate::ticket::{Capability, DocTicket, ShareMode, TicketPeer};
+use data_encoding::BASE32_NOPAD;
+use libp2p::identity::{self, ed25519};
+use libp2p::PeerId;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{hash_map, BTreeMap, HashMap},
+    error::Error,
+    fmt, fs, io,
+    ops::Bound,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+const ENTRY_DOMAIN: &[u8] = b"raggy/doc-entry/v1";
+const OWNED_DOC_DOMAIN: &[u8] = b"raggy/owned-doc/v1";
+const RELATIONSHIP_DOC_DOMAIN: &[u8] = b"raggy/relationship-doc/v1";
+const RAIL_DOC_DOMAIN: &[u8] = b"raggy/rail-doc/v1";
+/// How far ahead of our clock, in milliseconds, an entry may be timestamped.
+/// Anything later would win every write for too long.
+const MAX_CLOCK_SKEW: u64 = 10 * 60 * 1000;
+
+/// Label of the owner-only document holding a node's own state
+pub const MAIN_DOC: &str = "main";
+/// Label of the owner-only document holding read tickets for everything in MainDoc
+pub const TICKET_READ_DOC: &str = "ticket-read";
+/// Label of the owner-only document holding write tickets. It is private to
+/// the node and never served to peers.
+pub const TICKET_WRITE_DOC: &str = "ticket-write";
+/// Label of the owner-only document holding rail invitations we received.
+/// Who invites us is our own business, so it is private too.
+pub const RAIL_INVITES_DOC: &str = "rail-invites";
+/// Label of the owner-only document holding the node's allow and deny lists.
+/// Who a node blocks is its own business, so it is private as well.
+pub const ACCESS_LISTS_DOC: &str = "access-lists";
+
+/// Key naming what sort of document this is, which selects its `Validator`
+pub const KIND_KEY: &str = "kind";
+
+/// Key naming who created a document, for kinds whose rules depend on it.
+/// It is applied before anything else, the kind included.
+pub const CREATOR_KEY: &str = "creator";
+
+/// Value of the `kind` entry that marks a document as a VeracityRailDoc.
+/// Documents in a rail namespace are judged as one from their first entry.
+pub const RAIL_DOC_KIND: &str = "veracity-rail";
+
+/// Extra rules for documents of one kind, checked on top of signatures for
+/// local writes and for entries from peers. Gets the document as it stands,
+/// and the key, value (`None` for a deletion) and author of the write.
+pub type Validator = fn(&Document, &str, Option<&[u8]>, &AuthorId) -> Result<(), String>;
+
+/// Public key of the namespace keypair a document was created with, or a
+/// hash of the owner and label for owner-only documents
+#[derive(Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct DocId([u8; 32]);
+
+/// Public key of the node that wrote an entry
+#[derive(Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct AuthorId([u8; 32]);
+
+macro_rules! key_id {
+    ($name:ident) => {
+        impl $name {
+            pub fn from_bytes(bytes: [u8; 32]) -> Self {
+                $name(bytes)
+            }
+
+            pub fn as_bytes(&self) -> &[u8; 32] {
+                &self.0
+            }
+        }
+
+        impl fmt::Display for $name {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                write!(f, "{}", BASE32_NOPAD.encode(&self.0).to_ascii_lowercase())
+            }
+        }
+
+        impl fmt::Debug for $name {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                write!(f, "{}({self})", stringify!($name))
+            }
+        }
+
+        impl FromStr for $name {
+            type Err = DocError;
+
+            fn from_str(s: &str) -> Result<Self, Self::Err> {
+                let bytes = BASE32_NOPAD
+                    .decode(s.to_ascii_uppercase().as_bytes())
+                    .map_err(|e| DocError::InvalidId(e.to_string()))?;
+                let bytes = bytes
+                    .try_into()
+                    .map_err(|_| DocError::InvalidId(format!("'{s}' is not 32 bytes")))?;
+                Ok($name(bytes))
+            }
+        }
+    };
+}
+
+key_id!(DocId);
+key_id!(AuthorId);
+
+impl AuthorId {
+    pub fn of(keypair: &ed25519::Keypair) -> Self {
+        AuthorId(keypair.public().to_bytes())
+    }
+
+    /// Author id of the node whose identity is `peer`. Nodes sign entries
+    /// with their identity key, so this is who they write as.
+    pub fn from_peer(peer: &PeerId) -> Option<Self> {
+        let key = identity::PublicKey::try_decode_protobuf(peer.as_ref().digest()).ok()?;
+        Some(AuthorId(key.try_into_ed25519().ok()?.to_bytes()))
+    }
+
+    /// Peer id of the node whose identity key this is
+    pub fn peer_id(&self) -> Option<PeerId> {
+        let key = ed25519::PublicKey::try_from_bytes(&self.0).ok()?;
+        Some(PeerId::from_public_key(&identity::PublicKey::from(key)))
+    }
+}
+
+impl DocId {
+    pub fn of(namespace: &ed25519::Keypair) -> Self {
+        DocId(namespace.public().to_bytes())
+    }
+
+    pub fn owned(owner: &AuthorId, label: &str) -> Self {
+        let mut hasher = blake3::Hasher::new();
+        hasher.update(OWNED_DOC_DOMAIN);
+        hasher.update(&owner.0);
+        hasher.update(label.as_bytes());
+        DocId(*hasher.finalize().as_bytes())
+    }
+
+    /// Id of the RelationshipDoc of `entity`, which anyone can work out
+    pub fn relationships(entity: &DocId) -> Self {
+        let mut hasher = blake3::Hasher::new();
+        hasher.update(RELATIONSHIP_DOC_DOMAIN);
+        hasher.update(&entity.0);
+        DocId(*hasher.finalize().as_bytes())
+    }
+
+    /// Id of the rail `creator` made from `seed`, so nobody else can claim it
+    pub fn rail(creator: &AuthorId, seed: &[u8; 32]) -> Self {
+        let mut hasher = blake3::Hasher::new();
+        hasher.update(RAIL_DOC_DOMAIN);
+        hasher.update(&creator.0);
+        hasher.update(seed);
+        DocId(*hasher.finalize().as_bytes())
+    }
+}
+
+/// Who may write to a document
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Namespace {
+    /// Anyone holding the namespace secret
+    #[default]
+    Keyed,
+    /// Only `owner`. The document id is derived from the owner and `label`.
+    Owned { owner: AuthorId, label: String },
+    /// Anyone, but only under keys starting with their own author id and a
+    /// `/`, so no write ever replaces another author's. The document id is
+    /// derived from `entity`.
+    Relationships { entity: DocId },
+    /// Anyone, signing as themselves, with the rail rules deciding who may
+    /// write which key. The document id is derived from the creator and a
+    /// seed that its `creator` entry reveals.
+    Rail,
+}
+
+#[derive(Debug)]
+pub enum DocError {
+    Io(io::Error),
+    Codec(String),
+    InvalidId(String),
+    UnknownDoc(DocId),
+    /// An entry's signatures don't match its author and namespace
+    InvalidSignature,
+    /// An entry in an owner-only document written by someone else, or under
+    /// someone else's keys in a RelationshipDoc
+    NotOwner(AuthorId),
+    /// We only hold a read capability for the document
+    ReadOnly(DocId),
+    InvalidTicket(String),
+    /// An entry broke the rules of the document's kind
+    Rejected(String),
+    /// An entry is timestamped too far ahead of our clock, or the one we hold
+    /// leaves no later timestamp to write over it with
+    InvalidTimestamp(u64),
+}
+
+impl fmt::Display for DocError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DocError::Io(e) => write!(f, "docs i/o error: {e}"),
+            DocError::Codec(e) => write!(f, "{e}"),
+            DocError::InvalidId(e) => write!(f, "invalid id: {e}"),
+            DocError::UnknownDoc(id) => write!(f, "unknown document {id}"),
+            DocError::InvalidSignature => write!(f, "entry signature is invalid"),
+            DocError::NotOwner(author) => write!(f, "{author} doesn't own the document"),
+            DocError::ReadOnly(id) => write!(f, "document {id} is read-only here"),
+            DocError::InvalidTicket(e) => write!(f, "invalid ticket: {e}"),
+            DocError::Rejected(e) => write!(f, "entry rejected: {e}"),
+            DocError::InvalidTimestamp(timestamp) => write!(f, "timestamp {timestamp} is out of range"),
+        }
+    }
+}
+
+impl Error for DocError {}
+
+impl From<io::Error> for DocError {
+    fn from(e: io::Error) -> Self {
+        DocError::Io(e)
+    }
+}
+
+/// One signed write to a key. A `None` value is a deletion, kept as a
+/// tombstone so that it replicates like any other write. Entries are signed
+/// by their author and, in keyed namespaces, by the namespace key too, so
+/// only holders of a write capability produce entries that replicas accept.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Entry {
+    pub key: String,
+    #[serde(with = "serde_bytes")]
+    pub value: Option<Vec<u8>>,
+    pub author: AuthorId,
+    /// Milliseconds since the Unix epoch
+    pub timestamp: u64,
+    #[serde(with = "serde_bytes")]
+    pub signature: Vec<u8>,
+    #[serde(with = "serde_bytes")]
+    pub namespace_signature: Vec<u8>,
+}
+
+impl Entry {
+    pub fn new(namespace: &ed25519::Keypair, key: &str, value: Option<Vec<u8>>, author: &ed25519::Keypair, timestamp: u64) -> Self {
+        let mut entry = Entry {
+            key: key.to_string(),
+            value,
+            author: AuthorId::of(author),
+            timestamp,
+            signature: Vec::new(),
+            namespace_signature: Vec::new(),
+        };
+        let signed = entry.signed_bytes(&DocId::of(namespace));
+        entry.signature = author.sign(&signed);
+        entry.namespace_signature = namespace.sign(&signed);
+        entry
+    }
+
+    /// Entry for an owner-only document, signed by the owner alone
+    pub fn owned(doc: &DocId, key: &str, value: Option<Vec<u8>>, owner: &ed25519::Keypair, timestamp: u64) -> Self {
+        let mut entry = Entry {
+            key: key.to_string(),
+            value,
+            author: AuthorId::of(owner),
+            timestamp,
+            signature: Vec::new(),
+            namespace_signature: Vec::new(),
+        };
+        entry.signature = owner.sign(&entry.signed_bytes(doc));
+        entry
+    }
+
+    fn signed_bytes(&self, doc: &DocId) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(128);
+        bytes.extend_from_slice(ENTRY_DOMAIN);
+        bytes.extend_from_slice(&doc.0);
+        bytes.extend_from_slice(&(self.key.len() as u64).to_be_bytes());
+        bytes.extend_from_slice(self.key.as_bytes());
+        match &self.value {
+            Some(value) => {
+                bytes.push(1);
+                bytes.extend_from_slice(&(value.len() as u64).to_be_bytes());
+                bytes.extend_from_slice(value);
+            }
+            None => bytes.push(0),
+        }
+        bytes.extend_from_slice(&self.author.0);
+        bytes.extend_from_slice(&self.timestamp.to_be_bytes());
+        bytes
+    }
+
+    fn signed_by(&self, doc: &DocId, key: &[u8; 32], signature: &[u8]) -> bool {
+        ed25519::PublicKey::try_from_bytes(key)
+            .map(|key| key.verify(&self.signed_bytes(doc), signature))
+            .unwrap_or(false)
+    }
+
+    pub fn verify_author(&self, doc: &DocId) -> bool {
+        self.signed_by(doc, &self.author.0, &self.signature)
+    }
+
+    /// Whether the namespace key of a keyed document signed this entry
+    pub fn verify_namespace(&self, doc: &DocId) -> bool {
+        self.signed_by(doc, &doc.0, &self.namespace_signature)
+    }
+
+    /// Hash identifying this exact write, signature included
+    pub fn hash(&self, doc: &DocId) -> [u8; 32] {
+        let mut hasher = blake3::Hasher::new();
+        hasher.update(&self.signed_bytes(doc));
+        hasher.update(&self.signature);
+        hasher.update(&self.namespace_signature);
+        *hasher.finalize().as_bytes()
+    }
+
+    /// Last writer wins: the later timestamp, with the author breaking ties
+    pub fn supersedes(&self, other: &Entry) -> bool {
+        (self.timestamp, self.author) > (other.timestamp, other.author)
+    }
+}
+
+/// A document replica: the latest entry for every key.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Document {
+    id: DocId,
+    #[serde(default)]
+    namespace: Namespace,
+    /// Secret half of the namespace keypair, held only with write capability
+    #[serde(with = "serde_bytes")]
+    namespace_secret: Option<Vec<u8>>,
+    entries: BTreeMap<String, Entry>,
+    /// Local read statistics, never replicated
+    #[serde(default)]
+    access: BTreeMap<String, AccessStats>,
+}
+
+/// How often and how recently a key was read on this node
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct AccessStats {
+    pub count: u64,
+    /// Milliseconds since the Unix epoch
+    pub last_access: u64,
+}
+
+impl AccessStats {
+    /// Read count, discounted by the hours since the last read, so keys that
+    /// are read often and recently rank highest
+    pub fn score(&self, now: u64) -> f64 {
+        let hours = now.saturating_sub(self.last_access) as f64 / 3_600_000.0;
+        self.count as f64 / (1.0 + hours)
+    }
+}
+
+impl Document {
+    pub fn new() -> Self {
+        let namespace = ed25519::Keypair::generate();
+        Document {
+            id: DocId(namespace.public().to_bytes()),
+            namespace: Namespace::Keyed,
+            namespace_secret: Some(namespace.secret().as_ref().to_vec()),
+            entries: BTreeMap::new(),
+            access: BTreeMap::new(),
+        }
+    }
+
+    /// An empty replica of a keyed document created elsewhere
+    pub fn replica(id: DocId) -> Self {
+        Document {
+            id,
+            namespace: Namespace::Keyed,
+            namespace_secret: None,
+            entries: BTreeMap::new(),
+            access: BTreeMap::new(),
+        }
+    }
+
+    /// The owner-only document `owner` keeps under `label`. Only the owner can
+    /// write to it; anyone else holding it is a read replica.
+    pub fn owned(owner: AuthorId, label: &str) -> Self {
+        Document {
+            id: DocId::owned(&owner, label),
+            namespace: Namespace::Owned { owner, label: label.to_string() },
+            namespace_secret: None,
+            entries: BTreeMap::new(),
+            access: BTreeMap::new(),
+        }
+    }
+
+    /// The RelationshipDoc of `entity`, which everyone can write to
+    pub fn relationships(entity: DocId) -> Self {
+        Document {
+            id: DocId::relationships(&entity),
+            namespace: Namespace::Relationships { entity },
+            namespace_secret: None,
+            entries: BTreeMap::new(),
+            access: BTreeMap::new(),
+        }
+    }
+
+    /// The VeracityRailDoc `id`, whose creator is known once its `creator`
+    /// entry arrives
+    pub fn rail(id: DocId) -> Self {
+        Document {
+            id,
+            namespace: Namespace::Rail,
+            namespace_secret: None,
+            entries: BTreeMap::new(),
+            access: BTreeMap::new(),
+        }
+    }
+
+    /// Empty replica of the document a ticket points at, with the capability it grants
+    pub fn from_ticket(ticket: &DocTicket) -> Result<Self, DocError> {
+        let doc = match (&ticket.namespace, &ticket.capability) {
+            (Namespace::Keyed, Capability::Read) => Document::replica(ticket.doc),
+            (Namespace::Keyed, Capability::Write(secret)) => Document::with_namespace(secret)?,
+            (Namespace::Owned { owner, label }, Capability::Read) => Document::owned(*owner, label),
+            (Namespace::Owned { .. }, Capability::Write(_)) => {
+                return Err(DocError::InvalidTicket("owner-only documents can't be shared for writing".to_string()));
+            }
+            (Namespace::Relationships { entity }, Capability::Read) => Document::relationships(*entity),
+            (Namespace::Relationships { .. }, Capability::Write(_)) => {
+                return Err(DocError::InvalidTicket("RelationshipDocs need no write capability".to_string()));
+            }
+            (Namespace::Rail, Capability::Read) => Document::rail(ticket.doc),
+            (Namespace::Rail, Capability::Write(_)) => {
+                return Err(DocError::InvalidTicket("rails need no write capability".to_string()));
+            }
+        };
+        if doc.id() != ticket.doc {
+            return Err(DocError::InvalidTicket("namespace doesn't match the document".to_string()));
+        }
+        Ok(doc)
+    }
+
+    /// Replica holding the write capability for the namespace `secret`
+    pub fn with_namespace(secret: &[u8]) -> Result<Self, DocError> {
+        let namespace = namespace_keypair(secret)?;
+        Ok(Document {
+            id: DocId::of(&namespace),
+            namespace: Namespace::Keyed,
+            namespace_secret: Some(secret.to_vec()),
+            entries: BTreeMap::new(),
+            access: BTreeMap::new(),
+        })
+    }
+
+    pub fn id(&self) -> DocId {
+        self.id
+    }
+
+    pub fn namespace(&self) -> &Namespace {
+        &self.namespace
+    }
+
+    pub fn is_writable_by(&self, author: &AuthorId) -> bool {
+        match &self.namespace {
+            Namespace::Keyed => self.namespace_secret.is_some(),
+            Namespace::Owned { owner, .. } => owner == author,
+            Namespace::Relationships { .. } | Namespace::Rail => true,
+        }
+    }
+
+    pub fn namespace_secret(&self) -> Option<&[u8]> {
+        self.namespace_secret.as_deref()
+    }
+
+    /// Take the write capability for this document from a ticket, if we lacked it
+    pub fn grant_write(&mut self, secret: &[u8]) -> Result<(), DocError> {
+        if DocId::of(&namespace_keypair(secret)?) != self.id {
+            return Err(DocError::InvalidTicket("namespace secret doesn't match the document".to_string()));
+        }
+        self.namespace_secret = Some(secret.to_vec());
+        Ok(())
+    }
+
+    /// Check an entry's signatures against this document's namespace, and
+    /// that it isn't timestamped too far in the future
+    pub fn validate(&self, entry: &Entry) -> Result<(), DocError> {
+        if !entry.verify_author(&self.id) {
+            return Err(DocError::InvalidSignature);
+        }
+        if entry.timestamp > now_millis().saturating_add(MAX_CLOCK_SKEW) {
+            return Err(DocError::InvalidTimestamp(entry.timestamp));
+        }
+        match &self.namespace {
+            Namespace::Keyed if !entry.verify_namespace(&self.id) => Err(DocError::InvalidSignature),
+            Namespace::Owned { owner, .. } if entry.author != *owner => Err(DocError::NotOwner(entry.author)),
+            Namespace::Relationships { .. } if !entry.key.starts_with(&format!("{}/", entry.author)) => {
+                Err(DocError::NotOwner(entry.author))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Apply an entry if it is valid and newer than what we hold for its key.
+    /// Returns whether it was applied.
+    pub fn insert(&mut self, entry: Entry) -> Result<bool, DocError> {
+        self.validate(&entry)?;
+        if let Some(current) = self.entries.get(&entry.key) {
+            if !entry.supersedes(current) {
+                return Ok(false);
+            }
+        }
+        self.entries.insert(entry.key.clone(), entry);
+        Ok(true)
+    }
+
+    /// Write `value` (or a tombstone) as `author`. The timestamp is bumped past
+    /// the current entry so a local write always wins over what we've seen.
+    pub fn write(&mut self, author: &ed25519::Keypair, key: &str, value: Option<Vec<u8>>) -> Result<Entry, DocError> {
+        let floor = match self.entries.get(key) {
+            Some(e) if e.timestamp == u64::MAX => return Err(DocError::InvalidTimestamp(e.timestamp)),
+            Some(e) => e.timestamp.saturating_add(1),
+            None => 0,
+        };
+        let timestamp = now_millis().max(floor);
+        let entry = match &self.namespace {
+            Namespace::Keyed => {
+                let secret = self.namespace_secret.as_deref().ok_or(DocError::ReadOnly(self.id))?;
+                Entry::new(&namespace_keypair(secret)?, key, value, author, timestamp)
+            }
+            Namespace::Owned { owner, .. } if *owner == AuthorId::of(author) => {
+                Entry::owned(&self.id, key, value, author, timestamp)
+            }
+            Namespace::Owned { .. } => return Err(DocError::NotOwner(AuthorId::of(author))),
+            Namespace::Relationships { .. } if key.starts_with(&format!("{}/", AuthorId::of(author))) => {
+                Entry::owned(&self.id, key, value, author, timestamp)
+            }
+            Namespace::Relationships { .. } => return Err(DocError::NotOwner(AuthorId::of(author))),
+            Namespace::Rail => Entry::owned(&self.id, key, value, author, timestamp),
+        };
+        self.entries.insert(key.to_string(), entry.clone());
+        Ok(entry)
+    }
+
+    pub fn get(&self, key: &str) -> Option<&[u8]> {
+        self.entries.get(key).and_then(|e| e.value.as_deref())
+    }
+
+    /// Live keys starting with `prefix`, in key order
+    pub fn list(&self, prefix: &str) -> Vec<(String, Vec<u8>)> {
+        self.entries
+            .range(prefix.to_string()..)
+            .take_while(|(key, _)| key.starts_with(prefix))
+            .filter_map(|(key, e)| e.value.clone().map(|value| (key.clone(), value)))
+            .collect()
+    }
+
+    /// Every entry, tombstones included
+    pub fn entries(&self) -> impl Iterator<Item = &Entry> {
+        self.entries.values()
+    }
+
+    pub fn entry(&self, key: &str) -> Option<&Entry> {
+        self.entries.get(key)
+    }
+
+    /// Count a read of `key` at `now`, whether or not we hold it yet
+    pub fn record_access(&mut self, key: &str, now: u64) {
+        let stats = self.access.entry(key.to_string()).or_default();
+        stats.count += 1;
+        stats.last_access = stats.last_access.max(now);
+    }
+
+    pub fn access(&self, key: &str) -> Option<AccessStats> {
+        self.access.get(key).copied()
+    }
+
+    /// Up to `limit` entries we hold, hottest first by `AccessStats::score`
+    pub fn hot_entries(&self, limit: usize, now: u64) -> Vec<Entry> {
+        let mut hot: Vec<(f64, &Entry)> = self
+            .access
+            .iter()
+            .filter_map(|(key, stats)| Some((stats.score(now), self.entries.get(key)?)))
+            .collect();
+        hot.sort_by(|(a, x), (b, y)| b.total_cmp(a).then_with(|| x.key.cmp(&y.key)));
+        hot.into_iter().take(limit).map(|(_, entry)| entry.clone()).collect()
+    }
+
+    /// Entries with keys in `[lower, upper)`, or from `lower` on if there's no upper bound
+    pub fn range<'a, 'b>(&'a self, lower: &'b str, upper: Option<&'b str>) -> impl Iterator<Item = &'a Entry> + 'b
+    where
+        'a: 'b,
+    {
+        self.entries
+            .range::<str, _>((Bound::Included(lower), Bound::Unbounded))
+            .map(|(_, e)| e)
+            .take_while(move |e| !matches!(upper, Some(upper) if e.key.as_str() >= upper))
+    }
+}
+
+fn namespace_keypair(secret: &[u8]) -> Result<ed25519::Keypair, DocError> {
+    let secret = ed25519::SecretKey::try_from_bytes(secret.to_vec())
+        .map_err(|e| DocError::InvalidTicket(e.to_string()))?;
+    Ok(ed25519::Keypair::from(secret))
+}
+
+/// Run the validator registered for `doc`'s kind, if it has one
+fn check(
+    validators: &HashMap<String, Validator>,
+    doc: &Document,
+    key: &str,
+    value: Option<&[u8]>,
+    author: &AuthorId,
+) -> Result<(), DocError> {
+    let kind = match doc.namespace() {
+        Namespace::Rail => Some(RAIL_DOC_KIND),
+        _ => doc.get(KIND_KEY).and_then(|kind| std::str::from_utf8(kind).ok()),
+    };
+    match kind.and_then(|kind| validators.get(kind)) {
+        Some(validator) => validator(doc, key, value, author).map_err(DocError::Rejected),
+        None => Ok(()),
+    }
+}
+
+/// Check every entry `doc` holds besides its kind again, in the order
+/// `Docs::apply` uses, dropping the ones its validator now refuses. Entries
+/// that arrived before the kind entry were only checked against signatures.
+fn recheck(validators: &HashMap<String, Validator>, doc: &mut Document) -> Vec<(Entry, DocError)> {
+    let kind = doc.entries.remove(KIND_KEY);
+    let held = std::mem::take(&mut doc.entries);
+    doc.entries.extend(kind.map(|kind| (KIND_KEY.to_string(), kind)));
+    let mut rejected = Vec::new();
+    for (key, entry) in held {
+        match check(validators, doc, &key, entry.value.as_deref(), &entry.author) {
+            Ok(()) => {
+                doc.entries.insert(key, entry);
+            }
+            Err(e) => rejected.push((entry, e)),
+        }
+    }
+    rejected
+}
+
+impl Default for Document {
+    fn default() -> Self {
+        Document::new()
+    }
+}
+
+/// The documents a node holds, persisted as `<data-dir>/docs/<id>.cbor`.
+/// Writes are authored with the node's identity key.
+pub struct Docs {
+    dir: PathBuf,
+    author: ed25519::Keypair,
+    docs: HashMap<DocId, Document>,
+    changes: Vec<(DocId, Entry)>,
+    validators: HashMap<String, Validator>,
+}
+
+impl Docs {
+    pub fn open(data_dir: &Path, author: ed25519::Keypair) -> Result<Self, DocError> {
+        let dir = data_dir.join("docs");
+        fs::create_dir_all(&dir)?;
+        let mut docs = HashMap::new();
+        for file in fs::read_dir(&dir)? {
+            let path = file?.path();
+            if path.extension().and_then(|ext| ext.to_str()) != Some("cbor") {
+                continue;
+            }
+            let doc: Document = from_cbor(&fs::read(&path)?).map_err(|e| DocError::Codec(e.to_string()))?;
+            docs.insert(doc.id, doc);
+        }
+        Ok(Docs { dir, author, docs, changes: Vec::new(), validators: HashMap::new() })
+    }
+
+    /// Check writes to documents whose `kind` entry is `kind` with `validator`
+    pub fn register_validator(&mut self, kind: &str, validator: Validator) {
+        self.validators.insert(kind.to_string(), validator);
+    }
+
+    pub fn author(&self) -> AuthorId {
+        AuthorId::of(&self.author)
+    }
+
+    fn save(&self, id: &DocId) -> Result<(), DocError> {
+        let doc = self.docs.get(id).ok_or(DocError::UnknownDoc(*id))?;
+        let bytes = to_cbor(doc).map_err(|e| DocError::Codec(e.to_string()))?;
+        // Write then rename so a crash never leaves a truncated document
+        let path = self.dir.join(format!("{id}.cbor"));
+        let partial = path.with_extension("partial");
+        fs::write(&partial, bytes)?;
+        fs::rename(&partial, &path)?;
+        Ok(())
+    }
+
+    pub fn create(&mut self) -> Result<DocId, DocError> {
+        self.add(Document::new())
+    }
+
+    /// Create our owner-only document under `label`, or return it if it exists
+    pub fn create_owned(&mut self, label: &str) -> Result<DocId, DocError> {
+        self.add(Document::owned(self.author(), label))
+    }
+
+    /// Id of our owner-only document under `label`
+    pub fn owned_id(&self, label: &str) -> DocId {
+        DocId::owned(&self.author(), label)
+    }
+
+    /// Whether `id` must never be synced with peers, as it holds secrets
+    pub fn is_private(&self, id: &DocId) -> bool {
+        [TICKET_WRITE_DOC, RAIL_INVITES_DOC, ACCESS_LISTS_DOC].iter().any(|label| *id == self.owned_id(label))
+    }
+
+    /// Track a document, keeping our replica if we already have one
+    pub fn add(&mut self, doc: Document) -> Result<DocId, DocError> {
+        let id = doc.id;
+        if let hash_map::Entry::Vacant(slot) = self.docs.entry(id) {
+            slot.insert(doc);
+            self.save(&id)?;
+        }
+        Ok(id)
+    }
+
+    pub fn doc(&self, id: &DocId) -> Option<&Document> {
+        self.docs.get(id)
+    }
+
+    /// Ticket for a document we hold. Sharing write access needs the namespace secret.
+    pub fn share(&self, id: &DocId, mode: ShareMode, peers: Vec<TicketPeer>) -> Result<DocTicket, DocError> {
+        let doc = self.docs.get(id).ok_or(DocError::UnknownDoc(*id))?;
+        let capability = match (mode, doc.namespace()) {
+            (ShareMode::Read, _) => Capability::Read,
+            (ShareMode::Write, Namespace::Keyed) => {
+                Capability::Write(doc.namespace_secret().ok_or(DocError::ReadOnly(*id))?.to_vec())
+            }
+            (ShareMode::Write, Namespace::Owned { .. } | Namespace::Relationships { .. } | Namespace::Rail) => {
+                return Err(DocError::ReadOnly(*id))
+            }
+        };
+        Ok(DocTicket { doc: *id, namespace: doc.namespace().clone(), capability, peers })
+    }
+
+    /// Start replicating the document a ticket points at, or upgrade our
+    /// replica to writable if the ticket grants write access
+    pub fn join(&mut self, ticket: &DocTicket) -> Result<DocId, DocError> {
+        let id = ticket.doc;
+        let doc = Document::from_ticket(ticket)?;
+        match (self.docs.get_mut(&id), &ticket.capability) {
+            (Some(existing), Capability::Write(secret)) if existing.namespace_secret().is_none() => {
+                existing.grant_write(secret)?;
+                self.save(&id)?;
+            }
+            (Some(_), _) => {}
+            (None, _) => {
+                self.add(doc)?;
+            }
+        }
+        Ok(id)
+    }
+
+    pub fn ids(&self) -> Vec<DocId> {
+        let mut ids: Vec<DocId> = self.docs.keys().copied().collect();
+        ids.sort();
+        ids
+    }
+
+    pub fn get(&self, id: &DocId, key: &str) -> Result<Option<Vec<u8>>, DocError> {
+        let doc = self.docs.get(id).ok_or(DocError::UnknownDoc(*id))?;
+        Ok(doc.get(key).map(<[u8]>::to_vec))
+    }
+
+    /// Count a read of `key`, so it is offered first when peers sync from us.
+    /// Kept in memory until the document is next saved.
+    pub fn record_access(&mut self, id: &DocId, key: &str) -> Result<(), DocError> {
+        let doc = self.docs.get_mut(id).ok_or(DocError::UnknownDoc(*id))?;
+        doc.record_access(key, now_millis());
+        Ok(())
+    }
+
+    pub fn list(&self, id: &DocId, prefix: &str) -> Result<Vec<(String, Vec<u8>)>, DocError> {
+        let doc = self.docs.get(id).ok_or(DocError::UnknownDoc(*id))?;
+        Ok(doc.list(prefix))
+    }
+
+    pub fn set(&mut self, id: &DocId, key: &str, value: Vec<u8>) -> Result<Entry, DocError> {
+        self.write(id, key, Some(value))
+    }
+
+    pub fn delete(&mut self, id: &DocId, key: &str) -> Result<Entry, DocError> {
+        self.write(id, key, None)
+    }
+
+    fn write(&mut self, id: &DocId, key: &str, value: Option<Vec<u8>>) -> Result<Entry, DocError> {
+        let doc = self.docs.get_mut(id).ok_or(DocError::UnknownDoc(*id))?;
+        check(&self.validators, doc, key, value.as_deref(), &AuthorId::of(&self.author))?;
+        let entry = doc.write(&self.author, key, value)?;
+        self.save(id)?;
+        self.changes.push((*id, entry.clone()));
+        Ok(entry)
+    }
+
+    /// Apply an entry received from another replica
+    pub fn insert(&mut self, id: &DocId, entry: Entry) -> Result<bool, DocError> {
+        let doc = self.docs.get_mut(id).ok_or(DocError::UnknownDoc(*id))?;
+        if doc.entry(&entry.key).is_some_and(|current| !entry.supersedes(current)) {
+            return Ok(false);
+        }
+        let kindless = doc.get(KIND_KEY).is_none();
+        check(&self.validators, doc, &entry.key, entry.value.as_deref(), &entry.author)?;
+        if !doc.insert(entry.clone())? {
+            return Ok(false);
+        }
+        if kindless && entry.key == KIND_KEY {
+            recheck(&self.validators, doc);
+        }
+        self.save(id)?;
+        self.changes.push((*id, entry));
+        Ok(true)
+    }
+
+    /// Apply a batch of entries received from another replica, saving once.
+    /// Returns how many were applied and the ones rejected, with the reason.
+    pub fn apply(&mut self, id: &DocId, mut entries: Vec<Entry>) -> Result<(usize, Vec<(Entry, DocError)>), DocError> {
+        let doc = self.docs.get_mut(id).ok_or(DocError::UnknownDoc(*id))?;
+        let mut applied = 0;
+        let mut rejected = Vec::new();
+        // The creator and kind go first so a fresh replica validates everything
+        // after them, then key order, as rules may depend on earlier keys
+        let rank = |key: &str| [CREATOR_KEY, KIND_KEY].iter().position(|first| *first == key).unwrap_or(2);
+        entries.sort_by(|a, b| (rank(&a.key), &a.key).cmp(&(rank(&b.key), &b.key)));
+        for entry in entries {
+            // Entries we already hold, or older ones, were judged when they first arrived
+            if doc.entry(&entry.key).is_some_and(|current| !entry.supersedes(current)) {
+                continue;
+            }
+            let kindless = doc.get(KIND_KEY).is_none();
+            let checked = check(&self.validators, doc, &entry.key, entry.value.as_deref(), &entry.author);
+            match checked.and_then(|_| doc.insert(entry.clone())) {
+                Ok(true) => {
+                    applied += 1;
+                    // What arrived before the kind, such as hot entries, is judged now
+                    if kindless && entry.key == KIND_KEY {
+                        rejected.extend(recheck(&self.validators, doc));
+                    }
+                    self.changes.push((*id, entry));
+                }
+                Ok(false) => {}
+                Err(e) => rejected.push((entry, e)),
+            }
+        }
+        if applied > 0 {
+            self.save(id)?;
+        }
+        Ok((applied, rejected))
+    }
+
+    /// Entries written or accepted since the last call
+    pub fn take_changes(&mut self) -> Vec<(DocId, Entry)> {
+        std::mem::take(&mut self.changes)
+    }
+}
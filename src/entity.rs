@@ -0,0 +1,207 @@
+// Entities: owner-only documents with typed metadata, attributes and links
+use crate::codec::{from_cbor, to_cbor};
+use crate::docs::{AuthorId, DocError, DocId, Docs, Document, Namespace, TICKET_READ_DOC};
+use crate::message::now_millis;
+use crate::ticket::ShareMode;
+use data_encoding::BASE32_NOPAD;
+use libp2p::identity::ed25519;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+/// Label prefix of the owned documents that hold entities
+pub const ENTITY_LABEL_PREFIX: &str = "entity/";
+
+const META_KEY: &str = "meta";
+const ATTR_PREFIX: &str = "attr/";
+const LINK_PREFIX: &str = "link/";
+
+/// An entity is identified by the id of the document holding it
+pub type EntityId = DocId;
+
+/// Stored under `meta` in every EntityDoc
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct EntityMeta {
+    /// What sort of thing the entity is, such as `person` or `dataset`
+    pub kind: String,
+    pub name: String,
+    /// Milliseconds since the Unix epoch
+    pub created_at: u64,
+}
+
+/// Value of an attribute, stored under `attr/<name>`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum AttrValue {
+    Text(String),
+    Int(i64),
+    Float(f64),
+    Bool(bool),
+    Bytes(#[serde(with = "serde_bytes")] Vec<u8>),
+}
+
+/// The type of an `AttrValue`, for schemas
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum AttrType {
+    Text,
+    Int,
+    Float,
+    Bool,
+    Bytes,
+}
+
+impl AttrValue {
+    pub fn attr_type(&self) -> AttrType {
+        match self {
+            AttrValue::Text(_) => AttrType::Text,
+            AttrValue::Int(_) => AttrType::Int,
+            AttrValue::Float(_) => AttrType::Float,
+            AttrValue::Bool(_) => AttrType::Bool,
+            AttrValue::Bytes(_) => AttrType::Bytes,
+        }
+    }
+}
+
+/// An entity as read back from its document
+#[derive(Debug, Clone, PartialEq)]
+pub struct Entity {
+    pub id: EntityId,
+    pub owner: AuthorId,
+    pub meta: EntityMeta,
+    pub attrs: BTreeMap<String, AttrValue>,
+    /// Named links to other entities, stored under `link/<name>`
+    pub links: BTreeMap<String, EntityId>,
+}
+
+impl Entity {
+    /// Read an entity out of its document. `None` if the document isn't an
+    /// EntityDoc or its metadata hasn't synced yet.
+    pub fn from_document(doc: &Document) -> Option<Entity> {
+        let Namespace::Owned { owner, label } = doc.namespace() else { return None };
+        if !label.starts_with(ENTITY_LABEL_PREFIX) {
+            return None;
+        }
+        let meta = from_cbor(doc.get(META_KEY)?).ok()?;
+        Some(Entity {
+            id: doc.id(),
+            owner: *owner,
+            meta,
+            attrs: decode_prefixed(doc, ATTR_PREFIX),
+            links: decode_prefixed(doc, LINK_PREFIX),
+        })
+    }
+}
+
+/// Changes to apply to an entity. `None` values remove the attribute or link.
+#[derive(Debug, Clone, Default)]
+pub struct EntityUpdate {
+    pub name: Option<String>,
+    pub attrs: BTreeMap<String, Option<AttrValue>>,
+    pub links: BTreeMap<String, Option<EntityId>>,
+}
+
+impl EntityUpdate {
+    pub fn name(mut self, name: &str) -> Self {
+        self.name = Some(name.to_string());
+        self
+    }
+
+    pub fn set_attr(mut self, name: &str, value: AttrValue) -> Self {
+        self.attrs.insert(name.to_string(), Some(value));
+        self
+    }
+
+    pub fn remove_attr(mut self, name: &str) -> Self {
+        self.attrs.insert(name.to_string(), None);
+        self
+    }
+
+    pub fn link(mut self, name: &str, target: EntityId) -> Self {
+        self.links.insert(name.to_string(), Some(target));
+        self
+    }
+
+    pub fn unlink(mut self, name: &str) -> Self {
+        self.links.insert(name.to_string(), None);
+        self
+    }
+}
+
+/// Create an entity owned by this node. Its read ticket is listed in our
+/// TicketReadDoc, when we have one, so peers following our tickets replicate it.
+pub fn create(docs: &mut Docs, kind: &str, name: &str, attrs: BTreeMap<String, AttrValue>) -> Result<EntityId, DocError> {
+    // A throwaway public key is as good a random label as any
+    let nonce = ed25519::Keypair::generate().public().to_bytes();
+    let label = format!("{ENTITY_LABEL_PREFIX}{}", BASE32_NOPAD.encode(&nonce[..16]).to_ascii_lowercase());
+    let id = docs.create_owned(&label)?;
+    let meta = EntityMeta { kind: kind.to_string(), name: name.to_string(), created_at: now_millis() };
+    docs.set(&id, META_KEY, encode(&meta)?)?;
+    update(docs, &id, EntityUpdate { attrs: attrs.into_iter().map(|(k, v)| (k, Some(v))).collect(), ..Default::default() })?;
+
+    let ticket_read_doc = docs.owned_id(TICKET_READ_DOC);
+    if docs.doc(&ticket_read_doc).is_some() {
+        let ticket = docs.share(&id, ShareMode::Read, Vec::new())?;
+        docs.set(&ticket_read_doc, &format!("{ENTITY_LABEL_PREFIX}{id}"), ticket.to_string().into_bytes())?;
+    }
+    Ok(id)
+}
+
+/// Apply `update` to an entity we own
+pub fn update(docs: &mut Docs, id: &EntityId, update: EntityUpdate) -> Result<(), DocError> {
+    if let Some(name) = update.name {
+        let mut meta = get(docs, id)?.ok_or(DocError::UnknownDoc(*id))?.meta;
+        meta.name = name;
+        docs.set(id, META_KEY, encode(&meta)?)?;
+    }
+    for (name, value) in update.attrs {
+        let key = format!("{ATTR_PREFIX}{name}");
+        match value {
+            Some(value) => docs.set(id, &key, encode(&value)?)?,
+            None => docs.delete(id, &key)?,
+        };
+    }
+    for (name, target) in update.links {
+        let key = format!("{LINK_PREFIX}{name}");
+        match target {
+            Some(target) => docs.set(id, &key, encode(&target)?)?,
+            None => docs.delete(id, &key)?,
+        };
+    }
+    Ok(())
+}
+
+pub fn get(docs: &Docs, id: &EntityId) -> Result<Option<Entity>, DocError> {
+    let doc = docs.doc(id).ok_or(DocError::UnknownDoc(*id))?;
+    Ok(Entity::from_document(doc))
+}
+
+/// Every entity we hold, ours or replicated, whose attribute `name` equals `value`
+pub fn query(docs: &Docs, name: &str, value: &AttrValue) -> Vec<Entity> {
+    docs.ids()
+        .iter()
+        .filter_map(|id| Entity::from_document(docs.doc(id)?))
+        .filter(|entity| entity.attrs.get(name) == Some(value))
+        .collect()
+}
+
+/// Owner of every entity we hold, ours or replicated
+pub fn owners(docs: &Docs) -> BTreeMap<EntityId, AuthorId> {
+    docs.ids()
+        .iter()
+        .filter_map(|id| match docs.doc(id)?.namespace() {
+            Namespace::Owned { owner, label } if label.starts_with(ENTITY_LABEL_PREFIX) => Some((*id, *owner)),
+            _ => None,
+        })
+        .collect()
+}
+
+fn encode<T: Serialize>(value: &T) -> Result<Vec<u8>, DocError> {
+    to_cbor(value).map_err(|e| DocError::Codec(e.to_string()))
+}
+
+/// Values under `prefix`, keyed by the rest of their key. Ones that don't
+/// decode are skipped.
+fn decode_prefixed<T: DeserializeOwned>(doc: &Document, prefix: &str) -> BTreeMap<String, T> {
+    doc.list(prefix)
+        .into_iter()
+        .filter_map(|(key, value)| Some((key[prefix.len()..].to_string(), from_cbor(&value).ok()?)))
+        .collect()
+}
@@ -0,0 +1,241 @@
+// Directed graph of trust between entities, built from rail weights
+use crate::entity::EntityId;
+use crate::rail_index::RailIndex;
+use crate::rail_kind::{RailKind, RailKinds};
+use crate::rails::Rail;
+use std::cmp::Ordering;
+use std::collections::{btree_map::Entry, BTreeMap, BTreeSet, BinaryHeap};
+
+/// Edge weights are read as trust in `[0, 1]`. Larger weights count as 1,
+/// and edges weighted 0 or less are never followed.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TrustGraph {
+    edges: BTreeMap<EntityId, BTreeMap<EntityId, f64>>,
+}
+
+/// Weights each rail gives an edge, grouped by the rail's kind
+type KindWeights<'a> = BTreeMap<(EntityId, EntityId), BTreeMap<Option<&'a RailKind>, Vec<f64>>>;
+
+/// Settings for `personalised_trust`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Propagation {
+    /// Chance of following an edge rather than jumping back to the source
+    pub damping: f64,
+    pub max_iterations: usize,
+    /// Stop once scores move less than this in total between iterations
+    pub tolerance: f64,
+}
+
+impl Default for Propagation {
+    fn default() -> Self {
+        Propagation { damping: 0.85, max_iterations: 100, tolerance: 1e-10 }
+    }
+}
+
+/// A path through the graph and how much trust survives along it
+#[derive(Debug, Clone, PartialEq)]
+pub struct Path {
+    pub entities: Vec<EntityId>,
+    pub strength: f64,
+}
+
+impl TrustGraph {
+    /// One edge per weighted pair of entities in the index. Weights of rails
+    /// of the same kind are folded with that kind's combine function, and
+    /// the results for each kind averaged.
+    pub fn from_index(index: &RailIndex, kinds: &RailKinds) -> TrustGraph {
+        let mut rails: Vec<&Rail> = index.rails().collect();
+        rails.sort_by_key(|rail| rail.id);
+        let mut weights: KindWeights = BTreeMap::new();
+        for rail in rails {
+            for (edge, weight) in &rail.weights {
+                weights.entry(*edge).or_default().entry(rail.kind.as_ref()).or_default().push(*weight);
+            }
+        }
+
+        let mut graph = TrustGraph::default();
+        for ((from, to), by_kind) in weights {
+            let combined: Vec<f64> = by_kind
+                .into_iter()
+                .filter_map(|(kind, weights)| match kind {
+                    Some(kind) => kinds.combine(kind, &weights),
+                    None => Some(weights.iter().sum::<f64>() / weights.len() as f64),
+                })
+                .collect();
+            graph.add_edge(from, to, combined.iter().sum::<f64>() / combined.len() as f64);
+        }
+        graph
+    }
+
+    /// Add an edge, replacing any existing one between the same entities
+    pub fn add_edge(&mut self, from: EntityId, to: EntityId, weight: f64) {
+        self.edges.entry(from).or_default().insert(to, weight);
+    }
+
+    pub fn weight(&self, from: &EntityId, to: &EntityId) -> Option<f64> {
+        self.edges.get(from)?.get(to).copied()
+    }
+
+    /// Every entity with an edge from or to it, in order
+    pub fn entities(&self) -> BTreeSet<EntityId> {
+        self.edges.iter().flat_map(|(from, out)| std::iter::once(*from).chain(out.keys().copied())).collect()
+    }
+
+    /// Personalised PageRank from `source`, in the style of EigenTrust: a
+    /// walker follows edges in proportion to their weight, and jumps back to
+    /// `source` with chance `1 - damping` at each step, or whenever it
+    /// reaches an entity that trusts nobody. Scores sum to 1. Entities the
+    /// walker can't reach are left out.
+    pub fn personalised_trust(&self, source: &EntityId, params: &Propagation) -> BTreeMap<EntityId, f64> {
+        let mut rank: BTreeMap<EntityId, f64> = [(*source, 1.0)].into();
+        for _ in 0..params.max_iterations {
+            let mut next: BTreeMap<EntityId, f64> = BTreeMap::new();
+            let mut to_source = 1.0 - params.damping;
+            for (entity, score) in &rank {
+                let out: Vec<(EntityId, f64)> = self.trusted(entity).collect();
+                let total: f64 = out.iter().map(|(_, weight)| weight).sum();
+                if total <= 0.0 {
+                    to_source += params.damping * score;
+                    continue;
+                }
+                for (to, weight) in out {
+                    *next.entry(to).or_default() += params.damping * score * weight / total;
+                }
+            }
+            *next.entry(*source).or_default() += to_source;
+
+            let moved: f64 = next
+                .iter()
+                .map(|(entity, score)| (score - rank.get(entity).copied().unwrap_or_default()).abs())
+                .chain(rank.iter().filter(|(entity, _)| !next.contains_key(*entity)).map(|(_, score)| score.abs()))
+                .sum();
+            rank = next;
+            if moved < params.tolerance {
+                break;
+            }
+        }
+        rank
+    }
+
+    /// Entities within `k` hops of `source` along edges of any weight, with
+    /// how many hops away each is. `source` itself is 0 hops away.
+    pub fn neighbourhood(&self, source: &EntityId, k: usize) -> BTreeMap<EntityId, usize> {
+        let mut hops: BTreeMap<EntityId, usize> = [(*source, 0)].into();
+        let mut frontier = vec![*source];
+        for hop in 1..=k {
+            if frontier.is_empty() {
+                break;
+            }
+            let mut next = Vec::new();
+            for entity in &frontier {
+                for to in self.edges.get(entity).into_iter().flat_map(|out| out.keys()) {
+                    if let Entry::Vacant(slot) = hops.entry(*to) {
+                        slot.insert(hop);
+                        next.push(*to);
+                    }
+                }
+            }
+            frontier = next;
+        }
+        hops
+    }
+
+    /// Strongest trust `source` places in each entity it reaches within
+    /// `max_hops`. A path's strength is the product of its weights, times
+    /// `decay` for every hop after the first.
+    pub fn decayed_trust(&self, source: &EntityId, max_hops: usize, decay: f64) -> BTreeMap<EntityId, f64> {
+        let decay = decay.clamp(0.0, 1.0);
+        let mut strength: BTreeMap<EntityId, f64> = BTreeMap::new();
+        let mut frontier: BTreeMap<EntityId, f64> = [(*source, 1.0)].into();
+        for hop in 0..max_hops {
+            let factor = if hop == 0 { 1.0 } else { decay };
+            let mut next: BTreeMap<EntityId, f64> = BTreeMap::new();
+            for (entity, reached) in &frontier {
+                for (to, weight) in self.trusted(entity).filter(|(to, _)| to != source) {
+                    let reached = reached * weight * factor;
+                    // Only entities reached more strongly than before can improve on what's beyond them
+                    if reached > strength.get(&to).copied().unwrap_or_default() {
+                        strength.insert(to, reached);
+                        next.insert(to, reached);
+                    }
+                }
+            }
+            frontier = next;
+        }
+        strength
+    }
+
+    /// The path from `from` to `to` that keeps the most trust, scored as in
+    /// `decayed_trust` but with no limit on its length. `None` if `to` can't
+    /// be reached.
+    pub fn strongest_path(&self, from: &EntityId, to: &EntityId, decay: f64) -> Option<Path> {
+        let decay = decay.clamp(0.0, 1.0);
+        // Strengths only shrink along a path, so the strongest candidate is final once popped
+        let mut best: BTreeMap<EntityId, (f64, Option<EntityId>)> = [(*from, (1.0, None))].into();
+        let mut done = BTreeSet::new();
+        let mut queue = BinaryHeap::from([Candidate { strength: 1.0, entity: *from }]);
+        while let Some(Candidate { strength, entity }) = queue.pop() {
+            if !done.insert(entity) {
+                continue;
+            }
+            if entity == *to {
+                break;
+            }
+            let factor = if entity == *from { 1.0 } else { decay };
+            for (next, weight) in self.trusted(&entity) {
+                let reached = strength * weight * factor;
+                let improves = !matches!(best.get(&next), Some((known, _)) if *known >= reached);
+                if reached > 0.0 && !done.contains(&next) && improves {
+                    best.insert(next, (reached, Some(entity)));
+                    queue.push(Candidate { strength: reached, entity: next });
+                }
+            }
+        }
+        if !done.contains(to) {
+            return None;
+        }
+
+        let mut entities = vec![*to];
+        while let Some((_, Some(previous))) = entities.last().and_then(|entity| best.get(entity)) {
+            entities.push(*previous);
+        }
+        entities.reverse();
+        Some(Path { entities, strength: best[to].0 })
+    }
+
+    /// Edges from `entity` worth following, with weights clamped to `[0, 1]`
+    fn trusted(&self, entity: &EntityId) -> impl Iterator<Item = (EntityId, f64)> + '_ {
+        self.edges
+            .get(entity)
+            .into_iter()
+            .flatten()
+            .filter(|(_, weight)| **weight > 0.0)
+            .map(|(to, weight)| (*to, weight.min(1.0)))
+    }
+}
+
+/// Entry in the strongest-path queue, strongest first, then lowest id
+struct Candidate {
+    strength: f64,
+    entity: EntityId,
+}
+
+impl Ord for Candidate {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.strength.total_cmp(&other.strength).then_with(|| other.entity.cmp(&self.entity))
+    }
+}
+
+impl PartialOrd for Candidate {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for Candidate {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Candidate {}
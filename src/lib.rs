@@ -1,28 +1,83 @@
 // Re-export the run_node function and necessary types
-pub use crate::node::run_node;
+pub use crate::node::{run_node, run_node_with_handle, NodeError, NodeEvent, NodeHandle};
+pub use crate::message::{MessageKind, RaggyMessage};
+
+pub mod access_list;
+pub mod admin;
+pub mod announce;
+pub mod blob;
+pub mod codec;
+pub mod docs;
+pub mod entity;
+pub mod graph;
+pub mod message;
+pub mod policy;
+pub mod presence;
+pub mod rail_index;
+pub mod rail_kind;
+pub mod rails;
+pub mod relationship;
+pub mod rpc;
+pub mod store;
+pub mod sync;
+pub mod ticket;
+pub mod trust;
 
 pub mod node {
+    use crate::access_list::{self, AccessLists, List, Scope, Subject};
+    use crate::announce::{InterestFilter, JoinAnnouncement, JOIN_TOPIC};
+    use crate::blob::{self, BlobError, BlobProgress, BlobRequest, BlobResponse, Download};
+    use crate::codec::{from_cbor, to_cbor};
+    use crate::docs::{AuthorId, DocError, DocId, Docs, Entry, Namespace, MAIN_DOC, TICKET_READ_DOC};
+    use crate::entity::{self, AttrValue, Entity, EntityId, EntityUpdate};
+    use crate::graph::TrustGraph;
+    use crate::trust::{TrustMeasure, TrustView};
+    use crate::message::{now_millis, MessageKind, RaggyMessage, CONTENT_TYPE_CBOR};
+    use crate::policy::{Action, DefederationNotice, PolicyConfig, PolicyEngine, Rejection, DEFEDERATION_TOPIC};
+    use crate::presence::{Presence, Roster, RosterEntry, MISSED_HEARTBEATS, PRESENCE_INTERVAL, PRESENCE_TOPIC};
+    use crate::rail_index::{RailFilter, RailIndex};
+    use crate::rail_kind::{KindSpec, RailKind, RailKinds};
+    use crate::rails::{self, BackRefStatus, HalfLink, InvitePolicy, MissingLink, Rail, RailId, RailInvite, RailMetadata};
+    use crate::relationship::{self, Relationship};
+    use crate::rpc::{self, HandlerRegistry, RpcHandler, RpcRequest, RpcResponse, RPC_TIMEOUT};
+    use crate::store::{BlobStore, Cid};
+    use crate::sync::{self, DocHeads, SyncRequest, SyncResponse, SyncWalk, WalkProgress, DOC_HEADS_TOPIC};
+    use crate::ticket::{DocTicket, ShareMode, TicketPeer};
     use clap::Parser;
     use futures::StreamExt;
     use libp2p::{
-        gossipsub::{self, IdentTopic, MessageAuthenticity},
+        allow_block_list::{self, BlockedPeers},
+        gossipsub::{self, IdentTopic, MessageAcceptance, MessageAuthenticity, PeerScoreParams, PeerScoreThresholds, TopicHash, TopicScoreParams},
         identify,
         identity,
-        kad::{store::MemoryStore, Behaviour as KademliaBehaviour, Config as KademliaConfig, Event as KademliaEvent, QueryResult, RecordKey},
+        kad::{store::MemoryStore, Behaviour as KademliaBehaviour, Config as KademliaConfig, Event as KademliaEvent, GetProvidersOk, QueryId, QueryResult, RecordKey},
         mdns,
         multiaddr::Protocol,
         PeerId,
         ping,
+        request_response::{self, OutboundFailure, OutboundRequestId},
         swarm::{SwarmEvent, NetworkBehaviour, Config},
         Multiaddr,
         Swarm,
         Transport,
     };
-    use std::{error::Error, time::Duration, collections::HashSet, sync::Arc};
-    use tokio::{time::interval, sync::Mutex};
+    use std::{error::Error, fmt, path::{Path, PathBuf}, time::{Duration, Instant}, collections::{hash_map, BTreeMap, HashMap, HashSet}, sync::Arc};
+    use tokio::{time::interval, sync::{broadcast, mpsc, oneshot, Mutex}};
 
     const GOSSIP_TOPIC: &str = "raggy-chat";
-    const GOSSIP_INTERVAL: u64 = 10; // seconds
+    const SCORE_CHECK_INTERVAL: u64 = 5; // seconds
+    const RAIL_RECONCILE_INTERVAL: Duration = Duration::from_secs(60);
+    /// How long `relationships` waits for a RelationshipDoc to sync before
+    /// answering from what we have
+    const RELATIONSHIP_RESOLVE_TIMEOUT: Duration = Duration::from_secs(10);
+    const EVENT_CHANNEL_CAPACITY: usize = 256;
+
+    /// Capabilities advertised in our presence announcements
+    const CAPABILITIES: &[&str] = &["chat", "presence", "rpc", "blob", "docs", "join"];
+
+    /// Topics whose messages are identified by content alone, so the same
+    /// payload published by different peers is only delivered once
+    const CONTENT_ADDRESSED_TOPICS: &[&str] = &[];
 
     #[derive(Parser)]
     #[command(author, version, about, long_about = None)]
@@ -34,6 +89,20 @@ pub mod node {
         /// Node name
         #[arg(short, long, default_value = "anonymous")]
         name: String,
+
+        /// Directory for persistent node data [default: a new one under the
+        /// temp dir, named after a freshly generated peer id]
+        #[arg(long)]
+        data_dir: Option<PathBuf>,
+
+        /// Keep node data in <temp dir>/raggy-<name>, so a node restarted
+        /// under the same name keeps its identity. Nodes sharing a name share it too.
+        #[arg(long, conflicts_with = "data_dir")]
+        data_dir_by_name: bool,
+
+        /// How many hops of ticket references to follow when joining a document
+        #[arg(long, default_value_t = 3)]
+        sync_depth: usize,
     }
 
     #[derive(NetworkBehaviour)]
@@ -44,6 +113,13 @@ pub mod node {
         kademlia: KademliaBehaviour<MemoryStore>,
         mdns: mdns::tokio::Behaviour,
         gossipsub: gossipsub::Behaviour,
+        rpc: rpc::Behaviour,
+        blob: blob::Behaviour,
+        doc_sync: sync::Behaviour,
+        /// Refuses connections to and from peers on the deny list
+        blocked: allow_block_list::Behaviour<BlockedPeers>,
+        /// Refuses connections to and from peers an exclusive allow list leaves out
+        allowed: access_list::Behaviour,
     }
 
     #[derive(Debug)]
@@ -53,6 +129,11 @@ pub mod node {
         Kademlia(KademliaEvent),
         Mdns(mdns::Event),
         Gossipsub(gossipsub::Event),
+        Rpc(request_response::Event<RpcRequest, RpcResponse>),
+        Blob(request_response::Event<BlobRequest, BlobResponse>),
+        DocSync(request_response::Event<SyncRequest, SyncResponse>),
+        /// The access list behaviours never emit events
+        Blocked(void::Void),
     }
 
     impl From<ping::Event> for MyBehaviourEvent {
@@ -85,18 +166,1144 @@ pub mod node {
         }
     }
 
+    impl From<request_response::Event<RpcRequest, RpcResponse>> for MyBehaviourEvent {
+        fn from(event: request_response::Event<RpcRequest, RpcResponse>) -> Self {
+            MyBehaviourEvent::Rpc(event)
+        }
+    }
+
+    impl From<request_response::Event<BlobRequest, BlobResponse>> for MyBehaviourEvent {
+        fn from(event: request_response::Event<BlobRequest, BlobResponse>) -> Self {
+            MyBehaviourEvent::Blob(event)
+        }
+    }
+
+    impl From<request_response::Event<SyncRequest, SyncResponse>> for MyBehaviourEvent {
+        fn from(event: request_response::Event<SyncRequest, SyncResponse>) -> Self {
+            MyBehaviourEvent::DocSync(event)
+        }
+    }
+
+    impl From<void::Void> for MyBehaviourEvent {
+        fn from(event: void::Void) -> Self {
+            MyBehaviourEvent::Blocked(event)
+        }
+    }
+
+    /// Events emitted by a running node to every `NodeHandle` subscriber.
+    #[derive(Debug, Clone)]
+    pub enum NodeEvent {
+        /// A peer was disconnected because its gossipsub score fell below the graylist threshold
+        PeerScoreDisconnect { peer: PeerId, score: f64 },
+        /// A gossip message was received and decoded. `source` is the peer
+        /// that signed it, `peer` the one that passed it on to us.
+        Message { peer: PeerId, source: Option<PeerId>, message: RaggyMessage },
+        /// A peer started announcing its presence
+        PeerJoined { peer: PeerId, name: String },
+        /// A peer missed too many presence heartbeats
+        PeerLeft { peer: PeerId, name: String },
+        /// A chunk of a blob we are fetching arrived and was verified
+        BlobProgress { cid: Cid, progress: BlobProgress },
+        /// An entry was written locally or accepted from a peer
+        DocChanged { doc: DocId, entry: Entry },
+        /// A reconciliation of `doc` with `peer` finished
+        DocSynced { peer: PeerId, doc: DocId },
+        /// A document reached while recursively syncing from a joined ticket
+        /// is in sync, or was given up on for the `error` given
+        DocSyncProgress { doc: DocId, progress: WalkProgress, error: Option<String> },
+        /// `peer` sent an entry that doesn't validate against the document's
+        /// namespace, such as a write to someone else's MainDoc. It was dropped.
+        ValidationFailed { peer: PeerId, doc: DocId, author: AuthorId, reason: String },
+        /// A peer announced its TicketReadDoc on the join topic. `joined` is
+        /// true if the interest filter picked it and the node started syncing it.
+        JoinAnnounced { peer: PeerId, name: String, ticket: DocTicket, joined: bool },
+        /// `peer` invited us to link one of our entities to its rail, and the
+        /// invitation policy left the back-reference at `status`
+        RailInvited { peer: PeerId, invite: RailInvite, status: BackRefStatus },
+        /// The owner of an entity on one of our rails answered our invitation
+        RailInviteAnswered { peer: PeerId, rail: RailId, entity: EntityId, status: BackRefStatus },
+        /// The policy refused `action` by `peer`, and what it was dropped
+        PolicyRejected { peer: PeerId, action: Action, reason: Rejection },
+        /// `peer`, and with it `cut_off`, is no longer synced with or relayed
+        /// for. `by` is the peer whose notice we followed, `None` if it was us.
+        Defederated { peer: PeerId, by: Option<PeerId>, cut_off: Vec<PeerId> },
+        /// A peer published a defederation notice. `followed` is true if the
+        /// policy trusts it enough to act on it.
+        DefederationAnnounced { notice: DefederationNotice, followed: bool },
+        /// The periodic check found rails and entities that only one side links up
+        HalfLinkedRails(Vec<HalfLink>),
+    }
+
+    /// Errors returned by `NodeHandle` calls.
+    #[derive(Debug, Clone)]
+    pub enum NodeError {
+        /// The node's event loop has exited
+        Stopped,
+        /// A request to a peer did not complete in time
+        Timeout,
+        /// A request to a peer failed, or the peer's handler returned an error
+        Rpc(String),
+        /// A blob transfer failed
+        Blob(BlobError),
+        /// The local blob store failed
+        Store(String),
+        /// A document operation failed
+        Doc(String),
+        /// A message couldn't be published, such as when no peer is on the topic
+        Gossip(String),
+    }
+
+    impl fmt::Display for NodeError {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            match self {
+                NodeError::Stopped => write!(f, "node has stopped"),
+                NodeError::Timeout => write!(f, "request timed out"),
+                NodeError::Rpc(e) => write!(f, "rpc failed: {e}"),
+                NodeError::Blob(e) => write!(f, "blob transfer failed: {e}"),
+                NodeError::Store(e) => write!(f, "{e}"),
+                NodeError::Doc(e) => write!(f, "{e}"),
+                NodeError::Gossip(e) => write!(f, "publish failed: {e}"),
+            }
+        }
+    }
+
+    impl Error for NodeError {}
+
+    enum Command {
+        PeerScores { reply: oneshot::Sender<HashMap<PeerId, f64>> },
+        Roster { reply: oneshot::Sender<Vec<RosterEntry>> },
+        PublishChat { text: String, reply: oneshot::Sender<Result<(), NodeError>> },
+        Request { peer: PeerId, request: RpcRequest, reply: oneshot::Sender<Result<Vec<u8>, NodeError>> },
+        RegisterHandler { method: String, handler: RpcHandler },
+        PutBlob { data: Vec<u8>, reply: oneshot::Sender<Result<Cid, NodeError>> },
+        FetchBlob { peer: PeerId, cid: Cid, reply: oneshot::Sender<Result<Vec<u8>, NodeError>> },
+        WithStore(Box<dyn FnOnce(&BlobStore) + Send>),
+        WithRailIndex(Box<dyn FnOnce(&RailIndex) + Send>),
+        WithDocs(Box<dyn FnOnce(&mut Docs) + Send>),
+        ShareDoc { doc: DocId, mode: ShareMode, reply: oneshot::Sender<Result<DocTicket, NodeError>> },
+        JoinDoc { ticket: DocTicket, reply: oneshot::Sender<Result<DocId, NodeError>> },
+        SetJoinInterest(Option<InterestFilter>),
+        DocGet { doc: DocId, key: String, reply: oneshot::Sender<Result<Option<Vec<u8>>, NodeError>> },
+        RegisterRailKind { kind: RailKind, spec: KindSpec },
+        RailKinds { reply: oneshot::Sender<RailKinds> },
+        CreateRail {
+            kind: RailKind,
+            entities: Vec<EntityId>,
+            metadata: RailMetadata,
+            reply: oneshot::Sender<Result<RailId, NodeError>>,
+        },
+        SetRailInvitePolicy(Option<InvitePolicy>),
+        TrustGraph { reply: oneshot::Sender<TrustGraph> },
+        TrustView { viewer: EntityId, measure: TrustMeasure, reply: oneshot::Sender<TrustView> },
+        AnswerRailInvite { rail: RailId, entity: EntityId, accept: bool, reply: oneshot::Sender<Result<(), NodeError>> },
+        /// Replicate and announce an entity's RelationshipDoc, syncing it
+        /// from its providers. `reply` is answered once a sync completes or
+        /// nobody is found to sync with.
+        ResolveRelationships { entity: EntityId, reply: Option<oneshot::Sender<()>> },
+        SetPolicy(PolicyConfig),
+        Policy { reply: oneshot::Sender<PolicyEngine> },
+        SetAccess { list: List, subject: Subject, listed: bool, reply: oneshot::Sender<Result<(), NodeError>> },
+        SetExclusive { scope: Scope, exclusive: bool, reply: oneshot::Sender<Result<(), NodeError>> },
+        Defederate { peer: PeerId, subtree: bool, reason: String, reply: oneshot::Sender<Vec<PeerId>> },
+        RestorePeer { peer: PeerId, reply: oneshot::Sender<Vec<PeerId>> },
+    }
+
+    /// Reads waiting on a targeted fetch of one key, and how many peers are
+    /// still to reply
+    type KeyWaiters = (usize, Vec<oneshot::Sender<Result<Option<Vec<u8>>, NodeError>>>);
+
+    /// Callers waiting on a blob fetch
+    type BlobWaiters = Vec<oneshot::Sender<Result<Vec<u8>, NodeError>>>;
+
+    /// Cloneable handle used by the application to talk to a running node.
+    #[derive(Clone)]
+    pub struct NodeHandle {
+        commands: mpsc::UnboundedSender<Command>,
+        events: broadcast::Sender<NodeEvent>,
+    }
+
+    /// The node's side of a `NodeHandle`, consumed by `run_node_with_handle`.
+    pub struct NodeInbox {
+        commands: mpsc::UnboundedReceiver<Command>,
+        events: broadcast::Sender<NodeEvent>,
+    }
+
+    impl NodeHandle {
+        pub fn new() -> (NodeHandle, NodeInbox) {
+            let (command_tx, command_rx) = mpsc::unbounded_channel();
+            let (events, _) = broadcast::channel(EVENT_CHANNEL_CAPACITY);
+            let handle = NodeHandle { commands: command_tx, events: events.clone() };
+            let inbox = NodeInbox { commands: command_rx, events };
+            (handle, inbox)
+        }
+
+        /// Subscribe to events emitted by the node.
+        pub fn subscribe(&self) -> broadcast::Receiver<NodeEvent> {
+            self.events.subscribe()
+        }
+
+        /// Current gossipsub score of every peer the node knows about.
+        pub async fn peer_scores(&self) -> Result<HashMap<PeerId, f64>, NodeError> {
+            let (reply, rx) = oneshot::channel();
+            self.send(Command::PeerScores { reply })?;
+            rx.await.map_err(|_| NodeError::Stopped)
+        }
+
+        /// Peers currently announcing their presence, ordered by name.
+        pub async fn roster(&self) -> Result<Vec<RosterEntry>, NodeError> {
+            let (reply, rx) = oneshot::channel();
+            self.send(Command::Roster { reply })?;
+            rx.await.map_err(|_| NodeError::Stopped)
+        }
+
+        /// Publish `text` as a chat message. Peers that receive it add it to
+        /// the message callback they were started with.
+        pub async fn publish_chat(&self, text: &str) -> Result<(), NodeError> {
+            let (reply, rx) = oneshot::channel();
+            self.send(Command::PublishChat { text: text.to_string(), reply })?;
+            rx.await.map_err(|_| NodeError::Stopped)?
+        }
+
+        /// Send `body` to the `method` handler on `peer` and wait for its reply.
+        pub async fn request(&self, peer: PeerId, method: &str, body: Vec<u8>) -> Result<Vec<u8>, NodeError> {
+            self.request_with_timeout(peer, method, body, RPC_TIMEOUT).await
+        }
+
+        /// Like `request`, but gives up after `timeout` instead of the protocol default.
+        pub async fn request_with_timeout(
+            &self,
+            peer: PeerId,
+            method: &str,
+            body: Vec<u8>,
+            timeout: Duration,
+        ) -> Result<Vec<u8>, NodeError> {
+            let (reply, rx) = oneshot::channel();
+            let request = RpcRequest { method: method.to_string(), body };
+            self.send(Command::Request { peer, request, reply })?;
+            match tokio::time::timeout(timeout, rx).await {
+                Ok(result) => result.map_err(|_| NodeError::Stopped)?,
+                Err(_) => Err(NodeError::Timeout),
+            }
+        }
+
+        /// Serve `method` for requests from other peers, replacing any existing handler.
+        pub fn register_handler<F>(&self, method: &str, handler: F) -> Result<(), NodeError>
+        where
+            F: Fn(PeerId, &[u8]) -> Result<Vec<u8>, String> + Send + Sync + 'static,
+        {
+            self.send(Command::RegisterHandler { method: method.to_string(), handler: Arc::new(handler) })
+        }
+
+        /// Store `data`, serve it over the blob protocol and announce it as a DHT provider record.
+        pub async fn put_blob(&self, data: Vec<u8>) -> Result<Cid, NodeError> {
+            let (reply, rx) = oneshot::channel();
+            self.send(Command::PutBlob { data, reply })?;
+            rx.await.map_err(|_| NodeError::Stopped)?
+        }
+
+        /// Fetch a blob from `peer` chunk by chunk into the local store. Progress
+        /// is reported as `NodeEvent::BlobProgress`, and a fetch that fails part
+        /// way resumes from the first missing chunk when called again, even
+        /// after a restart.
+        pub async fn fetch_blob(&self, peer: PeerId, cid: Cid) -> Result<Vec<u8>, NodeError> {
+            let (reply, rx) = oneshot::channel();
+            self.send(Command::FetchBlob { peer, cid, reply })?;
+            rx.await.map_err(|_| NodeError::Stopped)?
+        }
+
+        pub async fn get_blob(&self, cid: Cid) -> Result<Option<Vec<u8>>, NodeError> {
+            self.with_store(move |store| store.get(&cid)).await?.map_err(|e| NodeError::Store(e.to_string()))
+        }
+
+        pub async fn has_blob(&self, cid: Cid) -> Result<bool, NodeError> {
+            self.with_store(move |store| store.has(&cid)).await
+        }
+
+        pub async fn delete_blob(&self, cid: Cid) -> Result<bool, NodeError> {
+            self.with_store(move |store| store.delete(&cid)).await?.map_err(|e| NodeError::Store(e.to_string()))
+        }
+
+        /// Keep a blob through garbage collection.
+        pub async fn pin_blob(&self, cid: Cid) -> Result<(), NodeError> {
+            self.with_store(move |store| store.pin(&cid)).await?.map_err(|e| NodeError::Store(e.to_string()))
+        }
+
+        pub async fn unpin_blob(&self, cid: Cid) -> Result<bool, NodeError> {
+            self.with_store(move |store| store.unpin(&cid)).await?.map_err(|e| NodeError::Store(e.to_string()))
+        }
+
+        /// Delete every unpinned blob, returning the CIDs that were removed.
+        pub async fn gc_blobs(&self) -> Result<Vec<Cid>, NodeError> {
+            self.with_store(|store| store.gc()).await?.map_err(|e| NodeError::Store(e.to_string()))
+        }
+
+        /// Create an empty document that this node can write to.
+        pub async fn create_doc(&self) -> Result<DocId, NodeError> {
+            self.with_docs(|docs| docs.create()).await?.map_err(|e| NodeError::Doc(e.to_string()))
+        }
+
+        /// Read a key. Reads count towards the hot set offered first to peers
+        /// syncing from us, and a key missing from a document that hasn't
+        /// finished syncing yet is fetched from connected peers on its own.
+        pub async fn doc_get(&self, doc: DocId, key: &str) -> Result<Option<Vec<u8>>, NodeError> {
+            let (reply, rx) = oneshot::channel();
+            self.send(Command::DocGet { doc, key: key.to_string(), reply })?;
+            rx.await.map_err(|_| NodeError::Stopped)?
+        }
+
+        pub async fn doc_set(&self, doc: DocId, key: &str, value: Vec<u8>) -> Result<Entry, NodeError> {
+            let key = key.to_string();
+            self.with_docs(move |docs| docs.set(&doc, &key, value)).await?.map_err(|e| NodeError::Doc(e.to_string()))
+        }
+
+        /// Delete a key by writing a tombstone, so the deletion replicates.
+        pub async fn doc_delete(&self, doc: DocId, key: &str) -> Result<Entry, NodeError> {
+            let key = key.to_string();
+            self.with_docs(move |docs| docs.delete(&doc, &key)).await?.map_err(|e| NodeError::Doc(e.to_string()))
+        }
+
+        /// Live keys and values starting with `prefix`, in key order.
+        pub async fn doc_list(&self, doc: DocId, prefix: &str) -> Result<Vec<(String, Vec<u8>)>, NodeError> {
+            let prefix = prefix.to_string();
+            self.with_docs(move |docs| docs.list(&doc, &prefix)).await?.map_err(|e| NodeError::Doc(e.to_string()))
+        }
+
+        pub async fn doc_ids(&self) -> Result<Vec<DocId>, NodeError> {
+            self.with_docs(|docs| docs.ids()).await
+        }
+
+        /// This node's owner-only MainDoc, the source of truth for its state.
+        pub async fn main_doc(&self) -> Result<DocId, NodeError> {
+            self.with_docs(|docs| docs.owned_id(MAIN_DOC)).await
+        }
+
+        /// This node's owner-only TicketReadDoc, holding read tickets for everything in MainDoc.
+        pub async fn ticket_read_doc(&self) -> Result<DocId, NodeError> {
+            self.with_docs(|docs| docs.owned_id(TICKET_READ_DOC)).await
+        }
+
+        /// Ticket that lets another node join `doc` with read or write access,
+        /// syncing from this node.
+        pub async fn share_doc(&self, doc: DocId, mode: ShareMode) -> Result<DocTicket, NodeError> {
+            let (reply, rx) = oneshot::channel();
+            self.send(Command::ShareDoc { doc, mode, reply })?;
+            rx.await.map_err(|_| NodeError::Stopped)?
+        }
+
+        /// Start replicating the document behind `ticket` and sync it from the
+        /// peers it lists, then the documents its tickets reference, up to
+        /// `--sync-depth` hops away.
+        pub async fn join_doc(&self, ticket: DocTicket) -> Result<DocId, NodeError> {
+            let (reply, rx) = oneshot::channel();
+            self.send(Command::JoinDoc { ticket, reply })?;
+            rx.await.map_err(|_| NodeError::Stopped)?
+        }
+
+        /// Choose which tickets announced on the join topic the node joins by
+        /// itself, as if passed to `join_doc`. `None`, the default, joins none.
+        pub fn set_join_interest(&self, filter: Option<InterestFilter>) -> Result<(), NodeError> {
+            self.send(Command::SetJoinInterest(filter))
+        }
+
+        /// Create an entity owned by this node and list it in our TicketReadDoc.
+        pub async fn create_entity(
+            &self,
+            kind: &str,
+            name: &str,
+            attrs: BTreeMap<String, AttrValue>,
+        ) -> Result<EntityId, NodeError> {
+            let (kind, name) = (kind.to_string(), name.to_string());
+            self.with_docs(move |docs| entity::create(docs, &kind, &name, attrs))
+                .await?
+                .map_err(|e| NodeError::Doc(e.to_string()))
+        }
+
+        pub async fn update_entity(&self, id: EntityId, update: EntityUpdate) -> Result<(), NodeError> {
+            self.with_docs(move |docs| entity::update(docs, &id, update)).await?.map_err(|e| NodeError::Doc(e.to_string()))
+        }
+
+        pub async fn get_entity(&self, id: EntityId) -> Result<Option<Entity>, NodeError> {
+            self.with_docs(move |docs| entity::get(docs, &id)).await?.map_err(|e| NodeError::Doc(e.to_string()))
+        }
+
+        /// Entities we hold whose attribute `name` equals `value`.
+        pub async fn query_entities(&self, name: &str, value: AttrValue) -> Result<Vec<Entity>, NodeError> {
+            let name = name.to_string();
+            self.with_docs(move |docs| entity::query(docs, &name, &value)).await
+        }
+
+        /// Link `entities` with a new veracity rail of a registered `kind`,
+        /// following ABOUT.md: the rail's tickets go in our TicketWriteDoc and
+        /// TicketReadDoc, and every entity we own records the rail and `metadata`.
+        /// The owners of the other entities are invited to record it too.
+        pub async fn create_rail(
+            &self,
+            kind: RailKind,
+            entities: Vec<EntityId>,
+            metadata: RailMetadata,
+        ) -> Result<RailId, NodeError> {
+            let (reply, rx) = oneshot::channel();
+            self.send(Command::CreateRail { kind, entities, metadata, reply })?;
+            rx.await.map_err(|_| NodeError::Stopped)?
+        }
+
+        /// Let rails of `kind` be created here, checking their metadata against
+        /// `spec`'s schema. Replaces any earlier spec for the kind.
+        pub fn register_rail_kind(&self, kind: RailKind, spec: KindSpec) -> Result<(), NodeError> {
+            self.send(Command::RegisterRailKind { kind, spec })
+        }
+
+        pub async fn rail_kinds(&self) -> Result<RailKinds, NodeError> {
+            let (reply, rx) = oneshot::channel();
+            self.send(Command::RailKinds { reply })?;
+            rx.await.map_err(|_| NodeError::Stopped)
+        }
+
+        /// Every rail we hold, or only those of `kind`.
+        pub async fn find_rails(&self, kind: Option<RailKind>) -> Result<Vec<Rail>, NodeError> {
+            self.with_docs(move |docs| rails::find_rails(docs, kind.as_ref())).await
+        }
+
+        pub async fn get_rail(&self, id: RailId) -> Result<Option<Rail>, NodeError> {
+            self.with_docs(move |docs| rails::get_rail(docs, &id)).await?.map_err(|e| NodeError::Doc(e.to_string()))
+        }
+
+        /// Rails recorded in an entity, with the metadata stored alongside.
+        pub async fn rails_of(&self, entity: EntityId) -> Result<BTreeMap<RailId, RailMetadata>, NodeError> {
+            self.with_docs(move |docs| rails::rails_of(docs, &entity)).await?.map_err(|e| NodeError::Doc(e.to_string()))
+        }
+
+        /// Add an entity we hold to a rail we can write to.
+        pub async fn add_rail_participant(&self, rail: RailId, entity: EntityId) -> Result<(), NodeError> {
+            self.with_docs(move |docs| rails::add_participant(docs, &rail, &entity))
+                .await?
+                .map_err(|e| NodeError::Doc(e.to_string()))
+        }
+
+        pub async fn remove_rail_participant(&self, rail: RailId, entity: EntityId) -> Result<(), NodeError> {
+            self.with_docs(move |docs| rails::remove_participant(docs, &rail, &entity))
+                .await?
+                .map_err(|e| NodeError::Doc(e.to_string()))
+        }
+
+        /// Weight the edge from `from`, an entity we own, to `to`. `None` removes it.
+        pub async fn set_rail_weight(
+            &self,
+            rail: RailId,
+            from: EntityId,
+            to: EntityId,
+            weight: Option<f64>,
+        ) -> Result<(), NodeError> {
+            self.with_docs(move |docs| match weight {
+                Some(weight) => rails::set_weight(docs, &rail, &from, &to, weight),
+                None => rails::remove_weight(docs, &rail, &from, &to),
+            })
+            .await?
+            .map_err(|e| NodeError::Doc(e.to_string()))
+        }
+
+        /// Choose what happens to invitations to link our entities to rails.
+        /// `None`, the default, leaves them pending until `answer_rail_invite`.
+        pub fn set_rail_invite_policy(&self, policy: Option<InvitePolicy>) -> Result<(), NodeError> {
+            self.send(Command::SetRailInvitePolicy(policy))
+        }
+
+        /// Invitations to link our entities to rails, and where each stands.
+        pub async fn rail_invites(&self) -> Result<Vec<(RailInvite, BackRefStatus)>, NodeError> {
+            self.with_docs(|docs| rails::invites(docs)).await
+        }
+
+        /// Accept or decline an invitation we received. Accepting records the
+        /// back-reference and starts syncing the rail.
+        pub async fn answer_rail_invite(&self, rail: RailId, entity: EntityId, accept: bool) -> Result<(), NodeError> {
+            let (reply, rx) = oneshot::channel();
+            self.send(Command::AnswerRailInvite { rail, entity, accept, reply })?;
+            rx.await.map_err(|_| NodeError::Stopped)?
+        }
+
+        /// Rails `entity` is on, from the rail documents and entity documents
+        /// we hold, that pass `filter`.
+        pub async fn rails_for(&self, entity: EntityId, filter: RailFilter) -> Result<Vec<RailId>, NodeError> {
+            self.with_rail_index(move |index| index.rails_for(&entity, &filter)).await
+        }
+
+        /// Rails linking both `a` and `b` that pass `filter`.
+        pub async fn rails_between(&self, a: EntityId, b: EntityId, filter: RailFilter) -> Result<Vec<RailId>, NodeError> {
+            self.with_rail_index(move |index| index.rails_between(&a, &b, &filter)).await
+        }
+
+        /// Snapshot of the weighted trust graph the rails we hold form, with
+        /// weights folded by the registered rail kinds.
+        pub async fn trust_graph(&self) -> Result<TrustGraph, NodeError> {
+            let (reply, rx) = oneshot::channel();
+            self.send(Command::TrustGraph { reply })?;
+            rx.await.map_err(|_| NodeError::Stopped)
+        }
+
+        /// `viewer`'s trust in every author it reaches over the trust graph,
+        /// for filtering what they wrote with `TrustView::apply`.
+        pub async fn trust_view(&self, viewer: EntityId, measure: TrustMeasure) -> Result<TrustView, NodeError> {
+            let (reply, rx) = oneshot::channel();
+            self.send(Command::TrustView { viewer, measure, reply })?;
+            rx.await.map_err(|_| NodeError::Stopped)
+        }
+
+        /// Rails and entities we hold that only one side links up.
+        pub async fn half_linked_rails(&self) -> Result<Vec<HalfLink>, NodeError> {
+            self.with_docs(|docs| rails::half_linked(docs)).await
+        }
+
+        /// Every relationship recorded about `entity`, keyed by who recorded it
+        /// and the other entity. The RelationshipDoc is found through the
+        /// DHT and synced first, waiting up to `RELATIONSHIP_RESOLVE_TIMEOUT`
+        /// before answering from our replica.
+        pub async fn relationships(&self, entity: EntityId) -> Result<BTreeMap<(AuthorId, EntityId), Relationship>, NodeError> {
+            let (reply, rx) = oneshot::channel();
+            self.send(Command::ResolveRelationships { entity, reply: Some(reply) })?;
+            let _ = tokio::time::timeout(RELATIONSHIP_RESOLVE_TIMEOUT, rx).await;
+            self.with_docs(move |docs| relationship::get(docs, &entity)).await
+        }
+
+        /// Record how `other` relates to `entity`, or remove what we recorded
+        /// with `None`. Our entries live under our own keys, so this never
+        /// replaces what others recorded.
+        pub async fn set_relationship(
+            &self,
+            entity: EntityId,
+            other: EntityId,
+            relationship: Option<Relationship>,
+        ) -> Result<(), NodeError> {
+            self.with_docs(move |docs| match relationship {
+                Some(relationship) => relationship::set(docs, &entity, &other, &relationship),
+                None => relationship::remove(docs, &entity, &other),
+            })
+            .await?
+            .map_err(|e| NodeError::Doc(e.to_string()))?;
+            self.send(Command::ResolveRelationships { entity, reply: None })
+        }
+
+        /// Decide whose writes, rails and gossip we accept by `config`.
+        pub fn set_policy(&self, config: PolicyConfig) -> Result<(), NodeError> {
+            self.send(Command::SetPolicy(config))
+        }
+
+        /// Snapshot of the policy, for asking who is blocked, defederated or
+        /// trusted.
+        pub async fn policy(&self) -> Result<PolicyEngine, NodeError> {
+            let (reply, rx) = oneshot::channel();
+            self.send(Command::Policy { reply })?;
+            rx.await.map_err(|_| NodeError::Stopped)
+        }
+
+        /// Refuse connections, gossip and writes from `peer` until unblocked,
+        /// and disconnect it. Kept on the deny list across restarts.
+        pub async fn block_peer(&self, peer: PeerId) -> Result<(), NodeError> {
+            self.set_access(List::Deny, Subject::Peer(peer), true).await
+        }
+
+        pub async fn unblock_peer(&self, peer: PeerId) -> Result<(), NodeError> {
+            self.set_access(List::Deny, Subject::Peer(peer), false).await
+        }
+
+        /// Put `subject` on the allow or deny list, or take it off. Lists are
+        /// saved, and applied to connections, gossip and document sync at once.
+        pub async fn set_access(&self, list: List, subject: Subject, listed: bool) -> Result<(), NodeError> {
+            let (reply, rx) = oneshot::channel();
+            self.send(Command::SetAccess { list, subject, listed, reply })?;
+            rx.await.map_err(|_| NodeError::Stopped)?
+        }
+
+        /// Accept only the peers or entities on the allow list, or stop doing so.
+        pub async fn set_exclusive(&self, scope: Scope, exclusive: bool) -> Result<(), NodeError> {
+            let (reply, rx) = oneshot::channel();
+            self.send(Command::SetExclusive { scope, exclusive, reply })?;
+            rx.await.map_err(|_| NodeError::Stopped)?
+        }
+
+        /// The node's allow and deny lists.
+        pub async fn access_lists(&self) -> Result<AccessLists, NodeError> {
+            Ok(self.policy().await?.access_lists().clone())
+        }
+
+        /// Stop syncing with and relaying for `peer`, and with `subtree`
+        /// everyone it vouches for over rails, then tell peers that trust us.
+        /// Returns the peers newly cut off.
+        pub async fn defederate(&self, peer: PeerId, subtree: bool, reason: &str) -> Result<Vec<PeerId>, NodeError> {
+            let (reply, rx) = oneshot::channel();
+            self.send(Command::Defederate { peer, subtree, reason: reason.to_string(), reply })?;
+            rx.await.map_err(|_| NodeError::Stopped)
+        }
+
+        /// Lift the defederation of `peer` and of everyone cut off with it,
+        /// taking them back into the gossip mesh. Returns the peers restored.
+        pub async fn restore_peer(&self, peer: PeerId) -> Result<Vec<PeerId>, NodeError> {
+            let (reply, rx) = oneshot::channel();
+            self.send(Command::RestorePeer { peer, reply })?;
+            rx.await.map_err(|_| NodeError::Stopped)
+        }
+
+        /// Replicate a rail found in an entity from the peers we're connected to.
+        pub async fn open_rail(&self, id: RailId) -> Result<RailId, NodeError> {
+            self.join_doc(rails::read_ticket(id)).await
+        }
+
+        /// Run `f` against the node's documents on its event loop.
+        async fn with_docs<R, F>(&self, f: F) -> Result<R, NodeError>
+        where
+            F: FnOnce(&mut Docs) -> R + Send + 'static,
+            R: Send + 'static,
+        {
+            let (reply, rx) = oneshot::channel();
+            self.send(Command::WithDocs(Box::new(move |docs| {
+                let _ = reply.send(f(docs));
+            })))?;
+            rx.await.map_err(|_| NodeError::Stopped)
+        }
+
+        /// Run `f` against the node's rail index on its event loop.
+        async fn with_rail_index<R, F>(&self, f: F) -> Result<R, NodeError>
+        where
+            F: FnOnce(&RailIndex) -> R + Send + 'static,
+            R: Send + 'static,
+        {
+            let (reply, rx) = oneshot::channel();
+            self.send(Command::WithRailIndex(Box::new(move |index| {
+                let _ = reply.send(f(index));
+            })))?;
+            rx.await.map_err(|_| NodeError::Stopped)
+        }
+
+        /// Run `f` against the node's blob store on its event loop.
+        async fn with_store<R, F>(&self, f: F) -> Result<R, NodeError>
+        where
+            F: FnOnce(&BlobStore) -> R + Send + 'static,
+            R: Send + 'static,
+        {
+            let (reply, rx) = oneshot::channel();
+            self.send(Command::WithStore(Box::new(move |store| {
+                let _ = reply.send(f(store));
+            })))?;
+            rx.await.map_err(|_| NodeError::Stopped)
+        }
+
+        fn send(&self, command: Command) -> Result<(), NodeError> {
+            self.commands.send(command).map_err(|_| NodeError::Stopped)
+        }
+    }
+
+    /// Score thresholds for gossip, publish and graylisting. Peers below the
+    /// graylist threshold are also disconnected by the node.
+    pub fn peer_score_thresholds() -> PeerScoreThresholds {
+        PeerScoreThresholds {
+            gossip_threshold: -10.0,
+            publish_threshold: -50.0,
+            graylist_threshold: -80.0,
+            accept_px_threshold: 10.0,
+            opportunistic_graft_threshold: 20.0,
+        }
+    }
+
+    /// Peer scoring with per-topic parameters for every topic we subscribe to.
+    pub fn peer_score_params(topics: &[&IdentTopic]) -> PeerScoreParams {
+        let mut params = PeerScoreParams::default();
+        for topic in topics {
+            params.topics.insert(topic.hash(), topic_score_params());
+        }
+        params
+    }
+
+    fn topic_score_params() -> TopicScoreParams {
+        TopicScoreParams {
+            topic_weight: 1.0,
+            // Reward peers for staying in the mesh, capped after an hour
+            time_in_mesh_weight: 0.01,
+            time_in_mesh_quantum: Duration::from_secs(1),
+            time_in_mesh_cap: 3600.0,
+            // Reward peers that deliver new messages first
+            first_message_deliveries_weight: 1.0,
+            first_message_deliveries_decay: 0.5,
+            first_message_deliveries_cap: 20.0,
+            // Our topics are low traffic, so don't penalise mesh peers for slow delivery
+            mesh_message_deliveries_weight: 0.0,
+            mesh_failure_penalty_weight: 0.0,
+            // Invalid messages are the main sign of a misbehaving peer
+            invalid_message_deliveries_weight: -100.0,
+            invalid_message_deliveries_decay: 0.3,
+            ..Default::default()
+        }
+    }
+
+    pub fn content_addressed_topics() -> HashSet<TopicHash> {
+        CONTENT_ADDRESSED_TOPICS
+            .iter()
+            .map(|topic| IdentTopic::new(*topic).hash())
+            .collect()
+    }
+
+    /// Stable gossipsub message id. Hashes the publisher, sequence number and
+    /// data with BLAKE3, or only the topic and data for content-addressed topics.
+    pub fn message_id(message: &gossipsub::Message, content_addressed: &HashSet<TopicHash>) -> gossipsub::MessageId {
+        let mut hasher = blake3::Hasher::new();
+        if content_addressed.contains(&message.topic) {
+            hasher.update(b"raggy/content/");
+            hasher.update(message.topic.as_str().as_bytes());
+        } else {
+            hasher.update(b"raggy/source/");
+            let source = message.source.map(|peer| peer.to_bytes()).unwrap_or_default();
+            hasher.update(&(source.len() as u64).to_be_bytes());
+            hasher.update(&source);
+            hasher.update(&message.sequence_number.unwrap_or_default().to_be_bytes());
+        }
+        hasher.update(&(message.data.len() as u64).to_be_bytes());
+        hasher.update(&message.data);
+        gossipsub::MessageId::from(hasher.finalize().as_bytes().to_vec())
+    }
+
+    /// Start reconciling our replica of `doc` with `peer`, unless a session is already running
+    fn start_doc_sync(
+        swarm: &mut Swarm<MyBehaviour>,
+        docs: &Docs,
+        sync_requests: &mut HashMap<OutboundRequestId, (PeerId, DocId)>,
+        peer: PeerId,
+        doc: DocId,
+    ) {
+        let Some(replica) = docs.doc(&doc) else { return };
+        if docs.is_private(&doc) || sync_requests.values().any(|session| *session == (peer, doc)) {
+            return;
+        }
+        let request = SyncRequest { doc, ranges: sync::start(replica), hot: sync::HOT_SET_SIZE };
+        let request_id = swarm.behaviour_mut().doc_sync.send_request(&peer, request);
+        sync_requests.insert(request_id, (peer, doc));
+    }
+
+    /// Count one reply to a targeted fetch of `key`, answering the waiting
+    /// reads once the key has arrived or every peer asked has replied
+    fn finish_key_fetch(docs: &Docs, key_waiters: &mut HashMap<(DocId, String), KeyWaiters>, doc: DocId, key: String) {
+        let waiting = (doc, key);
+        let Some((pending, _)) = key_waiters.get_mut(&waiting) else { return };
+        *pending = pending.saturating_sub(1);
+        let arrived = docs.doc(&doc).is_some_and(|replica| replica.entry(&waiting.1).is_some());
+        if *pending > 0 && !arrived {
+            return;
+        }
+        let Some((_, waiters)) = key_waiters.remove(&waiting) else { return };
+        let value = docs.get(&doc, &waiting.1).map_err(|e| NodeError::Doc(e.to_string()));
+        for waiter in waiters {
+            let _ = waiter.send(value.clone());
+        }
+    }
+
+    /// Move a download whose chunks have all arrived into the store and
+    /// answer everyone waiting on it
+    fn finish_download(
+        store: &BlobStore,
+        downloads: &mut HashMap<Cid, Download>,
+        blob_waiters: &mut HashMap<Cid, BlobWaiters>,
+        cid: Cid,
+    ) {
+        let Some(download) = downloads.get_mut(&cid) else { return };
+        let result = download.finish().map_err(NodeError::Blob).and_then(|data| {
+            store.put(&data).map_err(|e| NodeError::Store(e.to_string()))?;
+            Ok(data)
+        });
+        if result.is_ok() {
+            if let Some(Err(e)) = downloads.remove(&cid).map(Download::discard) {
+                println!("Failed to remove partial download of {cid}: {e}");
+            }
+        }
+        for waiter in blob_waiters.remove(&cid).unwrap_or_default() {
+            let _ = waiter.send(result.clone());
+        }
+    }
+
+    /// Join the document behind `ticket` and sync it from the peers it lists,
+    /// and from `via`, the peer we found the ticket on, if any. Peers we have
+    /// to dial first are noted in `dialing`.
+    fn join_ticket(
+        swarm: &mut Swarm<MyBehaviour>,
+        docs: &mut Docs,
+        sync_requests: &mut HashMap<OutboundRequestId, (PeerId, DocId)>,
+        dialing: &mut HashMap<PeerId, HashSet<DocId>>,
+        ticket: &DocTicket,
+        via: Option<PeerId>,
+    ) -> Result<DocId, DocError> {
+        let doc = docs.join(ticket)?;
+        let local_peer_id = *swarm.local_peer_id();
+        let mut peers: Vec<(PeerId, &[Multiaddr])> = ticket
+            .peers
+            .iter()
+            .map(|TicketPeer { peer, addrs }| (*peer, addrs.as_slice()))
+            .chain(via.map(|peer| (peer, &[][..])))
+            .collect();
+        // A ticket that lists nobody, such as one built from a bare id, is
+        // synced from everyone we're connected to
+        if peers.is_empty() {
+            peers = swarm.connected_peers().map(|peer| (*peer, &[][..])).collect();
+        }
+        for (peer, addrs) in peers.into_iter().filter(|(peer, _)| *peer != local_peer_id) {
+            if swarm.is_connected(&peer) {
+                start_doc_sync(swarm, docs, sync_requests, peer, doc);
+                continue;
+            }
+            // Sync starts once the connection is established
+            for addr in addrs {
+                swarm.behaviour_mut().kademlia.add_address(&peer, addr.clone());
+            }
+            match swarm.dial(peer) {
+                Ok(()) => {
+                    dialing.entry(peer).or_default().insert(doc);
+                }
+                Err(e) => println!("Failed to dial ticket peer {peer}: {e}"),
+            }
+        }
+        Ok(doc)
+    }
+
+    /// Give up on `doc` in the walks waiting on it once no sync of it is in
+    /// flight and no dial could still start one, finishing walks left with
+    /// nothing else to wait on
+    fn give_up_if_stalled(
+        walks: &mut Vec<SyncWalk>,
+        sync_requests: &HashMap<OutboundRequestId, (PeerId, DocId)>,
+        dialing: &HashMap<PeerId, HashSet<DocId>>,
+        events: &broadcast::Sender<NodeEvent>,
+        doc: DocId,
+        reason: &str,
+    ) {
+        let syncing = sync_requests.values().any(|(_, synced)| *synced == doc);
+        if syncing || dialing.values().any(|docs| docs.contains(&doc)) {
+            return;
+        }
+        for walk in walks.iter_mut().filter(|walk| walk.contains(&doc)) {
+            if walk.failed(&doc) {
+                println!("Gave up syncing {doc}: {reason}");
+                let error = Some(reason.to_string());
+                let _ = events.send(NodeEvent::DocSyncProgress { doc, progress: walk.progress(&doc), error });
+            }
+        }
+        walks.retain(|walk| !walk.is_complete());
+    }
+
+    /// Apply entries `peer` sent for `doc`. Invalid ones are reported and
+    /// dropped, so they are never passed on to other replicas. A rail is
+    /// held back from the moment its creator entry arrives until the policy
+    /// accepts the creator.
+    fn apply_remote_entries(
+        docs: &mut Docs,
+        peer_policy: &mut PolicyEngine,
+        peer: PeerId,
+        doc: DocId,
+        entries: Vec<Entry>,
+        events: &broadcast::Sender<NodeEvent>,
+    ) {
+        if peer_policy.is_cut_off(&peer) || !peer_policy.doc_allowed(docs, &doc) {
+            return;
+        }
+        let now = Instant::now();
+        // Rails we were invited to were judged with the invitation
+        let creator = docs.doc(&doc).and_then(|replica| rails::arriving_creator(replica, &entries));
+        let invited = || rails::invites(docs).iter().any(|(invite, _)| invite.rail == doc);
+        if let Some(creator) = creator.and_then(|creator| creator.peer_id()).filter(|_| !invited()) {
+            if let Err(reason) = peer_policy.decide(&creator, Action::Rail, now) {
+                println!("Refused rail {doc} by {creator}: {reason}");
+                let _ = events.send(NodeEvent::PolicyRejected { peer: creator, action: Action::Rail, reason });
+                return;
+            }
+        }
+        // Entries relayed from authors the policy refuses are dropped too
+        let mut accepted = Vec::with_capacity(entries.len());
+        for entry in entries {
+            let Some(author) = entry.author.peer_id() else {
+                accepted.push(entry);
+                continue;
+            };
+            match peer_policy.decide(&author, Action::Write, now) {
+                Ok(()) => accepted.push(entry),
+                Err(reason) => {
+                    println!("Refused entry '{}' in {doc} by {author}: {reason}", entry.key);
+                    let _ = events.send(NodeEvent::PolicyRejected { peer: author, action: Action::Write, reason });
+                }
+            }
+        }
+        match docs.apply(&doc, accepted) {
+            Ok((_, rejected)) => {
+                for (entry, reason) in rejected {
+                    println!("Rejected entry '{}' in {doc} from {peer}: {reason}", entry.key);
+                    let reason = reason.to_string();
+                    let _ = events.send(NodeEvent::ValidationFailed { peer, doc, author: entry.author, reason });
+                }
+            }
+            Err(e) => println!("Failed to apply entries from {peer}: {e}"),
+        }
+    }
+
+    /// Serve a `rail.invite` request: record the invitation as `policy`
+    /// decides, pending if there is none, and sync the rail once accepted
+    #[allow(clippy::too_many_arguments)]
+    fn handle_rail_invite(
+        swarm: &mut Swarm<MyBehaviour>,
+        docs: &mut Docs,
+        sync_requests: &mut HashMap<OutboundRequestId, (PeerId, DocId)>,
+        dialing: &mut HashMap<PeerId, HashSet<DocId>>,
+        peer_policy: &mut PolicyEngine,
+        policy: Option<&InvitePolicy>,
+        peer: PeerId,
+        body: &[u8],
+        events: &broadcast::Sender<NodeEvent>,
+    ) -> RpcResponse {
+        let invite: RailInvite = match from_cbor(body) {
+            Ok(invite) => invite,
+            Err(e) => return RpcResponse::Err(format!("malformed rail invitation: {e}")),
+        };
+        // Invitations are sent again until answered, but each rail counts once
+        let known = matches!(rails::invite_status(docs, &invite.rail, &invite.entity), Ok(Some(_)));
+        if !known {
+            if let Err(reason) = peer_policy.decide(&peer, Action::Rail, Instant::now()) {
+                println!("Refused rail {} from {peer}: {reason}", invite.rail);
+                let response = RpcResponse::Err(format!("rail refused: {reason}"));
+                let _ = events.send(NodeEvent::PolicyRejected { peer, action: Action::Rail, reason });
+                return response;
+            }
+        }
+        let status = policy.map_or(BackRefStatus::Pending, |decide| decide(peer, &invite));
+        let status = match rails::receive_invite(docs, invite.clone(), status) {
+            Ok(status) => status,
+            Err(e) => return RpcResponse::Err(e.to_string()),
+        };
+        println!("Invitation from {peer} to link {} to rail {}: {status:?}", invite.entity, invite.rail);
+        if status == BackRefStatus::Accepted {
+            if let Err(e) = join_ticket(swarm, docs, sync_requests, dialing, &invite.ticket, Some(peer)) {
+                println!("Failed to sync rail {}: {e}", invite.rail);
+            }
+        }
+        let _ = events.send(NodeEvent::RailInvited { peer, invite, status });
+        match to_cbor(&status) {
+            Ok(body) => RpcResponse::Ok(body),
+            Err(e) => RpcResponse::Err(e.to_string()),
+        }
+    }
+
+    /// Add `peer` to the gossip mesh as an explicit peer, unless the policy
+    /// cut it off
+    fn add_gossip_peer(swarm: &mut Swarm<MyBehaviour>, peer_policy: &PolicyEngine, peer: &PeerId) {
+        if !peer_policy.is_cut_off(peer) {
+            swarm.behaviour_mut().gossipsub.add_explicit_peer(peer);
+        }
+    }
+
+    /// Drop `peers` from the gossip mesh and disconnect them
+    fn cut_off_peers(swarm: &mut Swarm<MyBehaviour>, peers: &[PeerId]) {
+        for peer in peers {
+            swarm.behaviour_mut().gossipsub.remove_explicit_peer(peer);
+            let _ = swarm.disconnect_peer_id(*peer);
+        }
+    }
+
+    /// Bring the connection gates in line with the policy's access lists,
+    /// and drop connected peers the policy now refuses
+    fn apply_access_lists(swarm: &mut Swarm<MyBehaviour>, peer_policy: &PolicyEngine, changed: Option<&Subject>) {
+        swarm.behaviour_mut().allowed.set_lists(peer_policy.access_lists().clone());
+        if let Some(Subject::Peer(peer)) = changed {
+            if peer_policy.access_lists().subjects(List::Deny).contains(&Subject::Peer(*peer)) {
+                swarm.behaviour_mut().blocked.block_peer(*peer);
+            } else {
+                swarm.behaviour_mut().blocked.unblock_peer(*peer);
+            }
+        }
+        let refused: Vec<PeerId> = swarm.connected_peers().filter(|peer| peer_policy.is_cut_off(peer)).copied().collect();
+        cut_off_peers(swarm, &refused);
+    }
+
+    /// Score peers against the trust graph the rails we hold form
+    fn refresh_policy(peer_policy: &mut PolicyEngine, docs: &Docs, rail_index: &RailIndex, rail_kinds: &RailKinds) {
+        peer_policy.refresh(TrustGraph::from_index(rail_index, rail_kinds), entity::owners(docs));
+    }
+
+    /// Invite the owners of entities missing their back-reference to record
+    /// it. Owners we aren't connected to are tried again on the next check.
+    fn send_rail_invites(
+        swarm: &mut Swarm<MyBehaviour>,
+        docs: &Docs,
+        sent_invites: &mut HashMap<OutboundRequestId, (RailId, EntityId)>,
+        links: &[HalfLink],
+    ) {
+        let us = TicketPeer { peer: *swarm.local_peer_id(), addrs: swarm.listeners().cloned().collect() };
+        for link in links.iter().filter(|link| link.missing == MissingLink::BackReference && link.owner != docs.author()) {
+            if sent_invites.values().any(|sent| *sent == (link.rail, link.entity)) {
+                continue;
+            }
+            let Some(peer) = link.owner.peer_id().filter(|peer| swarm.is_connected(peer)) else { continue };
+            let mut invite = match rails::invite(docs, &link.rail, &link.entity) {
+                Ok(invite) => invite,
+                // Only rails we're on can be handed on
+                Err(DocError::ReadOnly(_)) => continue,
+                Err(e) => {
+                    println!("Failed to invite {peer} to rail {}: {e}", link.rail);
+                    continue;
+                }
+            };
+            invite.ticket.peers = vec![us.clone()];
+            match to_cbor(&invite) {
+                Ok(body) => {
+                    let request = RpcRequest { method: rails::RAIL_INVITE_METHOD.to_string(), body };
+                    let request_id = swarm.behaviour_mut().rpc.send_request(&peer, request);
+                    sent_invites.insert(request_id, (link.rail, link.entity));
+                }
+                Err(e) => println!("Failed to encode rail invitation: {e}"),
+            }
+        }
+    }
+
+    /// Load the node's identity from `path`, creating it on first start so
+    /// the PeerId, and everything the node owns, survives restarts
+    fn load_or_create_identity(path: &Path) -> Result<identity::Keypair, Box<dyn Error>> {
+        if path.exists() {
+            return Ok(identity::Keypair::from_protobuf_encoding(&std::fs::read(path)?)?);
+        }
+        let key = identity::Keypair::generate_ed25519();
+        std::fs::write(path, key.to_protobuf_encoding()?)?;
+        Ok(key)
+    }
+
+    /// Announce our TicketReadDoc on the join topic, with the addresses we listen on
+    fn publish_join_announcement(
+        swarm: &mut Swarm<MyBehaviour>,
+        docs: &Docs,
+        node_key: &identity::ed25519::Keypair,
+        name: &str,
+        topic: &IdentTopic,
+    ) {
+        let peers = vec![TicketPeer { peer: *swarm.local_peer_id(), addrs: swarm.listeners().cloned().collect() }];
+        let ticket = match docs.share(&docs.owned_id(TICKET_READ_DOC), ShareMode::Read, peers) {
+            Ok(ticket) => ticket,
+            Err(e) => {
+                println!("Failed to create join ticket: {e}");
+                return;
+            }
+        };
+        let message = to_cbor(&JoinAnnouncement::new(node_key, name, ticket))
+            .map(|body| RaggyMessage::new(MessageKind::Join, name, CONTENT_TYPE_CBOR, body))
+            .and_then(|message| message.encode());
+        match message {
+            Ok(bytes) => {
+                if let Err(e) = swarm.behaviour_mut().gossipsub.publish(topic.clone(), bytes) {
+                    println!("Failed to publish join announcement: {e}");
+                }
+            }
+            Err(e) => println!("Failed to encode join announcement: {e}"),
+        }
+    }
+
+    /// Report document changes to subscribers, re-index the documents that
+    /// changed and announce their new heads
+    fn publish_doc_changes(
+        docs: &mut Docs,
+        rail_index: &mut RailIndex,
+        gossipsub: &mut gossipsub::Behaviour,
+        topic: &IdentTopic,
+        name: &str,
+        events: &broadcast::Sender<NodeEvent>,
+    ) {
+        let mut changed = HashSet::new();
+        for (doc, entry) in docs.take_changes() {
+            changed.insert(doc);
+            let _ = events.send(NodeEvent::DocChanged { doc, entry });
+        }
+        for doc in &changed {
+            rail_index.update(docs, doc);
+        }
+        for doc in changed.into_iter().filter_map(|id| docs.doc(&id)) {
+            let message = to_cbor(&sync::heads(doc))
+                .map(|body| RaggyMessage::new(MessageKind::DocSync, name, CONTENT_TYPE_CBOR, body))
+                .and_then(|message| message.encode());
+            match message {
+                Ok(bytes) => {
+                    // Nobody else subscribed yet is fine; they reconcile on connect
+                    if let Err(e) = gossipsub.publish(topic.clone(), bytes) {
+                        println!("Failed to announce heads of {}: {e}", doc.id());
+                    }
+                }
+                Err(e) => println!("Failed to encode heads of {}: {e}", doc.id()),
+            }
+        }
+    }
+
     pub async fn run_node(
         args: Vec<String>,
         message_callback: Arc<Mutex<HashSet<String>>>,
     ) -> Result<(), Box<dyn Error>> {
+        let (_handle, inbox) = NodeHandle::new();
+        run_node_with_handle(args, message_callback, inbox).await
+    }
+
+    /// Same as `run_node`, but serves commands from the `NodeHandle` paired with `inbox`.
+    pub async fn run_node_with_handle(
+        args: Vec<String>,
+        message_callback: Arc<Mutex<HashSet<String>>>,
+        inbox: NodeInbox,
+    ) -> Result<(), Box<dyn Error>> {
+        let NodeInbox { commands: mut command_rx, events } = inbox;
+
         // Parse command line arguments using the vector
         let cli = Cli::try_parse_from(args)?;
+        let started = Instant::now();
+
+        // Without a directory to keep, the node gets a new identity and a
+        // directory of its own, so no two nodes ever share a key
+        let (data_dir, new_key) = match (&cli.data_dir, cli.data_dir_by_name) {
+            (Some(dir), _) => (dir.clone(), None),
+            (None, true) => (std::env::temp_dir().join(format!("raggy-{}", cli.name)), None),
+            (None, false) => {
+                let key = identity::Keypair::generate_ed25519();
+                (std::env::temp_dir().join(format!("raggy-{}", PeerId::from(key.public()))), Some(key))
+            }
+        };
 
-        // Create a random PeerId
-        let local_key = identity::Keypair::generate_ed25519();
+        // Open the local content-addressed store
+        let store = BlobStore::open(&data_dir)?;
+        println!("Using data directory {}", data_dir.display());
+
+        // Load or create our identity
+        let identity_path = data_dir.join("identity.key");
+        let local_key = match new_key {
+            Some(key) => {
+                std::fs::write(&identity_path, key.to_protobuf_encoding()?)?;
+                key
+            }
+            None => load_or_create_identity(&identity_path)?,
+        };
         let local_peer_id = PeerId::from(local_key.public());
         println!("Local peer id: {local_peer_id}");
 
+        // Documents are authored with the node's identity key
+        let node_key = local_key.clone().try_into_ed25519()?;
+        let mut docs = Docs::open(&data_dir, node_key.clone())?;
+        rails::register(&mut docs);
+
+        // Every node keeps its own state in an owner-only MainDoc, and read
+        // tickets for it in an owner-only TicketReadDoc
+        let main_doc = docs.create_owned(MAIN_DOC)?;
+        let ticket_read_doc = docs.create_owned(TICKET_READ_DOC)?;
+        let ticket_peers = vec![TicketPeer { peer: local_peer_id, addrs: Vec::new() }];
+        let main_ticket = docs.share(&main_doc, ShareMode::Read, ticket_peers.clone())?;
+        let ticket_read_ticket = docs.share(&ticket_read_doc, ShareMode::Read, ticket_peers)?;
+        let node_state = [
+            (ticket_read_doc, MAIN_DOC, main_ticket.to_string()),
+            (main_doc, "node/name", cli.name.clone()),
+            (main_doc, "node/peer-id", local_peer_id.to_string()),
+            (main_doc, "tickets/read", ticket_read_ticket.to_string()),
+        ];
+        for (doc, key, value) in node_state {
+            if docs.get(&doc, key)?.as_deref() != Some(value.as_bytes()) {
+                docs.set(&doc, key, value.into_bytes())?;
+            }
+        }
+        // Nobody is subscribed yet, and peers reconcile on connect anyway
+        docs.take_changes();
+
+        // Which rails touch which entities, kept up to date as documents change
+        let mut rail_index = RailIndex::build(&docs);
+
         // Create transport with TCP and QUIC support
         let transport = {
             let tcp = libp2p::tcp::tokio::Transport::new(libp2p::tcp::Config::default())
@@ -127,15 +1334,19 @@ pub mod node {
         cfg.set_query_timeout(Duration::from_secs(5 * 60));
         cfg.set_record_ttl(Some(Duration::from_secs(60)));
         cfg.set_publication_interval(Some(Duration::from_secs(30)));
-        let store = MemoryStore::new(local_peer_id);
-        let kademlia = KademliaBehaviour::with_config(local_peer_id, store, cfg);
+        let kad_store = MemoryStore::new(local_peer_id);
+        let kademlia = KademliaBehaviour::with_config(local_peer_id, kad_store, cfg);
 
         // Set up mDNS for local peer discovery
         let mdns = mdns::tokio::Behaviour::new(mdns::Config::default(), local_peer_id)?;
 
         // Set up GossipSub
+        let content_addressed = content_addressed_topics();
         let gossipsub_config = gossipsub::ConfigBuilder::default()
             .heartbeat_interval(Duration::from_secs(1))
+            .message_id_fn(move |message| message_id(message, &content_addressed))
+            // Nothing is relayed before the policy has accepted it
+            .validate_messages()
             .build()
             .expect("Valid config");
 
@@ -150,13 +1361,48 @@ pub mod node {
         // Subscribe to the topic
         gossipsub.subscribe(&topic)?;
 
+        // Presence heartbeats go on their own topic
+        let presence_topic = IdentTopic::new(PRESENCE_TOPIC);
+        gossipsub.subscribe(&presence_topic)?;
+
+        // Document heads are announced so replicas know when to reconcile
+        let heads_topic = IdentTopic::new(DOC_HEADS_TOPIC);
+        gossipsub.subscribe(&heads_topic)?;
+
+        // Nodes announce their TicketReadDoc so others can find their state
+        let join_topic = IdentTopic::new(JOIN_TOPIC);
+        gossipsub.subscribe(&join_topic)?;
+
+        // Defederations are shared so peers that trust us can follow them
+        let defederation_topic = IdentTopic::new(DEFEDERATION_TOPIC);
+        gossipsub.subscribe(&defederation_topic)?;
+
+        // Score peers so spammy or misbehaving ones lose their place in the mesh
+        gossipsub.with_peer_score(
+            peer_score_params(&[&topic, &presence_topic, &heads_topic, &join_topic, &defederation_topic]),
+            peer_score_thresholds(),
+        )?;
+
         // Create the network behaviour
+        // Peers on the saved deny list, or left off an exclusive allow list,
+        // can't connect either way
+        let access_lists = AccessLists::load(&docs);
+        let mut blocked = allow_block_list::Behaviour::default();
+        for peer in access_lists.denied_peers() {
+            blocked.block_peer(peer);
+        }
+
         let behaviour = MyBehaviour {
             ping: ping::Behaviour::new(ping::Config::new()),
             identify,
             kademlia,
             mdns,
             gossipsub,
+            rpc: rpc::new_behaviour(),
+            blob: blob::new_behaviour(),
+            doc_sync: sync::new_behaviour(),
+            blocked,
+            allowed: access_list::Behaviour::new(access_lists.clone()),
         };
 
         // Create a Swarm to manage peers and events
@@ -170,6 +1416,19 @@ pub mod node {
         // Listen on multiple protocols for better connectivity
         swarm.listen_on(format!("/ip4/0.0.0.0/tcp/{}", cli.port).parse()?)?;
 
+        // Announce everything already in the store, and the RelationshipDocs we hold
+        for cid in store.list()? {
+            if let Err(e) = swarm.behaviour_mut().kademlia.start_providing(RecordKey::new(&cid.to_bytes())) {
+                println!("Failed to announce blob {cid}: {e}");
+            }
+        }
+        for id in docs.ids() {
+            let Some(Namespace::Relationships { entity }) = docs.doc(&id).map(|doc| doc.namespace()) else { continue };
+            if let Err(e) = swarm.behaviour_mut().kademlia.start_providing(RecordKey::new(&relationship::provider_key(entity))) {
+                println!("Failed to announce relationships of {entity}: {e}");
+            }
+        }
+
         // Bootstrap with public DHT nodes
         let bootstrap_nodes = vec![
             "/dnsaddr/bootstrap.libp2p.io/p2p/QmNnooDu7bfjPFoTZYxMNLWUQJyrVwtbZg5gBMjTezGAJN",
@@ -183,8 +1442,60 @@ pub mod node {
         // Set up periodic DHT peer search interval
         let mut search_interval = interval(Duration::from_secs(30));
 
-        // Set up periodic message broadcast interval
-        let mut broadcast_interval = interval(Duration::from_secs(GOSSIP_INTERVAL));
+        // Set up periodic presence heartbeat and the roster built from everyone else's
+        let mut presence_interval = interval(PRESENCE_INTERVAL);
+        let mut roster = Roster::new(PRESENCE_INTERVAL, MISSED_HEARTBEATS);
+
+        // Set up periodic check for peers that fell below the graylist threshold
+        let mut score_interval = interval(Duration::from_secs(SCORE_CHECK_INTERVAL));
+
+        // Set up periodic check for rails only one side links up
+        let mut rail_reconcile_interval = interval(RAIL_RECONCILE_INTERVAL);
+
+        // RPC handlers registered through the NodeHandle, and requests awaiting a response
+        let mut rpc_handlers = HandlerRegistry::default();
+
+        // Rail kinds the application registered; none are built in
+        let mut rail_kinds = RailKinds::default();
+        let mut pending_requests: HashMap<OutboundRequestId, oneshot::Sender<Result<Vec<u8>, NodeError>>> = HashMap::new();
+
+        // Downloads in progress (kept so failed fetches can resume) and the
+        // callers waiting on each of them
+        let mut downloads: HashMap<Cid, Download> = HashMap::new();
+        let mut blob_waiters: HashMap<Cid, BlobWaiters> = HashMap::new();
+        let mut blob_requests: HashMap<OutboundRequestId, (PeerId, Cid)> = HashMap::new();
+
+        // Document reconciliations we started and are waiting on
+        let mut sync_requests: HashMap<OutboundRequestId, (PeerId, DocId)> = HashMap::new();
+
+        // Recursive syncs of joined documents and everything they reference,
+        // and the documents waiting on a dial before they can sync
+        let mut walks: Vec<SyncWalk> = Vec::new();
+        let mut dialing: HashMap<PeerId, HashSet<DocId>> = HashMap::new();
+
+        // Documents that have completed a sync with some peer, and reads
+        // waiting on single keys fetched ahead of that
+        let mut synced_docs: HashSet<DocId> = HashSet::new();
+        let mut key_fetches: HashMap<OutboundRequestId, (DocId, String)> = HashMap::new();
+        let mut key_waiters: HashMap<(DocId, String), KeyWaiters> = HashMap::new();
+
+        // Which announced tickets to join by ourselves
+        let mut join_interest: Option<InterestFilter> = None;
+
+        // What to do with rail invitations we receive, the ones we sent and
+        // await an answer to, and the ones answered, which aren't sent again
+        let mut rail_invite_policy: Option<InvitePolicy> = None;
+        let mut sent_invites: HashMap<OutboundRequestId, (RailId, EntityId)> = HashMap::new();
+        let mut answered_invites: HashSet<(RailId, EntityId)> = HashSet::new();
+
+        // Whose writes, rails and gossip we accept, and who is cut off
+        let mut peer_policy = PolicyEngine::new(local_peer_id, PolicyConfig::default());
+        peer_policy.set_access_lists(access_lists);
+
+        // DHT lookups for RelationshipDoc providers, and callers waiting on
+        // each document to sync
+        let mut relationship_lookups: HashMap<QueryId, DocId> = HashMap::new();
+        let mut relationship_waiters: HashMap<DocId, Vec<oneshot::Sender<()>>> = HashMap::new();
 
         // Main event loop
         loop {
@@ -199,9 +1510,9 @@ pub mod node {
                                     let remote: Multiaddr = addr.parse()?;
                                     println!("Dialing bootstrap node: {remote}");
                                     if let Some(Protocol::P2p(hash)) = remote.iter().find(|p| matches!(p, Protocol::P2p(_))) {
-                                        let peer_id = PeerId::from(hash);
+                                        let peer_id = hash;
                                         swarm.behaviour_mut().kademlia.add_address(&peer_id, remote.clone());
-                                        swarm.behaviour_mut().gossipsub.add_explicit_peer(&peer_id);
+                                        add_gossip_peer(&mut swarm, &peer_policy, &peer_id);
                                     }
                                 }
                                 if let Err(e) = swarm.behaviour_mut().kademlia.bootstrap() {
@@ -211,15 +1522,38 @@ pub mod node {
                                     println!("Failed to start providing record: {e}");
                                 }
                             }
+                            SwarmEvent::ConnectionEstablished { peer_id, num_established, .. } => {
+                                if peer_policy.is_cut_off(&peer_id) {
+                                    println!("Disconnecting cut off peer {peer_id}");
+                                    cut_off_peers(&mut swarm, &[peer_id]);
+                                    continue;
+                                }
+                                // Catch up on every document we share with a newly connected
+                                // peer, bar those our access lists refuse
+                                if num_established.get() == 1 {
+                                    for doc in docs.ids().into_iter().filter(|doc| peer_policy.doc_allowed(&docs, doc)) {
+                                        start_doc_sync(&mut swarm, &docs, &mut sync_requests, peer_id, doc);
+                                    }
+                                }
+                                for doc in dialing.remove(&peer_id).unwrap_or_default() {
+                                    give_up_if_stalled(&mut walks, &sync_requests, &dialing, &events, doc, "the peer won't sync it");
+                                }
+                            }
+                            SwarmEvent::OutgoingConnectionError { peer_id: Some(peer), error, .. } => {
+                                let reason = format!("failed to dial {peer}: {error}");
+                                for doc in dialing.remove(&peer).unwrap_or_default() {
+                                    give_up_if_stalled(&mut walks, &sync_requests, &dialing, &events, doc, &reason);
+                                }
+                            }
                             SwarmEvent::Behaviour(event) => {
                                 match event {
                                     MyBehaviourEvent::Kademlia(event) => {
                                         match event {
                                             KademliaEvent::RoutingUpdated { peer, .. } => {
                                                 println!("Routing table updated for peer: {peer}");
-                                                swarm.behaviour_mut().gossipsub.add_explicit_peer(&peer);
+                                                add_gossip_peer(&mut swarm, &peer_policy, &peer);
                                             }
-                                            KademliaEvent::OutboundQueryProgressed { result, .. } => {
+                                            KademliaEvent::OutboundQueryProgressed { id, result, step, .. } => {
                                                 match result {
                                                     QueryResult::GetRecord(Ok(ok)) => {
                                                         println!("GetRecord query completed");
@@ -231,11 +1565,11 @@ pub mod node {
                                                                         if let Ok(addr) = addr_str.parse::<Multiaddr>() {
                                                                             // Extract peer ID from the address if present
                                                                             if let Some(Protocol::P2p(hash)) = addr.iter().find(|p| matches!(p, Protocol::P2p(_))) {
-                                                                                let peer_id = PeerId::from(hash);
+                                                                                let peer_id = hash;
                                                                                 if peer_id != local_peer_id {  // Don't dial ourselves
                                                                                     println!("Found peer address in DHT: {}", addr);
                                                                                     swarm.behaviour_mut().kademlia.add_address(&peer_id, addr.clone());
-                                                                                    swarm.behaviour_mut().gossipsub.add_explicit_peer(&peer_id);
+                                                                                    add_gossip_peer(&mut swarm, &peer_policy, &peer_id);
                                                                                     if let Err(e) = swarm.dial(addr.clone()) {
                                                                                         println!("Failed to dial address {}: {}", addr, e);
                                                                                     }
@@ -250,12 +1584,37 @@ pub mod node {
                                                             }
                                                         }
                                                     }
-                                                    QueryResult::GetProviders(_) | QueryResult::StartProviding(_) => {
-                                                        // Ignore provider events - we're not using them
+                                                    QueryResult::GetProviders(found) => {
+                                                        let Some(doc) = relationship_lookups.get(&id).copied() else { continue };
+                                                        if !peer_policy.doc_allowed(&docs, &doc) {
+                                                            continue;
+                                                        }
+                                                        if let Ok(GetProvidersOk::FoundProviders { providers, .. }) = found {
+                                                            for provider in providers.into_iter().filter(|provider| *provider != local_peer_id) {
+                                                                if swarm.is_connected(&provider) {
+                                                                    start_doc_sync(&mut swarm, &docs, &mut sync_requests, provider, doc);
+                                                                } else if let Err(e) = swarm.dial(provider) {
+                                                                    // Connecting syncs every document we hold
+                                                                    println!("Failed to dial relationship provider {provider}: {e}");
+                                                                }
+                                                            }
+                                                        }
+                                                        if step.last {
+                                                            relationship_lookups.remove(&id);
+                                                            // Nobody left to hear from
+                                                            if !sync_requests.values().any(|(_, session)| *session == doc) {
+                                                                for waiter in relationship_waiters.remove(&doc).unwrap_or_default() {
+                                                                    let _ = waiter.send(());
+                                                                }
+                                                            }
+                                                        }
+                                                    }
+                                                    QueryResult::StartProviding(_) => {
+                                                        // Nothing to do once announced
                                                     }
                                                     QueryResult::Bootstrap(Ok(ok)) => {
                                                         println!("Bootstrap completed with peer: {}", ok.peer);
-                                                        swarm.behaviour_mut().gossipsub.add_explicit_peer(&ok.peer);
+                                                        add_gossip_peer(&mut swarm, &peer_policy, &ok.peer);
                                                         // After bootstrap, get the peer's addresses
                                                         let key = RecordKey::new(&format!("/raggy/peers/{}", ok.peer));
                                                         swarm.behaviour_mut().kademlia.get_record(key);
@@ -265,7 +1624,7 @@ pub mod node {
                                                         for peer in ok.peers {
                                                             if peer != local_peer_id {  // Don't dial ourselves
                                                                 println!("Found close peer: {}", peer);
-                                                                swarm.behaviour_mut().gossipsub.add_explicit_peer(&peer);
+                                                                add_gossip_peer(&mut swarm, &peer_policy, &peer);
                                                                 // Get the peer's addresses from DHT
                                                                 let key = RecordKey::new(&format!("/raggy/peers/{}", peer));
                                                                 swarm.behaviour_mut().kademlia.get_record(key);
@@ -310,16 +1669,17 @@ pub mod node {
                                             for addr in info.listen_addrs {
                                                 swarm.behaviour_mut().kademlia.add_address(&peer_id, addr);
                                             }
-                                            swarm.behaviour_mut().gossipsub.add_explicit_peer(&peer_id);
+                                            add_gossip_peer(&mut swarm, &peer_policy, &peer_id);
                                         }
                                     }
                                     MyBehaviourEvent::Mdns(event) => {
                                         match event {
                                             mdns::Event::Discovered(list) => {
-                                                for (peer_id, multiaddr) in list {
+                                                // Peers we refuse aren't dial candidates
+                                                for (peer_id, multiaddr) in list.into_iter().filter(|(peer, _)| !peer_policy.is_cut_off(peer)) {
                                                     println!("mDNS discovered a new peer: {peer_id}");
                                                     swarm.behaviour_mut().kademlia.add_address(&peer_id, multiaddr);
-                                                    swarm.behaviour_mut().gossipsub.add_explicit_peer(&peer_id);
+                                                    add_gossip_peer(&mut swarm, &peer_policy, &peer_id);
                                                 }
                                             }
                                             mdns::Event::Expired(list) => {
@@ -336,19 +1696,370 @@ pub mod node {
                                             message,
                                         } = gossip_event
                                         {
-                                            let msg_str = String::from_utf8_lossy(&message.data).to_string();
+                                            let source = message.source;
+                                            // Refused messages are only held against the peer that sent
+                                            // them if it published them too, not against relays
+                                            let publisher = source.unwrap_or(peer_id);
+                                            let verdict = match peer_policy.cut_off(&peer_id) {
+                                                Some(reason) => Err((peer_id, true, reason)),
+                                                None => peer_policy
+                                                    .decide(&publisher, Action::Gossip, Instant::now())
+                                                    .map_err(|reason| (publisher, publisher == peer_id, reason)),
+                                            };
+                                            let decoded = RaggyMessage::decode(&message.data);
+                                            // Only peers we refuse outright, or that send garbage, are
+                                            // penalised; young or untrusted ones just aren't relayed
+                                            let acceptance = match (&verdict, &decoded) {
+                                                (Err((_, true, reason)), _) if reason.cuts_off() => MessageAcceptance::Reject,
+                                                (Err(_), _) => MessageAcceptance::Ignore,
+                                                (Ok(()), Err(_)) => MessageAcceptance::Reject,
+                                                (Ok(()), Ok(_)) => MessageAcceptance::Accept,
+                                            };
+                                            let _ = swarm.behaviour_mut().gossipsub.report_message_validation_result(&id, &peer_id, acceptance);
+                                            if let Err((publisher, _, reason)) = verdict {
+                                                println!("Refused message {id} from {publisher}: {reason}");
+                                                let _ = events.send(NodeEvent::PolicyRejected { peer: publisher, action: Action::Gossip, reason });
+                                                continue;
+                                            }
+                                            let message = match decoded {
+                                                Ok(message) => message,
+                                                Err(e) => {
+                                                    println!("Dropping undecodable message {id} from peer {peer_id}: {e}");
+                                                    continue;
+                                                }
+                                            };
+                                            // Kinds and versions from newer builds are still relayed for
+                                            // the peers that understand them, but not acted on here
+                                            if !message.is_supported() {
+                                                println!("Ignoring unsupported {:?} message v{} from peer {peer_id}", message.kind, message.version);
+                                                continue;
+                                            }
                                             println!(
-                                                "Got message: '{}' with id: {} from peer: {:?}",
-                                                msg_str,
+                                                "Got {:?} message v{} from '{}' with id: {} from peer: {:?}",
+                                                message.kind,
+                                                message.version,
+                                                message.sender,
                                                 id,
                                                 peer_id
                                             );
-                                            message_callback.lock().await.insert(msg_str);
+                                            match &message.kind {
+                                                MessageKind::Chat => {
+                                                    if let Some(text) = message.text() {
+                                                        message_callback.lock().await.insert(text.to_string());
+                                                    }
+                                                }
+                                                MessageKind::Presence => {
+                                                    let presence: Presence = match from_cbor(&message.body) {
+                                                        Ok(presence) => presence,
+                                                        Err(e) => {
+                                                            println!("Dropping malformed presence from peer {peer_id}: {e}");
+                                                            continue;
+                                                        }
+                                                    };
+                                                    if source != Some(presence.peer_id) {
+                                                        println!("Dropping presence for {} published by {source:?}", presence.peer_id);
+                                                        continue;
+                                                    }
+                                                    let (peer, name) = (presence.peer_id, presence.name.clone());
+                                                    if roster.observe(presence, Instant::now()) {
+                                                        println!("Peer {name} ({peer}) joined");
+                                                        let _ = events.send(NodeEvent::PeerJoined { peer, name });
+                                                    }
+                                                }
+                                                MessageKind::DocSync => {
+                                                    let heads: DocHeads = match from_cbor(&message.body) {
+                                                        Ok(heads) => heads,
+                                                        Err(e) => {
+                                                            println!("Dropping malformed document heads from peer {peer_id}: {e}");
+                                                            continue;
+                                                        }
+                                                    };
+                                                    let behind = docs
+                                                        .doc(&heads.doc)
+                                                        .is_some_and(|doc| sync::heads(doc).fingerprint != heads.fingerprint);
+                                                    if behind && peer_policy.doc_allowed(&docs, &heads.doc) {
+                                                        let peer = source.unwrap_or(peer_id);
+                                                        start_doc_sync(&mut swarm, &docs, &mut sync_requests, peer, heads.doc);
+                                                    }
+                                                }
+                                                MessageKind::Join => {
+                                                    let announcement: JoinAnnouncement = match from_cbor(&message.body) {
+                                                        Ok(announcement) => announcement,
+                                                        Err(e) => {
+                                                            println!("Dropping malformed join announcement from peer {peer_id}: {e}");
+                                                            continue;
+                                                        }
+                                                    };
+                                                    if let Err(e) = announcement.verify() {
+                                                        println!("Dropping join announcement from peer {peer_id}: {e}");
+                                                        continue;
+                                                    }
+                                                    if source != Some(announcement.peer_id) {
+                                                        println!("Dropping join announcement for {} published by {source:?}", announcement.peer_id);
+                                                        continue;
+                                                    }
+                                                    let wanted = join_interest.as_ref().is_some_and(|wants| wants(&announcement));
+                                                    let JoinAnnouncement { peer_id: peer, name, ticket, .. } = announcement;
+                                                    let mut joined = false;
+                                                    if wanted && docs.doc(&ticket.doc).is_none() {
+                                                        match join_ticket(&mut swarm, &mut docs, &mut sync_requests, &mut dialing, &ticket, Some(peer)) {
+                                                            Ok(doc) => {
+                                                                println!("Joined {name}'s ticket document {doc}");
+                                                                walks.push(SyncWalk::new(doc, cli.sync_depth));
+                                                                give_up_if_stalled(&mut walks, &sync_requests, &dialing, &events, doc, "no peer to sync it from");
+                                                                joined = true;
+                                                            }
+                                                            Err(e) => println!("Failed to join ticket announced by {peer}: {e}"),
+                                                        }
+                                                    }
+                                                    let _ = events.send(NodeEvent::JoinAnnounced { peer, name, ticket, joined });
+                                                }
+                                                MessageKind::Defederation => {
+                                                    let notice: DefederationNotice = match from_cbor(&message.body) {
+                                                        Ok(notice) => notice,
+                                                        Err(e) => {
+                                                            println!("Dropping malformed defederation notice from peer {peer_id}: {e}");
+                                                            continue;
+                                                        }
+                                                    };
+                                                    if source != Some(notice.by) {
+                                                        println!("Dropping defederation notice by {} published by {source:?}", notice.by);
+                                                        continue;
+                                                    }
+                                                    let followed = peer_policy.follows(&notice);
+                                                    if followed {
+                                                        let cut_off: Vec<PeerId> = peer_policy.defederate(notice.peer, notice.subtree, Some(notice.by)).into_iter().collect();
+                                                        println!("Following {}'s defederation of {}: {}", notice.by, notice.peer, notice.reason);
+                                                        cut_off_peers(&mut swarm, &cut_off);
+                                                        let _ = events.send(NodeEvent::Defederated { peer: notice.peer, by: Some(notice.by), cut_off });
+                                                    }
+                                                    let _ = events.send(NodeEvent::DefederationAnnounced { notice, followed });
+                                                }
+                                                // Ignored above as unsupported
+                                                MessageKind::Unknown(_) => continue,
+                                            }
+                                            let _ = events.send(NodeEvent::Message { peer: peer_id, source, message });
+                                        } else if let gossipsub::Event::Subscribed { peer_id, topic: subscribed } = gossip_event {
+                                            // Announce again so a newly arrived peer learns about us
+                                            if subscribed == join_topic.hash() {
+                                                println!("Peer {peer_id} subscribed to join announcements");
+                                                publish_join_announcement(&mut swarm, &docs, &node_key, &cli.name, &join_topic);
+                                            }
                                         }
                                     }
                                     MyBehaviourEvent::Ping(event) => {
                                         println!("Ping event: {event:?}");
                                     }
+                                    MyBehaviourEvent::Rpc(event) => match event {
+                                        request_response::Event::Message { peer, message } => match message {
+                                            request_response::Message::Request { request, channel, .. } => {
+                                                println!("RPC request '{}' from peer {peer}", request.method);
+                                                let response = if request.method == rails::RAIL_INVITE_METHOD {
+                                                    let policy = rail_invite_policy.as_ref();
+                                                    let response = handle_rail_invite(
+                                                        &mut swarm,
+                                                        &mut docs,
+                                                        &mut sync_requests,
+                                                        &mut dialing,
+                                                        &mut peer_policy,
+                                                        policy,
+                                                        peer,
+                                                        &request.body,
+                                                        &events,
+                                                    );
+                                                    publish_doc_changes(&mut docs, &mut rail_index, &mut swarm.behaviour_mut().gossipsub, &heads_topic, &cli.name, &events);
+                                                    response
+                                                } else {
+                                                    rpc_handlers.handle(peer, &request)
+                                                };
+                                                if swarm.behaviour_mut().rpc.send_response(channel, response).is_err() {
+                                                    println!("Failed to send RPC response to {peer}");
+                                                }
+                                            }
+                                            request_response::Message::Response { request_id, response } => {
+                                                if let Some(reply) = pending_requests.remove(&request_id) {
+                                                    let _ = reply.send(match response {
+                                                        RpcResponse::Ok(body) => Ok(body),
+                                                        RpcResponse::Err(e) => Err(NodeError::Rpc(e)),
+                                                    });
+                                                } else if let Some((rail, entity)) = sent_invites.remove(&request_id) {
+                                                    match response {
+                                                        RpcResponse::Ok(body) => match from_cbor::<BackRefStatus>(&body) {
+                                                            Ok(status) => {
+                                                                println!("Peer {peer} answered the invitation to link {entity} to rail {rail}: {status:?}");
+                                                                answered_invites.insert((rail, entity));
+                                                                let _ = events.send(NodeEvent::RailInviteAnswered { peer, rail, entity, status });
+                                                            }
+                                                            Err(e) => println!("Malformed answer to a rail invitation from {peer}: {e}"),
+                                                        },
+                                                        RpcResponse::Err(e) => println!("Peer {peer} refused the invitation to link {entity} to rail {rail}: {e}"),
+                                                    }
+                                                }
+                                            }
+                                        },
+                                        request_response::Event::OutboundFailure { peer, request_id, error } => {
+                                            println!("RPC request to {peer} failed: {error}");
+                                            if let Some(reply) = pending_requests.remove(&request_id) {
+                                                let _ = reply.send(Err(match error {
+                                                    OutboundFailure::Timeout => NodeError::Timeout,
+                                                    error => NodeError::Rpc(error.to_string()),
+                                                }));
+                                            }
+                                            sent_invites.remove(&request_id);
+                                        }
+                                        request_response::Event::InboundFailure { peer, error, .. } => {
+                                            println!("RPC request from {peer} failed: {error}");
+                                        }
+                                        request_response::Event::ResponseSent { .. } => {}
+                                    },
+                                    MyBehaviourEvent::Blob(event) => match event {
+                                        request_response::Event::Message { peer, message } => match message {
+                                            request_response::Message::Request { request, channel, .. } => {
+                                                let response = match blob::serve(&store, &request) {
+                                                    Ok(response) => response,
+                                                    Err(e) => {
+                                                        println!("Failed to read blob for {peer}: {e}");
+                                                        BlobResponse::NotFound
+                                                    }
+                                                };
+                                                if swarm.behaviour_mut().blob.send_response(channel, response).is_err() {
+                                                    println!("Failed to send blob response to {peer}");
+                                                }
+                                            }
+                                            request_response::Message::Response { request_id, response } => {
+                                                let Some((peer, cid)) = blob_requests.remove(&request_id) else { continue };
+                                                let Some(download) = downloads.get_mut(&cid) else { continue };
+                                                match download.on_response(response) {
+                                                    Ok(progress) => {
+                                                        let _ = events.send(NodeEvent::BlobProgress { cid, progress });
+                                                        if let Some(request) = download.next_request() {
+                                                            let request_id = swarm.behaviour_mut().blob.send_request(&peer, request);
+                                                            blob_requests.insert(request_id, (peer, cid));
+                                                            continue;
+                                                        }
+                                                        finish_download(&store, &mut downloads, &mut blob_waiters, cid);
+                                                    }
+                                                    Err(e) => {
+                                                        println!("Blob transfer from {peer} failed: {e}");
+                                                        for waiter in blob_waiters.remove(&cid).unwrap_or_default() {
+                                                            let _ = waiter.send(Err(NodeError::Blob(e.clone())));
+                                                        }
+                                                    }
+                                                }
+                                            }
+                                        },
+                                        request_response::Event::OutboundFailure { peer, request_id, error } => {
+                                            println!("Blob request to {peer} failed: {error}");
+                                            if let Some((_, cid)) = blob_requests.remove(&request_id) {
+                                                for waiter in blob_waiters.remove(&cid).unwrap_or_default() {
+                                                    let _ = waiter.send(Err(match &error {
+                                                        OutboundFailure::Timeout => NodeError::Timeout,
+                                                        error => NodeError::Rpc(error.to_string()),
+                                                    }));
+                                                }
+                                            }
+                                        }
+                                        request_response::Event::InboundFailure { peer, error, .. } => {
+                                            println!("Blob request from {peer} failed: {error}");
+                                        }
+                                        request_response::Event::ResponseSent { .. } => {}
+                                    },
+                                    MyBehaviourEvent::DocSync(event) => match event {
+                                        request_response::Event::Message { peer, message } => match message {
+                                            request_response::Message::Request { request, channel, .. } => {
+                                                let doc = request.doc;
+                                                // Peers we cut off are told we don't hold the document
+                                                let shared = !docs.is_private(&doc) && !peer_policy.is_cut_off(&peer) && peer_policy.doc_allowed(&docs, &doc);
+                                                let response = match docs.doc(&doc) {
+                                                    Some(replica) if shared => {
+                                                        let (response, received) = sync::answer(replica, request, now_millis());
+                                                        apply_remote_entries(&mut docs, &mut peer_policy, peer, doc, received, &events);
+                                                        response
+                                                    }
+                                                    _ => SyncResponse::UnknownDoc,
+                                                };
+                                                if swarm.behaviour_mut().doc_sync.send_response(channel, response).is_err() {
+                                                    println!("Failed to send doc sync response to {peer}");
+                                                }
+                                                publish_doc_changes(&mut docs, &mut rail_index, &mut swarm.behaviour_mut().gossipsub, &heads_topic, &cli.name, &events);
+                                            }
+                                            request_response::Message::Response { request_id, response } => {
+                                                if let Some((doc, key)) = key_fetches.remove(&request_id) {
+                                                    if let (SyncResponse::Ranges(ranges), Some(replica)) = (response, docs.doc(&doc)) {
+                                                        let step = sync::respond(replica, ranges);
+                                                        apply_remote_entries(&mut docs, &mut peer_policy, peer, doc, step.received, &events);
+                                                        publish_doc_changes(&mut docs, &mut rail_index, &mut swarm.behaviour_mut().gossipsub, &heads_topic, &cli.name, &events);
+                                                    }
+                                                    finish_key_fetch(&docs, &mut key_waiters, doc, key);
+                                                    continue;
+                                                }
+                                                let Some((peer, doc)) = sync_requests.remove(&request_id) else { continue };
+                                                let (hot, ranges) = match response {
+                                                    SyncResponse::Ranges(ranges) => (Vec::new(), ranges),
+                                                    SyncResponse::Prioritised { hot, ranges } => (hot, ranges),
+                                                    SyncResponse::UnknownDoc => {
+                                                        let reason = format!("{peer} doesn't have it");
+                                                        give_up_if_stalled(&mut walks, &sync_requests, &dialing, &events, doc, &reason);
+                                                        continue;
+                                                    }
+                                                };
+                                                // The peer's most-read entries land before the rest of the sync
+                                                if !hot.is_empty() {
+                                                    apply_remote_entries(&mut docs, &mut peer_policy, peer, doc, hot, &events);
+                                                }
+                                                let Some(replica) = docs.doc(&doc) else { continue };
+                                                let step = sync::respond(replica, ranges);
+                                                apply_remote_entries(&mut docs, &mut peer_policy, peer, doc, step.received, &events);
+                                                publish_doc_changes(&mut docs, &mut rail_index, &mut swarm.behaviour_mut().gossipsub, &heads_topic, &cli.name, &events);
+                                                if step.replies.is_empty() {
+                                                    println!("Document {doc} is in sync with {peer}");
+                                                    synced_docs.insert(doc);
+                                                    for waiter in relationship_waiters.remove(&doc).unwrap_or_default() {
+                                                        let _ = waiter.send(());
+                                                    }
+                                                    let _ = events.send(NodeEvent::DocSynced { peer, doc });
+                                                    let mut follow = Vec::new();
+                                                    if let Some(replica) = docs.doc(&doc) {
+                                                        for walk in walks.iter_mut() {
+                                                            if let Some(tickets) = walk.synced(replica) {
+                                                                follow.extend(tickets);
+                                                                let _ = events.send(NodeEvent::DocSyncProgress { doc, progress: walk.progress(&doc), error: None });
+                                                            }
+                                                        }
+                                                    }
+                                                    walks.retain(|walk| !walk.is_complete());
+                                                    for ticket in follow {
+                                                        let reason = match join_ticket(&mut swarm, &mut docs, &mut sync_requests, &mut dialing, &ticket, Some(peer)) {
+                                                            Ok(_) => "no peer to sync it from".to_string(),
+                                                            Err(e) => {
+                                                                println!("Failed to follow ticket for {}: {e}", ticket.doc);
+                                                                e.to_string()
+                                                            }
+                                                        };
+                                                        give_up_if_stalled(&mut walks, &sync_requests, &dialing, &events, ticket.doc, &reason);
+                                                    }
+                                                    continue;
+                                                }
+                                                let request = SyncRequest { doc, ranges: step.replies, hot: 0 };
+                                                let request_id = swarm.behaviour_mut().doc_sync.send_request(&peer, request);
+                                                sync_requests.insert(request_id, (peer, doc));
+                                            }
+                                        },
+                                        request_response::Event::OutboundFailure { peer, request_id, error } => {
+                                            println!("Doc sync with {peer} failed: {error}");
+                                            if let Some((_, doc)) = sync_requests.remove(&request_id) {
+                                                let reason = format!("sync with {peer} failed: {error}");
+                                                give_up_if_stalled(&mut walks, &sync_requests, &dialing, &events, doc, &reason);
+                                            }
+                                            if let Some((doc, key)) = key_fetches.remove(&request_id) {
+                                                finish_key_fetch(&docs, &mut key_waiters, doc, key);
+                                            }
+                                        }
+                                        request_response::Event::InboundFailure { peer, error, .. } => {
+                                            println!("Doc sync request from {peer} failed: {error}");
+                                        }
+                                        request_response::Event::ResponseSent { .. } => {}
+                                    },
                                 }
                             }
                             _ => {}
@@ -359,11 +2070,10 @@ pub mod node {
                     println!("Searching for peers in DHT...");
                     // Get addresses of all known peers from the DHT
                     let peers: Vec<_> = swarm.behaviour_mut().kademlia.kbuckets()
-                        .into_iter()
                         .flat_map(|bucket| {
                             bucket.iter()
                                 .filter(|entry| entry.node.key.preimage() != &local_peer_id)
-                                .map(|entry| entry.node.key.preimage().clone())
+                                .map(|entry| *entry.node.key.preimage())
                                 .collect::<Vec<_>>()
                         })
                         .collect();
@@ -374,14 +2084,288 @@ pub mod node {
                         swarm.behaviour_mut().kademlia.get_record(key);
                     }
                 }
-                _ = broadcast_interval.tick() => {
-                    let message = format!("HELO FROM {}", cli.name);
-                    if let Err(e) = swarm
-                        .behaviour_mut()
-                        .gossipsub
-                        .publish(topic.clone(), message.as_bytes())
-                    {
-                        println!("Failed to publish message: {e}");
+                _ = presence_interval.tick() => {
+                    for entry in roster.expire(Instant::now()) {
+                        let (peer, name) = (entry.presence.peer_id, entry.presence.name);
+                        println!("Peer {name} ({peer}) left");
+                        let _ = events.send(NodeEvent::PeerLeft { peer, name });
+                    }
+
+                    let presence = Presence::local(&cli.name, local_peer_id, started, CAPABILITIES);
+                    let message = to_cbor(&presence)
+                        .map(|body| RaggyMessage::new(MessageKind::Presence, &cli.name, CONTENT_TYPE_CBOR, body))
+                        .and_then(|message| message.encode());
+                    match message {
+                        Ok(bytes) => {
+                            if let Err(e) = swarm.behaviour_mut().gossipsub.publish(presence_topic.clone(), bytes) {
+                                println!("Failed to publish presence: {e}");
+                            }
+                        }
+                        Err(e) => println!("Failed to encode presence: {e}"),
+                    }
+                }
+                _ = score_interval.tick() => {
+                    let graylist_threshold = peer_score_thresholds().graylist_threshold;
+                    let gossipsub = &swarm.behaviour().gossipsub;
+                    let graylisted: Vec<(PeerId, f64)> = gossipsub
+                        .all_peers()
+                        .filter_map(|(peer, _)| gossipsub.peer_score(peer).map(|score| (*peer, score)))
+                        .filter(|(_, score)| *score < graylist_threshold)
+                        .collect();
+
+                    for (peer, score) in graylisted {
+                        println!("Disconnecting peer {peer} with score {score:.2}");
+                        swarm.behaviour_mut().gossipsub.remove_explicit_peer(&peer);
+                        let _ = swarm.disconnect_peer_id(peer);
+                        let _ = events.send(NodeEvent::PeerScoreDisconnect { peer, score });
+                    }
+                }
+                _ = rail_reconcile_interval.tick() => {
+                    refresh_policy(&mut peer_policy, &docs, &rail_index, &rail_kinds);
+                    let links = rails::half_linked(&docs);
+                    if !links.is_empty() {
+                        println!("Found {} half-linked rail participants", links.len());
+                        let unanswered: Vec<HalfLink> = links
+                            .iter()
+                            .filter(|link| !answered_invites.contains(&(link.rail, link.entity)))
+                            .cloned()
+                            .collect();
+                        send_rail_invites(&mut swarm, &docs, &mut sent_invites, &unanswered);
+                        let _ = events.send(NodeEvent::HalfLinkedRails(links));
+                    }
+                }
+                Some(command) = command_rx.recv() => {
+                    match command {
+                        Command::PeerScores { reply } => {
+                            let gossipsub = &swarm.behaviour().gossipsub;
+                            let scores = gossipsub
+                                .all_peers()
+                                .filter_map(|(peer, _)| gossipsub.peer_score(peer).map(|score| (*peer, score)))
+                                .collect();
+                            let _ = reply.send(scores);
+                        }
+                        Command::Roster { reply } => {
+                            let _ = reply.send(roster.entries());
+                        }
+                        Command::PublishChat { text, reply } => {
+                            let result = RaggyMessage::chat(&cli.name, &text)
+                                .encode()
+                                .map_err(|e| NodeError::Gossip(e.to_string()))
+                                .and_then(|bytes| {
+                                    let published = swarm.behaviour_mut().gossipsub.publish(topic.clone(), bytes);
+                                    published.map(|_| ()).map_err(|e| NodeError::Gossip(e.to_string()))
+                                });
+                            let _ = reply.send(result);
+                        }
+                        Command::Request { peer, request, reply } => {
+                            let request_id = swarm.behaviour_mut().rpc.send_request(&peer, request);
+                            pending_requests.insert(request_id, reply);
+                        }
+                        Command::RegisterHandler { method, handler } => {
+                            rpc_handlers.register(&method, handler);
+                        }
+                        Command::PutBlob { data, reply } => {
+                            let result = store.put(&data).map_err(|e| NodeError::Store(e.to_string()));
+                            if let Ok(cid) = &result {
+                                if let Err(e) = swarm.behaviour_mut().kademlia.start_providing(RecordKey::new(&cid.to_bytes())) {
+                                    println!("Failed to announce blob {cid}: {e}");
+                                }
+                            }
+                            let _ = reply.send(result);
+                        }
+                        Command::FetchBlob { peer, cid, reply } => {
+                            if let Ok(Some(data)) = store.get(&cid) {
+                                let _ = reply.send(Ok(data));
+                                continue;
+                            }
+                            let waiters = blob_waiters.entry(cid).or_default();
+                            waiters.push(reply);
+                            // Only one request per blob is in flight; later callers share its result
+                            if waiters.len() > 1 {
+                                continue;
+                            }
+                            // Pick up what an earlier attempt, or run, left on disk
+                            let download = match downloads.entry(cid) {
+                                hash_map::Entry::Occupied(entry) => entry.into_mut(),
+                                hash_map::Entry::Vacant(slot) => match Download::open(store.downloads_dir(), cid.digest()) {
+                                    Ok(download) => slot.insert(download),
+                                    Err(e) => {
+                                        for waiter in blob_waiters.remove(&cid).unwrap_or_default() {
+                                            let _ = waiter.send(Err(NodeError::Blob(e.clone())));
+                                        }
+                                        continue;
+                                    }
+                                },
+                            };
+                            match download.next_request() {
+                                Some(request) => {
+                                    let request_id = swarm.behaviour_mut().blob.send_request(&peer, request);
+                                    blob_requests.insert(request_id, (peer, cid));
+                                }
+                                // Every chunk arrived before, but the blob never made it into the store
+                                None => finish_download(&store, &mut downloads, &mut blob_waiters, cid),
+                            }
+                        }
+                        Command::WithStore(f) => f(&store),
+                        Command::WithRailIndex(f) => f(&rail_index),
+                        Command::ShareDoc { doc, mode, reply } => {
+                            let peers = vec![TicketPeer { peer: local_peer_id, addrs: swarm.listeners().cloned().collect() }];
+                            let _ = reply.send(docs.share(&doc, mode, peers).map_err(|e| NodeError::Doc(e.to_string())));
+                        }
+                        Command::JoinDoc { ticket, reply } => {
+                            let result = join_ticket(&mut swarm, &mut docs, &mut sync_requests, &mut dialing, &ticket, None);
+                            if let Ok(doc) = result {
+                                if !walks.iter().any(|walk| walk.root() == doc) {
+                                    walks.push(SyncWalk::new(doc, cli.sync_depth));
+                                    give_up_if_stalled(&mut walks, &sync_requests, &dialing, &events, doc, "no peer to sync it from");
+                                }
+                            }
+                            let _ = reply.send(result.map_err(|e| NodeError::Doc(e.to_string())));
+                        }
+                        Command::DocGet { doc, key, reply } => {
+                            if let Err(e) = docs.record_access(&doc, &key) {
+                                let _ = reply.send(Err(NodeError::Doc(e.to_string())));
+                                continue;
+                            }
+                            let held = docs.doc(&doc).is_some_and(|replica| replica.entry(&key).is_some());
+                            let peers: Vec<PeerId> = swarm.connected_peers().copied().collect();
+                            if held || synced_docs.contains(&doc) || peers.is_empty() || docs.is_private(&doc) {
+                                let _ = reply.send(docs.get(&doc, &key).map_err(|e| NodeError::Doc(e.to_string())));
+                                continue;
+                            }
+                            // Fetch just this key rather than waiting for the whole document
+                            let (pending, waiters) = key_waiters.entry((doc, key.clone())).or_default();
+                            waiters.push(reply);
+                            if waiters.len() > 1 {
+                                continue;
+                            }
+                            let Some(replica) = docs.doc(&doc) else { continue };
+                            for peer in peers {
+                                let request = SyncRequest { doc, ranges: sync::fetch(replica, &key), hot: 0 };
+                                let request_id = swarm.behaviour_mut().doc_sync.send_request(&peer, request);
+                                key_fetches.insert(request_id, (doc, key.clone()));
+                                *pending += 1;
+                            }
+                        }
+                        Command::RegisterRailKind { kind, spec } => {
+                            rail_kinds.register(kind, spec);
+                        }
+                        Command::RailKinds { reply } => {
+                            let _ = reply.send(rail_kinds.clone());
+                        }
+                        Command::CreateRail { kind, entities, metadata, reply } => {
+                            let result = rail_kinds
+                                .check(&kind, &metadata)
+                                .and_then(|_| rails::create_rail(&mut docs, &kind, &entities, metadata));
+                            if let Ok(rail) = &result {
+                                let links: Vec<HalfLink> =
+                                    rails::half_linked(&docs).into_iter().filter(|link| link.rail == *rail).collect();
+                                send_rail_invites(&mut swarm, &docs, &mut sent_invites, &links);
+                            }
+                            let _ = reply.send(result.map_err(|e| NodeError::Doc(e.to_string())));
+                            publish_doc_changes(&mut docs, &mut rail_index, &mut swarm.behaviour_mut().gossipsub, &heads_topic, &cli.name, &events);
+                        }
+                        Command::SetPolicy(config) => {
+                            peer_policy.set_config(config);
+                            refresh_policy(&mut peer_policy, &docs, &rail_index, &rail_kinds);
+                        }
+                        Command::Policy { reply } => {
+                            let _ = reply.send(peer_policy.clone());
+                        }
+                        Command::SetAccess { list, subject, listed, reply } => {
+                            let mut access = peer_policy.access_lists().clone();
+                            let result = access.set(&mut docs, list, subject, listed).map_err(|e| NodeError::Doc(e.to_string()));
+                            peer_policy.set_access_lists(access);
+                            apply_access_lists(&mut swarm, &peer_policy, Some(&subject));
+                            publish_doc_changes(&mut docs, &mut rail_index, &mut swarm.behaviour_mut().gossipsub, &heads_topic, &cli.name, &events);
+                            let _ = reply.send(result);
+                        }
+                        Command::SetExclusive { scope, exclusive, reply } => {
+                            let mut access = peer_policy.access_lists().clone();
+                            let result = access.set_exclusive(&mut docs, scope, exclusive).map_err(|e| NodeError::Doc(e.to_string()));
+                            peer_policy.set_access_lists(access);
+                            apply_access_lists(&mut swarm, &peer_policy, None);
+                            publish_doc_changes(&mut docs, &mut rail_index, &mut swarm.behaviour_mut().gossipsub, &heads_topic, &cli.name, &events);
+                            let _ = reply.send(result);
+                        }
+                        Command::Defederate { peer, subtree, reason, reply } => {
+                            // Cut off who the rails we hold say right now
+                            refresh_policy(&mut peer_policy, &docs, &rail_index, &rail_kinds);
+                            let cut_off: Vec<PeerId> = peer_policy.defederate(peer, subtree, None).into_iter().collect();
+                            cut_off_peers(&mut swarm, &cut_off);
+                            let notice = DefederationNotice { by: local_peer_id, peer, subtree, reason };
+                            let message = to_cbor(&notice)
+                                .map(|body| RaggyMessage::new(MessageKind::Defederation, &cli.name, CONTENT_TYPE_CBOR, body))
+                                .and_then(|message| message.encode());
+                            match message {
+                                Ok(bytes) => {
+                                    if let Err(e) = swarm.behaviour_mut().gossipsub.publish(defederation_topic.clone(), bytes) {
+                                        println!("Failed to publish defederation of {peer}: {e}");
+                                    }
+                                }
+                                Err(e) => println!("Failed to encode defederation notice: {e}"),
+                            }
+                            let _ = events.send(NodeEvent::Defederated { peer, by: None, cut_off: cut_off.clone() });
+                            let _ = reply.send(cut_off);
+                        }
+                        Command::RestorePeer { peer, reply } => {
+                            let restored: Vec<PeerId> = peer_policy.restore(&peer).into_iter().collect();
+                            for peer in &restored {
+                                add_gossip_peer(&mut swarm, &peer_policy, peer);
+                            }
+                            let _ = reply.send(restored);
+                        }
+                        Command::SetRailInvitePolicy(policy) => {
+                            rail_invite_policy = policy;
+                        }
+                        Command::TrustGraph { reply } => {
+                            let _ = reply.send(TrustGraph::from_index(&rail_index, &rail_kinds));
+                        }
+                        Command::TrustView { viewer, measure, reply } => {
+                            let graph = TrustGraph::from_index(&rail_index, &rail_kinds);
+                            let _ = reply.send(TrustView::new(&graph, &viewer, &entity::owners(&docs), &measure));
+                        }
+                        Command::AnswerRailInvite { rail, entity, accept, reply } => {
+                            // The ticket lists the peer that invited us, so the rail syncs from them
+                            let result = match rails::answer_invite(&mut docs, &rail, &entity, accept) {
+                                Ok(invite) if accept => {
+                                    join_ticket(&mut swarm, &mut docs, &mut sync_requests, &mut dialing, &invite.ticket, None).map(|_| ())
+                                }
+                                Ok(_) => Ok(()),
+                                Err(e) => Err(e),
+                            };
+                            let _ = reply.send(result.map_err(|e| NodeError::Doc(e.to_string())));
+                            publish_doc_changes(&mut docs, &mut rail_index, &mut swarm.behaviour_mut().gossipsub, &heads_topic, &cli.name, &events);
+                        }
+                        Command::SetJoinInterest(filter) => {
+                            join_interest = filter;
+                        }
+                        Command::ResolveRelationships { entity, reply } => {
+                            let doc = match relationship::open(&mut docs, &entity) {
+                                Ok(doc) => doc,
+                                Err(e) => {
+                                    println!("Failed to open relationships of {entity}: {e}");
+                                    continue;
+                                }
+                            };
+                            let key = RecordKey::new(&relationship::provider_key(&entity));
+                            if let Err(e) = swarm.behaviour_mut().kademlia.start_providing(key.clone()) {
+                                println!("Failed to announce relationships of {entity}: {e}");
+                            }
+                            let connected: Vec<PeerId> = swarm.connected_peers().copied().collect();
+                            for peer in connected.into_iter().filter(|_| peer_policy.doc_allowed(&docs, &doc)) {
+                                start_doc_sync(&mut swarm, &docs, &mut sync_requests, peer, doc);
+                            }
+                            if let Some(reply) = reply {
+                                relationship_waiters.entry(doc).or_default().push(reply);
+                            }
+                            let query = swarm.behaviour_mut().kademlia.get_providers(key);
+                            relationship_lookups.insert(query, doc);
+                        }
+                        Command::WithDocs(f) => {
+                            f(&mut docs);
+                            publish_doc_changes(&mut docs, &mut rail_index, &mut swarm.behaviour_mut().gossipsub, &heads_topic, &cli.name, &events);
+                        }
                     }
                 }
             }
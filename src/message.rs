@@ -0,0 +1,108 @@
+// Typed, versioned envelope for everything we publish over gossipsub
+use crate::codec::{from_cbor, to_cbor, CodecError};
+use serde::{Deserialize, Serialize};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Envelope schema version written by this build
+pub const MESSAGE_VERSION: u16 = 1;
+
+pub const CONTENT_TYPE_TEXT: &str = "text/plain; charset=utf-8";
+pub const CONTENT_TYPE_CBOR: &str = "application/cbor";
+
+/// What a message carries. Kinds this build doesn't know about decode as
+/// `Unknown` so that newer peers can keep sharing the swarm with older ones.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(from = "String", into = "String")]
+pub enum MessageKind {
+    Chat,
+    Presence,
+    DocSync,
+    Join,
+    Defederation,
+    Unknown(String),
+}
+
+impl From<String> for MessageKind {
+    fn from(kind: String) -> Self {
+        match kind.as_str() {
+            "chat" => MessageKind::Chat,
+            "presence" => MessageKind::Presence,
+            "doc-sync" => MessageKind::DocSync,
+            "join" => MessageKind::Join,
+            "defederation" => MessageKind::Defederation,
+            _ => MessageKind::Unknown(kind),
+        }
+    }
+}
+
+impl From<MessageKind> for String {
+    fn from(kind: MessageKind) -> Self {
+        match kind {
+            MessageKind::Chat => "chat".to_string(),
+            MessageKind::Presence => "presence".to_string(),
+            MessageKind::DocSync => "doc-sync".to_string(),
+            MessageKind::Join => "join".to_string(),
+            MessageKind::Defederation => "defederation".to_string(),
+            MessageKind::Unknown(kind) => kind,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RaggyMessage {
+    pub kind: MessageKind,
+    pub version: u16,
+    /// Human readable name of the sending node
+    pub sender: String,
+    /// Milliseconds since the Unix epoch
+    pub created_at: u64,
+    pub content_type: String,
+    #[serde(with = "serde_bytes")]
+    pub body: Vec<u8>,
+}
+
+impl RaggyMessage {
+    pub fn new(kind: MessageKind, sender: &str, content_type: &str, body: Vec<u8>) -> Self {
+        RaggyMessage {
+            kind,
+            version: MESSAGE_VERSION,
+            sender: sender.to_string(),
+            created_at: now_millis(),
+            content_type: content_type.to_string(),
+            body,
+        }
+    }
+
+    pub fn chat(sender: &str, text: &str) -> Self {
+        RaggyMessage::new(MessageKind::Chat, sender, CONTENT_TYPE_TEXT, text.as_bytes().to_vec())
+    }
+
+    /// Body as text, if the message carries text
+    pub fn text(&self) -> Option<&str> {
+        if self.content_type.starts_with("text/") {
+            std::str::from_utf8(&self.body).ok()
+        } else {
+            None
+        }
+    }
+
+    /// Whether this build understands the envelope's kind and version
+    pub fn is_supported(&self) -> bool {
+        !matches!(self.kind, MessageKind::Unknown(_)) && self.version <= MESSAGE_VERSION
+    }
+
+    pub fn encode(&self) -> Result<Vec<u8>, CodecError> {
+        to_cbor(self)
+    }
+
+    pub fn decode(bytes: &[u8]) -> Result<Self, CodecError> {
+        from_cbor(bytes)
+    }
+}
+
+pub fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or_default()
+}
@@ -0,0 +1,309 @@
+// Sybil resistance: which peers' writes, rails and gossip a node accepts,
+// and defederation of peers that misbehave
+use crate::access_list::{AccessLists, List, Subject};
+use crate::docs::{AuthorId, DocId, Docs};
+use crate::entity::EntityId;
+use crate::graph::TrustGraph;
+use crate::trust::{TrustMeasure, TrustView};
+use libp2p::PeerId;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet, HashMap, VecDeque};
+use std::fmt;
+use std::time::{Duration, Instant};
+
+pub const DEFEDERATION_TOPIC: &str = "raggy-defederation";
+
+/// What a peer is trying to get accepted
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Action {
+    /// Entries it wrote, in documents we sync
+    Write,
+    /// A rail it created, such as an invitation to back-reference one
+    Rail,
+    /// A gossip message it published
+    Gossip,
+}
+
+/// What a peer must have earned before one kind of action is accepted. The
+/// default asks for nothing.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Rule {
+    /// Lowest trust score accepted. Only applies once the policy has a viewer.
+    pub min_trust: Option<f64>,
+    /// How long we must have known of the peer
+    pub min_age: Duration,
+}
+
+/// At most `max` events per peer in any `window`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RateLimit {
+    pub max: usize,
+    pub window: Duration,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct PolicyConfig {
+    /// The entity whose trust in others scores peers, usually our own. With
+    /// none, trust rules are skipped.
+    pub viewer: Option<EntityId>,
+    pub measure: TrustMeasure,
+    pub writes: Rule,
+    pub rails: Rule,
+    pub gossip: Rule,
+    /// How fast a peer may create rails we accept
+    pub rail_rate: Option<RateLimit>,
+    /// Follow defederation notices from peers we trust at least this much.
+    /// With none, notices are only reported.
+    pub follow_notices: Option<f64>,
+}
+
+impl PolicyConfig {
+    fn rule(&self, action: Action) -> &Rule {
+        match action {
+            Action::Write => &self.writes,
+            Action::Rail => &self.rails,
+            Action::Gossip => &self.gossip,
+        }
+    }
+}
+
+/// Why an action was refused
+#[derive(Debug, Clone, PartialEq)]
+pub enum Rejection {
+    /// The peer is on our deny list
+    Blocked,
+    /// Only peers on the allow list are accepted, and it isn't
+    NotAllowed,
+    /// Cut off by a defederation, ours or one we followed
+    Defederated(Box<Defederation>),
+    Untrusted { score: f64, required: f64 },
+    TooNew { age: Duration, required: Duration },
+    RailRate(RateLimit),
+}
+
+impl fmt::Display for Rejection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Rejection::Blocked => write!(f, "peer is blocked"),
+            Rejection::NotAllowed => write!(f, "peer is not on the allow list"),
+            Rejection::Defederated(defederation) => match defederation.by {
+                Some(by) => write!(f, "defederated with {} by {by}", defederation.root),
+                None => write!(f, "defederated with {}", defederation.root),
+            },
+            Rejection::Untrusted { score, required } => write!(f, "trust {score} is below {required}"),
+            Rejection::TooNew { age, required } => write!(f, "known for {age:?} of the {required:?} required"),
+            Rejection::RailRate(limit) => write!(f, "more than {} rails in {:?}", limit.max, limit.window),
+        }
+    }
+}
+
+impl std::error::Error for Rejection {}
+
+impl Rejection {
+    /// Whether the peer is refused outright, rather than only until it has
+    /// earned enough trust or age, or its rate limit frees up
+    pub fn cuts_off(&self) -> bool {
+        matches!(self, Rejection::Blocked | Rejection::NotAllowed | Rejection::Defederated(_))
+    }
+}
+
+/// Published on the defederation topic so peers that trust us can follow
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DefederationNotice {
+    /// Who defederated. Must be the peer that signed the gossip message.
+    pub by: PeerId,
+    pub peer: PeerId,
+    /// Whether everyone `peer` vouches for is cut off too
+    pub subtree: bool,
+    pub reason: String,
+}
+
+/// Why a peer is no longer synced with or relayed for
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Defederation {
+    /// The peer whose defederation cut this one off, itself if it was named
+    pub root: PeerId,
+    /// Who decided, `None` if we did
+    pub by: Option<PeerId>,
+}
+
+/// Decides what each peer may do from its trust score, how long we've known
+/// of it, how fast it creates rails, the defederations in force and the
+/// node's allow and deny lists. Ages count from when this node first heard
+/// of a peer since it started.
+#[derive(Debug, Clone)]
+pub struct PolicyEngine {
+    local: PeerId,
+    config: PolicyConfig,
+    trust: Option<TrustView>,
+    graph: TrustGraph,
+    owners: BTreeMap<EntityId, AuthorId>,
+    access: AccessLists,
+    defederated: HashMap<PeerId, Defederation>,
+    first_seen: HashMap<PeerId, Instant>,
+    rails: HashMap<PeerId, VecDeque<Instant>>,
+}
+
+impl PolicyEngine {
+    /// A policy for the node `local`, which it always accepts
+    pub fn new(local: PeerId, config: PolicyConfig) -> Self {
+        PolicyEngine {
+            local,
+            config,
+            trust: None,
+            graph: TrustGraph::default(),
+            owners: BTreeMap::new(),
+            access: AccessLists::default(),
+            defederated: HashMap::new(),
+            first_seen: HashMap::new(),
+            rails: HashMap::new(),
+        }
+    }
+
+    pub fn config(&self) -> &PolicyConfig {
+        &self.config
+    }
+
+    /// Replace the configuration. Call `refresh` after changing the viewer.
+    pub fn set_config(&mut self, config: PolicyConfig) {
+        self.config = config;
+    }
+
+    /// Score peers against the current trust graph, and `owners` of the
+    /// entities in it
+    pub fn refresh(&mut self, graph: TrustGraph, owners: BTreeMap<EntityId, AuthorId>) {
+        self.trust = self
+            .config
+            .viewer
+            .map(|viewer| TrustView::new(&graph, &viewer, &owners, &self.config.measure));
+        self.graph = graph;
+        self.owners = owners;
+    }
+
+    /// Apply `access` on top of the rules
+    pub fn set_access_lists(&mut self, access: AccessLists) {
+        self.access = access;
+    }
+
+    pub fn access_lists(&self) -> &AccessLists {
+        &self.access
+    }
+
+    /// Whether we may sync the document `id`, as the access lists say
+    pub fn doc_allowed(&self, docs: &Docs, id: &DocId) -> bool {
+        self.access.doc_allowed(docs, id)
+    }
+
+    /// The viewer's trust in `peer`, `None` without a viewer
+    pub fn trust(&self, peer: &PeerId) -> Option<f64> {
+        let view = self.trust.as_ref()?;
+        Some(AuthorId::from_peer(peer).map(|author| view.score(&author)).unwrap_or_default())
+    }
+
+    /// Whether `peer` may do `action` at `now`. The first question about a
+    /// peer starts its age, and an accepted rail counts towards its rate.
+    /// Peers on the allow list skip the rules.
+    pub fn decide(&mut self, peer: &PeerId, action: Action, now: Instant) -> Result<(), Rejection> {
+        if *peer == self.local {
+            return Ok(());
+        }
+        if let Some(reason) = self.cut_off(peer) {
+            return Err(reason);
+        }
+        if self.access.peer_trusted(peer) {
+            return Ok(());
+        }
+        let first_seen = *self.first_seen.entry(*peer).or_insert(now);
+        let rule = self.config.rule(action);
+        if let Some(required) = rule.min_trust {
+            if let Some(score) = self.trust(peer).filter(|score| *score < required) {
+                return Err(Rejection::Untrusted { score, required });
+            }
+        }
+        let age = now.saturating_duration_since(first_seen);
+        if age < rule.min_age {
+            return Err(Rejection::TooNew { age, required: rule.min_age });
+        }
+        if let (Action::Rail, Some(limit)) = (action, self.config.rail_rate) {
+            let times = self.rails.entry(*peer).or_default();
+            while times.front().is_some_and(|time| now.saturating_duration_since(*time) >= limit.window) {
+                times.pop_front();
+            }
+            if times.len() >= limit.max {
+                return Err(Rejection::RailRate(limit));
+            }
+            times.push_back(now);
+        }
+        Ok(())
+    }
+
+    /// Why we refuse to sync with or relay for `peer` at all, if we do
+    pub fn cut_off(&self, peer: &PeerId) -> Option<Rejection> {
+        if *peer == self.local {
+            return None;
+        }
+        if self.access.subjects(List::Deny).contains(&Subject::Peer(*peer)) {
+            return Some(Rejection::Blocked);
+        }
+        if !self.access.peer_allowed(peer) {
+            return Some(Rejection::NotAllowed);
+        }
+        let defederation = self.defederated.get(peer)?;
+        Some(Rejection::Defederated(Box::new(*defederation)))
+    }
+
+    pub fn is_cut_off(&self, peer: &PeerId) -> bool {
+        self.cut_off(peer).is_some()
+    }
+
+    /// Cut off `peer`, and with `subtree` every peer owning an entity its
+    /// entities reach in the trust graph. `by` is who decided, `None` for
+    /// us. Neither we nor `by` are ever cut off. Returns the peers newly
+    /// cut off.
+    pub fn defederate(&mut self, peer: PeerId, subtree: bool, by: Option<PeerId>) -> BTreeSet<PeerId> {
+        let mut peers = BTreeSet::from([peer]);
+        if subtree {
+            peers.extend(self.subtree(&peer));
+        }
+        peers.retain(|cut| *cut != self.local && Some(*cut) != by && !self.defederated.contains_key(cut));
+        for cut in &peers {
+            self.defederated.insert(*cut, Defederation { root: peer, by });
+        }
+        peers
+    }
+
+    /// Whether to follow a notice `notice.by` published, as `follow_notices` says
+    pub fn follows(&self, notice: &DefederationNotice) -> bool {
+        let Some(required) = self.config.follow_notices else { return false };
+        notice.peer != self.local && self.trust(&notice.by).is_some_and(|score| score >= required)
+    }
+
+    /// Lift the defederation of `peer` and of everyone cut off with it.
+    /// Returns the peers restored.
+    pub fn restore(&mut self, peer: &PeerId) -> BTreeSet<PeerId> {
+        let restored: BTreeSet<PeerId> = self
+            .defederated
+            .iter()
+            .filter(|(cut, defederated)| *cut == peer || defederated.root == *peer)
+            .map(|(cut, _)| *cut)
+            .collect();
+        self.defederated.retain(|cut, _| !restored.contains(cut));
+        restored
+    }
+
+    /// Every peer cut off by a defederation
+    pub fn defederated(&self) -> BTreeSet<PeerId> {
+        self.defederated.keys().copied().collect()
+    }
+
+    /// Peers owning an entity reachable from one `peer` owns
+    fn subtree(&self, peer: &PeerId) -> BTreeSet<PeerId> {
+        let Some(author) = AuthorId::from_peer(peer) else { return BTreeSet::new() };
+        self.owners
+            .iter()
+            .filter(|(_, owner)| **owner == author)
+            .flat_map(|(entity, _)| self.graph.neighbourhood(entity, usize::MAX).into_keys())
+            .filter_map(|entity| self.owners.get(&entity)?.peer_id())
+            .collect()
+    }
+}
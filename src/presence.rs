@@ -0,0 +1,121 @@
+// Presence announcements and the live roster built from them
+use libp2p::PeerId;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+
+pub const PRESENCE_TOPIC: &str = "raggy-presence";
+pub const PRESENCE_INTERVAL: Duration = Duration::from_secs(10);
+/// Heartbeats a peer may miss before it is dropped from the roster
+pub const MISSED_HEARTBEATS: u32 = 3;
+
+/// What each node announces about itself on the presence topic
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Presence {
+    pub name: String,
+    pub peer_id: PeerId,
+    pub version: String,
+    pub capabilities: Vec<String>,
+    pub uptime_secs: u64,
+    /// One minute load average, or 0 where it isn't available
+    pub load: f32,
+}
+
+impl Presence {
+    pub fn local(name: &str, peer_id: PeerId, started: Instant, capabilities: &[&str]) -> Self {
+        Presence {
+            name: name.to_string(),
+            peer_id,
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            capabilities: capabilities.iter().map(|c| c.to_string()).collect(),
+            uptime_secs: started.elapsed().as_secs(),
+            load: load_average(),
+        }
+    }
+}
+
+fn load_average() -> f32 {
+    std::fs::read_to_string("/proc/loadavg")
+        .ok()
+        .and_then(|loadavg| loadavg.split_whitespace().next().and_then(|load| load.parse().ok()))
+        .unwrap_or_default()
+}
+
+#[derive(Debug, Clone)]
+pub struct RosterEntry {
+    pub presence: Presence,
+    pub first_seen: Instant,
+    pub last_seen: Instant,
+}
+
+/// Live view of the peers that are currently announcing themselves
+#[derive(Debug)]
+pub struct Roster {
+    entries: HashMap<PeerId, RosterEntry>,
+    timeout: Duration,
+}
+
+impl Roster {
+    pub fn new(interval: Duration, missed_heartbeats: u32) -> Self {
+        Roster {
+            entries: HashMap::new(),
+            timeout: interval * missed_heartbeats,
+        }
+    }
+
+    /// Record a heartbeat. Returns true if the peer just joined.
+    pub fn observe(&mut self, presence: Presence, now: Instant) -> bool {
+        match self.entries.get_mut(&presence.peer_id) {
+            Some(entry) => {
+                entry.presence = presence;
+                entry.last_seen = now;
+                false
+            }
+            None => {
+                let entry = RosterEntry { presence, first_seen: now, last_seen: now };
+                self.entries.insert(entry.presence.peer_id, entry);
+                true
+            }
+        }
+    }
+
+    /// Drop peers that missed too many heartbeats and return them
+    pub fn expire(&mut self, now: Instant) -> Vec<RosterEntry> {
+        let expired: Vec<PeerId> = self
+            .entries
+            .iter()
+            .filter(|(_, entry)| now.saturating_duration_since(entry.last_seen) > self.timeout)
+            .map(|(peer, _)| *peer)
+            .collect();
+
+        expired
+            .into_iter()
+            .filter_map(|peer| self.entries.remove(&peer))
+            .collect()
+    }
+
+    pub fn get(&self, peer: &PeerId) -> Option<&RosterEntry> {
+        self.entries.get(peer)
+    }
+
+    pub fn contains(&self, peer: &PeerId) -> bool {
+        self.entries.contains_key(peer)
+    }
+
+    /// Current roster, ordered by node name
+    pub fn entries(&self) -> Vec<RosterEntry> {
+        let mut entries: Vec<RosterEntry> = self.entries.values().cloned().collect();
+        entries.sort_by(|a, b| a.presence.name.cmp(&b.presence.name));
+        entries
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
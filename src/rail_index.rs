@@ -0,0 +1,143 @@
+// Secondary index answering which rails touch an entity, built from the rail
+// and entity documents we hold and updated as they change
+use crate::docs::{DocId, Docs, Document, Namespace};
+use crate::entity::{EntityId, ENTITY_LABEL_PREFIX};
+use crate::rail_kind::RailKind;
+use crate::rails::{Rail, RailId, RAIL_PREFIX};
+use std::collections::{BTreeMap, HashMap};
+use std::ops::RangeInclusive;
+
+/// Narrows a lookup. The default passes every rail.
+#[derive(Debug, Clone, Default)]
+pub struct RailFilter {
+    pub kind: Option<RailKind>,
+    /// Keep rails with an edge weight in this range, among the edges the
+    /// lookup is about
+    pub weight: Option<RangeInclusive<f64>>,
+}
+
+impl RailFilter {
+    pub fn kind(mut self, kind: RailKind) -> Self {
+        self.kind = Some(kind);
+        self
+    }
+
+    pub fn weight(mut self, range: RangeInclusive<f64>) -> Self {
+        self.weight = Some(range);
+        self
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct RailIndex {
+    /// Rails whose documents we hold
+    rails: HashMap<RailId, Rail>,
+    /// Rails each entity is on, as the rail's participants or the entity's
+    /// back-references say, counting how many of the two do
+    by_entity: HashMap<EntityId, BTreeMap<RailId, usize>>,
+    /// The links each indexed document added, so they can be taken out again
+    sources: HashMap<DocId, Vec<(EntityId, RailId)>>,
+}
+
+impl RailIndex {
+    /// Index every document `docs` holds
+    pub fn build(docs: &Docs) -> RailIndex {
+        let mut index = RailIndex::default();
+        for id in docs.ids() {
+            index.update(docs, &id);
+        }
+        index
+    }
+
+    /// Re-index one document after it changed
+    pub fn update(&mut self, docs: &Docs, id: &DocId) {
+        self.remove(id);
+        let Some(doc) = docs.doc(id) else { return };
+        let links: Vec<(EntityId, RailId)> = if let Some(rail) = Rail::from_document(doc) {
+            let links = rail.entities.iter().map(|entity| (*entity, *id)).collect();
+            self.rails.insert(*id, rail);
+            links
+        } else if is_entity(doc) {
+            doc.list(RAIL_PREFIX)
+                .into_iter()
+                .filter_map(|(key, _)| Some((*id, key[RAIL_PREFIX.len()..].parse().ok()?)))
+                .collect()
+        } else {
+            return;
+        };
+        for (entity, rail) in &links {
+            *self.by_entity.entry(*entity).or_default().entry(*rail).or_default() += 1;
+        }
+        self.sources.insert(*id, links);
+    }
+
+    /// Every indexed rail document
+    pub fn rails(&self) -> impl Iterator<Item = &Rail> {
+        self.rails.values()
+    }
+
+    /// The indexed rail document, if we hold it
+    pub fn rail(&self, id: &RailId) -> Option<&Rail> {
+        self.rails.get(id)
+    }
+
+    /// Rails `entity` is on that pass `filter`, in order. Weights are those
+    /// of edges from or to `entity`.
+    pub fn rails_for(&self, entity: &EntityId, filter: &RailFilter) -> Vec<RailId> {
+        self.by_entity
+            .get(entity)
+            .into_iter()
+            .flat_map(|rails| rails.keys())
+            .filter(|rail| self.passes(rail, filter, |from, to| from == entity || to == entity))
+            .copied()
+            .collect()
+    }
+
+    /// Rails linking both `a` and `b` that pass `filter`, in order. Weights
+    /// are those of the edges between the two, either way.
+    pub fn rails_between(&self, a: &EntityId, b: &EntityId, filter: &RailFilter) -> Vec<RailId> {
+        let (Some(of_a), Some(of_b)) = (self.by_entity.get(a), self.by_entity.get(b)) else { return Vec::new() };
+        of_a.keys()
+            .filter(|rail| of_b.contains_key(*rail))
+            .filter(|rail| self.passes(rail, filter, |from, to| (from, to) == (a, b) || (from, to) == (b, a)))
+            .copied()
+            .collect()
+    }
+
+    /// Whether `rail` passes `filter`, judging weights by the edges `edge`
+    /// picks. Rails we only know of from an entity pass no filter that asks
+    /// for something.
+    fn passes(&self, rail: &RailId, filter: &RailFilter, edge: impl Fn(&EntityId, &EntityId) -> bool) -> bool {
+        if filter.kind.is_none() && filter.weight.is_none() {
+            return true;
+        }
+        let Some(rail) = self.rails.get(rail) else { return false };
+        if filter.kind.is_some() && rail.kind != filter.kind {
+            return false;
+        }
+        match &filter.weight {
+            Some(range) => rail.weights.iter().any(|((from, to), weight)| edge(from, to) && range.contains(weight)),
+            None => true,
+        }
+    }
+
+    fn remove(&mut self, id: &DocId) {
+        self.rails.remove(id);
+        for (entity, rail) in self.sources.remove(id).unwrap_or_default() {
+            let Some(rails) = self.by_entity.get_mut(&entity) else { continue };
+            if let Some(count) = rails.get_mut(&rail) {
+                *count -= 1;
+                if *count == 0 {
+                    rails.remove(&rail);
+                }
+            }
+            if rails.is_empty() {
+                self.by_entity.remove(&entity);
+            }
+        }
+    }
+}
+
+fn is_entity(doc: &Document) -> bool {
+    matches!(doc.namespace(), Namespace::Owned { label, .. } if label.starts_with(ENTITY_LABEL_PREFIX))
+}
@@ -0,0 +1,131 @@
+// Runtime registry of rail kinds. None are built in: applications register
+// the kinds they understand, such as semantic closeness or shared history.
+use crate::docs::DocError;
+use crate::entity::AttrType;
+use crate::rails::RailMetadata;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt,
+    str::FromStr,
+};
+
+/// A namespaced kind tag, `<namespace>/<name>`, such as
+/// `org.example/semantic-closeness`. Both parts use lowercase letters,
+/// digits, `.`, `-` and `_`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct RailKind(String);
+
+impl RailKind {
+    pub fn namespace(&self) -> &str {
+        self.0.split_once('/').map(|(namespace, _)| namespace).unwrap_or_default()
+    }
+
+    pub fn name(&self) -> &str {
+        self.0.split_once('/').map(|(_, name)| name).unwrap_or_default()
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl FromStr for RailKind {
+    type Err = DocError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let valid = |part: &str| {
+            !part.is_empty() && part.chars().all(|c| matches!(c, 'a'..='z' | '0'..='9' | '.' | '-' | '_'))
+        };
+        match s.split_once('/') {
+            Some((namespace, name)) if valid(namespace) && valid(name) => Ok(RailKind(s.to_string())),
+            _ => Err(DocError::InvalidId(format!("rail kind '{s}' isn't of the form <namespace>/<name>"))),
+        }
+    }
+}
+
+impl TryFrom<String> for RailKind {
+    type Error = DocError;
+
+    fn try_from(kind: String) -> Result<Self, Self::Error> {
+        kind.parse()
+    }
+}
+
+impl From<RailKind> for String {
+    fn from(kind: RailKind) -> Self {
+        kind.0
+    }
+}
+
+impl fmt::Display for RailKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+/// Metadata attributes a rail of some kind must carry, and their types
+pub type Schema = BTreeMap<String, AttrType>;
+
+/// Folds several weights for the same edge, such as one per rail of a kind
+/// linking two entities, into one
+pub type CombineFn = fn(&[f64]) -> f64;
+
+/// What a registered kind may add on top of its name
+#[derive(Debug, Clone, Default)]
+pub struct KindSpec {
+    pub schema: Option<Schema>,
+    pub combine: Option<CombineFn>,
+}
+
+/// The rail kinds this node knows about
+#[derive(Debug, Clone, Default)]
+pub struct RailKinds {
+    kinds: HashMap<RailKind, KindSpec>,
+}
+
+impl RailKinds {
+    /// Register `kind`, replacing any existing spec for it
+    pub fn register(&mut self, kind: RailKind, spec: KindSpec) {
+        self.kinds.insert(kind, spec);
+    }
+
+    pub fn get(&self, kind: &RailKind) -> Option<&KindSpec> {
+        self.kinds.get(kind)
+    }
+
+    /// Registered kinds, in order
+    pub fn kinds(&self) -> Vec<RailKind> {
+        let mut kinds: Vec<RailKind> = self.kinds.keys().cloned().collect();
+        kinds.sort();
+        kinds
+    }
+
+    /// Check that `kind` is registered and `metadata` fits its schema, if it has one
+    pub fn check(&self, kind: &RailKind, metadata: &RailMetadata) -> Result<(), DocError> {
+        let spec = self.kinds.get(kind).ok_or_else(|| DocError::Rejected(format!("rail kind {kind} isn't registered")))?;
+        for (name, expected) in spec.schema.iter().flatten() {
+            match metadata.get(name) {
+                Some(value) if value.attr_type() == *expected => {}
+                Some(value) => {
+                    return Err(DocError::Rejected(format!("{kind} wants '{name}' as {expected:?}, not {:?}", value.attr_type())));
+                }
+                None => return Err(DocError::Rejected(format!("{kind} rails need a '{name}' attribute"))),
+            }
+        }
+        Ok(())
+    }
+
+    /// Combine weights with the kind's function, or their mean if it has none.
+    /// `None` for no weights.
+    pub fn combine(&self, kind: &RailKind, weights: &[f64]) -> Option<f64> {
+        if weights.is_empty() {
+            return None;
+        }
+        match self.kinds.get(kind).and_then(|spec| spec.combine) {
+            Some(combine) => Some(combine(weights)),
+            None => Some(weights.iter().sum::<f64>() / weights.len() as f64),
+        }
+    }
+}
@@ -0,0 +1,496 @@
+// Veracity rails: shared documents linking entities, created as ABOUT.md describes
+use crate::codec::{from_cbor, to_cbor};
+use crate::docs::{
+    AuthorId, DocError, DocId, Docs, Document, Entry, Namespace, CREATOR_KEY, KIND_KEY, RAIL_INVITES_DOC, TICKET_READ_DOC,
+};
+use crate::entity::{AttrValue, EntityId, ENTITY_LABEL_PREFIX};
+use crate::rail_kind::RailKind;
+use crate::ticket::{Capability, DocTicket, ShareMode};
+use libp2p::identity::ed25519;
+use libp2p::PeerId;
+use serde::{Deserialize, Serialize};
+use std::{collections::BTreeMap, sync::Arc};
+
+pub use crate::docs::RAIL_DOC_KIND;
+
+/// Prefix of the keys under which entities and TicketRead/WriteDocs refer to rails
+pub const RAIL_PREFIX: &str = "rail/";
+
+/// RPC method a rail's creator uses to ask the owners of the other entities
+/// it links to record the back-reference. Handled by the node itself.
+pub const RAIL_INVITE_METHOD: &str = "rail.invite";
+
+const META_KEY: &str = "meta";
+const RAIL_KIND_KEY: &str = "rail-kind";
+const ENTITY_PREFIX: &str = "entity/";
+const WEIGHT_PREFIX: &str = "weight/";
+
+/// A rail is identified by the id of its VeracityRailDoc
+pub type RailId = DocId;
+
+/// Free-form description of a link, stored in the rail and in every linked entity
+pub type RailMetadata = BTreeMap<String, AttrValue>;
+
+/// Stored under `creator` in a rail. The author and seed hash to the rail's
+/// id, so no one else can claim to have created it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Creator {
+    pub author: AuthorId,
+    pub seed: [u8; 32],
+}
+
+impl Creator {
+    /// Whether this is the creator of the rail `id`, as written by `author`
+    fn proves(&self, id: &RailId, author: &AuthorId) -> bool {
+        self.author == *author && DocId::rail(&self.author, &self.seed) == *id
+    }
+}
+
+/// Stored under `entity/<id>` in a rail. The owner and label hash to the
+/// entity's id, so anyone can check who controls the entity's outgoing weights.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Participant {
+    pub owner: AuthorId,
+    pub label: String,
+}
+
+/// A rail as read back from its VeracityRailDoc
+#[derive(Debug, Clone, PartialEq)]
+pub struct Rail {
+    pub id: RailId,
+    /// `None` for a kind tag that doesn't parse
+    pub kind: Option<RailKind>,
+    pub metadata: RailMetadata,
+    pub entities: Vec<EntityId>,
+    /// Directed weight of each edge between participants
+    pub weights: BTreeMap<(EntityId, EntityId), f64>,
+}
+
+impl Rail {
+    /// `None` if the document isn't a VeracityRailDoc or hasn't synced yet
+    pub fn from_document(doc: &Document) -> Option<Rail> {
+        if *doc.namespace() != Namespace::Rail || doc.get(KIND_KEY)? != RAIL_DOC_KIND.as_bytes() {
+            return None;
+        }
+        let participants = participants(doc);
+        let weights = doc
+            .list(WEIGHT_PREFIX)
+            .into_iter()
+            .filter_map(|(key, value)| Some((parse_edge(&key[WEIGHT_PREFIX.len()..])?, from_cbor(&value).ok()?)))
+            .filter(|((from, to), _)| participants.contains_key(from) && participants.contains_key(to))
+            .collect();
+        let kind = doc.get(RAIL_KIND_KEY).and_then(|kind| std::str::from_utf8(kind).ok()?.parse().ok());
+        Some(Rail {
+            id: doc.id(),
+            kind,
+            metadata: from_cbor(doc.get(META_KEY)?).ok()?,
+            entities: participants.into_keys().collect(),
+            weights,
+        })
+    }
+
+    /// Weight of the edge from `from` to `to`, if its owner has set one
+    pub fn weight(&self, from: &EntityId, to: &EntityId) -> Option<f64> {
+        self.weights.get(&(*from, *to)).copied()
+    }
+}
+
+/// Link `entities` with a new veracity rail of `kind`:
+///
+/// 1. Create the VeracityRailDoc, in a rail namespace naming us its creator
+/// 2. Store a read ticket for it in our TicketReadDoc
+/// 3. Store `rail/<rail id>` = metadata in each entity's EntityDoc
+///
+/// We must hold every entity, so the rail can record who owns it. Only the
+/// entities we own are written to; the owners of the others have to add
+/// the back-reference themselves.
+pub fn create_rail(
+    docs: &mut Docs,
+    kind: &RailKind,
+    entities: &[EntityId],
+    metadata: RailMetadata,
+) -> Result<RailId, DocError> {
+    let encoded = to_cbor(&metadata).map_err(|e| DocError::Codec(e.to_string()))?;
+    // A fresh key's public half is as good a random seed as any
+    let creator = Creator { author: docs.author(), seed: ed25519::Keypair::generate().public().to_bytes() };
+    let id = docs.add(Document::rail(DocId::rail(&creator.author, &creator.seed)))?;
+    docs.set(&id, CREATOR_KEY, encode(&creator)?)?;
+    docs.set(&id, KIND_KEY, RAIL_DOC_KIND.as_bytes().to_vec())?;
+    docs.set(&id, RAIL_KIND_KEY, kind.as_str().as_bytes().to_vec())?;
+    docs.set(&id, META_KEY, encoded)?;
+
+    let key = format!("{RAIL_PREFIX}{id}");
+    let ticket_read_doc = docs.create_owned(TICKET_READ_DOC)?;
+    docs.set(&ticket_read_doc, &key, read_ticket(id).to_string().into_bytes())?;
+
+    for entity in entities {
+        add_participant(docs, &id, entity)?;
+    }
+    Ok(id)
+}
+
+/// Add an entity we hold to a rail, recording the rail in the entity if it is ours
+pub fn add_participant(docs: &mut Docs, rail: &RailId, entity: &EntityId) -> Result<(), DocError> {
+    let doc = docs.doc(entity).ok_or(DocError::UnknownDoc(*entity))?;
+    let Namespace::Owned { owner, label } = doc.namespace().clone() else {
+        return Err(DocError::InvalidId(format!("{entity} is not an entity")));
+    };
+    docs.set(rail, &format!("{ENTITY_PREFIX}{entity}"), encode(&Participant { owner, label })?)?;
+    if owner == docs.author() {
+        let metadata = docs.get(rail, META_KEY)?.unwrap_or_default();
+        docs.set(entity, &format!("{RAIL_PREFIX}{rail}"), metadata)?;
+    }
+    Ok(())
+}
+
+/// Take an entity off a rail. Its weights stop counting, and an entity we
+/// own drops its reference to the rail.
+pub fn remove_participant(docs: &mut Docs, rail: &RailId, entity: &EntityId) -> Result<(), DocError> {
+    docs.delete(rail, &format!("{ENTITY_PREFIX}{entity}"))?;
+    if docs.doc(entity).is_some_and(|doc| doc.is_writable_by(&docs.author())) {
+        docs.delete(entity, &format!("{RAIL_PREFIX}{rail}"))?;
+    }
+    Ok(())
+}
+
+/// Set the weight of the edge from `from`, which must be ours, to `to`
+pub fn set_weight(docs: &mut Docs, rail: &RailId, from: &EntityId, to: &EntityId, weight: f64) -> Result<(), DocError> {
+    docs.set(rail, &format!("{WEIGHT_PREFIX}{from}/{to}"), encode(&weight)?)?;
+    Ok(())
+}
+
+pub fn remove_weight(docs: &mut Docs, rail: &RailId, from: &EntityId, to: &EntityId) -> Result<(), DocError> {
+    docs.delete(rail, &format!("{WEIGHT_PREFIX}{from}/{to}"))?;
+    Ok(())
+}
+
+/// Rules for VeracityRailDocs, registered with `register`:
+///
+/// - rails live in rail namespaces, and `creator` comes before anything
+///   else, written by the author whose id and seed hash to the rail's id
+/// - `entity/<id>` is written by the creator or the entity's owner
+/// - `weight/<from>/<to>` is written by the owner of `from` only
+/// - every other key, `kind` and `meta` included, by the creator only
+pub fn validate(doc: &Document, key: &str, value: Option<&[u8]>, author: &AuthorId) -> Result<(), String> {
+    if *doc.namespace() != Namespace::Rail {
+        return Err("rails live in rail namespaces".to_string());
+    }
+    if key == CREATOR_KEY {
+        let claimed: Option<Creator> = value.and_then(|value| from_cbor(value).ok());
+        if !claimed.is_some_and(|claimed| claimed.proves(&doc.id(), author)) {
+            return Err("the creator entry doesn't match the rail's id".to_string());
+        }
+        return Ok(());
+    }
+    // Nothing can be judged before we know who created the rail
+    let Some(creator) = creator(doc) else {
+        return Err(format!("'{key}' arrived before the rail's creator"));
+    };
+    let by_creator = creator == *author;
+
+    if let Some(entity) = key.strip_prefix(ENTITY_PREFIX) {
+        let entity: EntityId = entity.parse().map_err(|e: DocError| e.to_string())?;
+        let owner = match value {
+            Some(value) => {
+                let participant: Participant = from_cbor(value).map_err(|e| e.to_string())?;
+                if DocId::owned(&participant.owner, &participant.label) != entity {
+                    return Err(format!("participant doesn't match entity {entity}"));
+                }
+                Some(participant.owner)
+            }
+            None => participants(doc).get(&entity).copied(),
+        };
+        if by_creator || owner == Some(*author) {
+            return Ok(());
+        }
+        return Err(format!("{author} can't change participant {entity}"));
+    }
+    if let Some(edge) = key.strip_prefix(WEIGHT_PREFIX) {
+        let (from, to) = parse_edge(edge).ok_or_else(|| format!("malformed weight key '{key}'"))?;
+        let participants = participants(doc);
+        if participants.get(&from) != Some(author) {
+            return Err(format!("only the owner of {from} can weight its edges"));
+        }
+        if let Some(value) = value {
+            if !participants.contains_key(&to) {
+                return Err(format!("{to} isn't on the rail"));
+            }
+            let weight: f64 = from_cbor(value).map_err(|e| e.to_string())?;
+            if !weight.is_finite() {
+                return Err(format!("weight {weight} isn't finite"));
+            }
+        }
+        return Ok(());
+    }
+    if key == KIND_KEY && value != Some(RAIL_DOC_KIND.as_bytes()) {
+        return Err("a rail stays a rail".to_string());
+    }
+    if !by_creator {
+        return Err(format!("only the rail's creator can change '{key}'"));
+    }
+    Ok(())
+}
+
+/// Enforce the rail rules on every VeracityRailDoc `docs` holds
+pub fn register(docs: &mut Docs) {
+    docs.register_validator(RAIL_DOC_KIND, validate);
+}
+
+pub fn get_rail(docs: &Docs, id: &RailId) -> Result<Option<Rail>, DocError> {
+    let doc = docs.doc(id).ok_or(DocError::UnknownDoc(*id))?;
+    Ok(Rail::from_document(doc))
+}
+
+/// Who created the rail `doc`, if `doc` doesn't know yet and `entries`
+/// carry a creator entry matching its id
+pub fn arriving_creator(doc: &Document, entries: &[Entry]) -> Option<AuthorId> {
+    if *doc.namespace() != Namespace::Rail || creator(doc).is_some() {
+        return None;
+    }
+    entries.iter().filter(|entry| entry.key == CREATOR_KEY).find_map(|entry| {
+        let claimed: Creator = from_cbor(entry.value.as_deref()?).ok()?;
+        claimed.proves(&doc.id(), &entry.author).then_some(claimed.author)
+    })
+}
+
+/// Every rail we hold, or only those of `kind`
+pub fn find_rails(docs: &Docs, kind: Option<&RailKind>) -> Vec<Rail> {
+    docs.ids()
+        .iter()
+        .filter_map(|id| Rail::from_document(docs.doc(id)?))
+        .filter(|rail| kind.is_none() || rail.kind.as_ref() == kind)
+        .collect()
+}
+
+/// Rails recorded in an entity's EntityDoc, with the metadata stored there
+pub fn rails_of(docs: &Docs, entity: &EntityId) -> Result<BTreeMap<RailId, RailMetadata>, DocError> {
+    Ok(docs
+        .list(entity, RAIL_PREFIX)?
+        .into_iter()
+        .filter_map(|(key, value)| Some((key[RAIL_PREFIX.len()..].parse().ok()?, from_cbor(&value).ok()?)))
+        .collect())
+}
+
+/// Ticket for a rail, knowing only its id. Rails need no write capability:
+/// the rules decide what each author may write.
+pub fn read_ticket(id: RailId) -> DocTicket {
+    DocTicket { doc: id, namespace: Namespace::Rail, capability: Capability::Read, peers: Vec::new() }
+}
+
+/// Start replicating a rail found in an entity, so it can be evaluated
+pub fn open_rail(docs: &mut Docs, id: &RailId) -> Result<RailId, DocError> {
+    docs.join(&read_ticket(*id))
+}
+
+/// Sent to the owner of an entity on a rail whose EntityDoc the sender can't
+/// write to, asking them to record the rail in it
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RailInvite {
+    pub rail: RailId,
+    pub entity: EntityId,
+    pub metadata: RailMetadata,
+    /// Read ticket for the rail. The invitee writes its own participant and
+    /// edges as itself, so no secret is handed on.
+    pub ticket: DocTicket,
+}
+
+/// Where an invitation to link one of our entities to a rail stands
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum BackRefStatus {
+    /// Waiting for the application to accept or decline it
+    Pending,
+    /// The entity records the rail
+    Accepted,
+    Declined,
+}
+
+/// Decides what happens to an invitation when it arrives
+pub type InvitePolicy = Arc<dyn Fn(PeerId, &RailInvite) -> BackRefStatus + Send + Sync>;
+
+/// Accept every invitation to link one of our entities
+pub fn accept_all() -> InvitePolicy {
+    Arc::new(|_, _: &RailInvite| BackRefStatus::Accepted)
+}
+
+/// A rail and an entity only one of which refers to the other
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct HalfLink {
+    pub rail: RailId,
+    pub entity: EntityId,
+    /// Owner of the entity, who has to record a missing back-reference
+    pub owner: AuthorId,
+    pub missing: MissingLink,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum MissingLink {
+    /// The rail lists the entity, but the entity doesn't record the rail
+    BackReference,
+    /// The entity records the rail, but the rail doesn't list the entity
+    Participant,
+}
+
+#[derive(Serialize, Deserialize)]
+struct StoredInvite {
+    invite: RailInvite,
+    status: BackRefStatus,
+}
+
+/// Invitation for the owner of `entity` to record `rail`. Only the rail's
+/// creator and the owners of its participants hand it on.
+pub fn invite(docs: &Docs, rail: &RailId, entity: &EntityId) -> Result<RailInvite, DocError> {
+    let doc = docs.doc(rail).ok_or(DocError::UnknownDoc(*rail))?;
+    let us = docs.author();
+    if creator(doc) != Some(us) && !participants(doc).values().any(|owner| *owner == us) {
+        return Err(DocError::ReadOnly(*rail));
+    }
+    let metadata = doc.get(META_KEY).ok_or(DocError::UnknownDoc(*rail))?;
+    Ok(RailInvite {
+        rail: *rail,
+        entity: *entity,
+        metadata: from_cbor(metadata).map_err(|e| DocError::Codec(e.to_string()))?,
+        ticket: docs.share(rail, ShareMode::Read, Vec::new())?,
+    })
+}
+
+/// Record an invitation to link one of our entities, with the `status` the
+/// caller picked for it. Accepting records the back-reference at once. An
+/// invitation we already accepted or declined keeps that answer, which is
+/// returned.
+pub fn receive_invite(docs: &mut Docs, invite: RailInvite, status: BackRefStatus) -> Result<BackRefStatus, DocError> {
+    let ours = docs
+        .doc(&invite.entity)
+        .is_some_and(|doc| matches!(doc.namespace(), Namespace::Owned { owner, .. } if *owner == docs.author()));
+    if !ours {
+        return Err(DocError::Rejected(format!("{} isn't one of our entities", invite.entity)));
+    }
+    if invite.ticket.doc != invite.rail || invite.ticket.namespace != Namespace::Rail {
+        return Err(DocError::InvalidTicket(format!("not a ticket for rail {}", invite.rail)));
+    }
+    let status = match invite_status(docs, &invite.rail, &invite.entity)? {
+        Some(answered @ (BackRefStatus::Accepted | BackRefStatus::Declined)) => answered,
+        _ => status,
+    };
+    store_invite(docs, invite, status)?;
+    Ok(status)
+}
+
+/// Accept or decline an invitation we received. Declining one we accepted
+/// drops the back-reference again.
+pub fn answer_invite(docs: &mut Docs, rail: &RailId, entity: &EntityId, accept: bool) -> Result<RailInvite, DocError> {
+    let stored = stored_invite(docs, rail, entity)?
+        .ok_or_else(|| DocError::Rejected(format!("no invitation to link {entity} to {rail}")))?;
+    let status = if accept { BackRefStatus::Accepted } else { BackRefStatus::Declined };
+    store_invite(docs, stored.invite.clone(), status)?;
+    Ok(stored.invite)
+}
+
+/// Status of the invitation to link `entity` to `rail`, if we received one
+pub fn invite_status(docs: &Docs, rail: &RailId, entity: &EntityId) -> Result<Option<BackRefStatus>, DocError> {
+    Ok(stored_invite(docs, rail, entity)?.map(|stored| stored.status))
+}
+
+/// Every invitation we received, with where it stands
+pub fn invites(docs: &Docs) -> Vec<(RailInvite, BackRefStatus)> {
+    let Some(doc) = docs.doc(&docs.owned_id(RAIL_INVITES_DOC)) else { return Vec::new() };
+    doc.list("")
+        .into_iter()
+        .filter_map(|(_, value)| from_cbor::<StoredInvite>(&value).ok())
+        .map(|stored| (stored.invite, stored.status))
+        .collect()
+}
+
+/// Rails and entities we hold that only one side links up: participants
+/// whose EntityDoc doesn't record the rail, and entities recording a rail
+/// that doesn't list them. Pairs where we hold only one side aren't known
+/// to be half-linked, so aren't reported.
+pub fn half_linked(docs: &Docs) -> Vec<HalfLink> {
+    let mut links = Vec::new();
+    for id in docs.ids() {
+        let Some(doc) = docs.doc(&id) else { continue };
+        if Rail::from_document(doc).is_some() {
+            for (entity, owner) in participants(doc) {
+                let Some(entity_doc) = docs.doc(&entity) else { continue };
+                if entity_doc.get(&format!("{RAIL_PREFIX}{id}")).is_none() {
+                    links.push(HalfLink { rail: id, entity, owner, missing: MissingLink::BackReference });
+                }
+            }
+            continue;
+        }
+        let Namespace::Owned { owner, label } = doc.namespace() else { continue };
+        if !label.starts_with(ENTITY_LABEL_PREFIX) {
+            continue;
+        }
+        for (key, _) in doc.list(RAIL_PREFIX) {
+            let Ok(rail) = key[RAIL_PREFIX.len()..].parse::<RailId>() else { continue };
+            let Some(rail_doc) = docs.doc(&rail).filter(|rail_doc| Rail::from_document(rail_doc).is_some()) else {
+                continue;
+            };
+            if !participants(rail_doc).contains_key(&id) {
+                links.push(HalfLink { rail, entity: id, owner: *owner, missing: MissingLink::Participant });
+            }
+        }
+    }
+    links.sort();
+    links
+}
+
+fn invite_key(rail: &RailId, entity: &EntityId) -> String {
+    format!("{rail}/{entity}")
+}
+
+fn stored_invite(docs: &Docs, rail: &RailId, entity: &EntityId) -> Result<Option<StoredInvite>, DocError> {
+    let Some(doc) = docs.doc(&docs.owned_id(RAIL_INVITES_DOC)) else { return Ok(None) };
+    let Some(stored) = doc.get(&invite_key(rail, entity)) else { return Ok(None) };
+    from_cbor(stored).map(Some).map_err(|e| DocError::Codec(e.to_string()))
+}
+
+/// Save an invitation in our private RailInvitesDoc, and make the entity's
+/// back-reference match `status`
+fn store_invite(docs: &mut Docs, invite: RailInvite, status: BackRefStatus) -> Result<(), DocError> {
+    let key = format!("{RAIL_PREFIX}{}", invite.rail);
+    match status {
+        BackRefStatus::Accepted => {
+            // Keep the rail's ticket as if we had created it ourselves
+            docs.join(&invite.ticket)?;
+            let ticket_read_doc = docs.create_owned(TICKET_READ_DOC)?;
+            docs.set(&ticket_read_doc, &key, read_ticket(invite.rail).to_string().into_bytes())?;
+            docs.set(&invite.entity, &key, encode(&invite.metadata)?)?;
+        }
+        BackRefStatus::Declined => {
+            if docs.get(&invite.entity, &key)?.is_some() {
+                docs.delete(&invite.entity, &key)?;
+            }
+        }
+        BackRefStatus::Pending => {}
+    }
+    let invites = docs.create_owned(RAIL_INVITES_DOC)?;
+    let entry_key = invite_key(&invite.rail, &invite.entity);
+    docs.set(&invites, &entry_key, encode(&StoredInvite { invite, status })?)?;
+    Ok(())
+}
+
+/// Who created a rail, once its creator entry is in
+fn creator(doc: &Document) -> Option<AuthorId> {
+    doc.get(CREATOR_KEY).and_then(|creator| from_cbor::<Creator>(creator).ok()).map(|creator| creator.author)
+}
+
+/// Live participants of a rail and who owns each
+fn participants(doc: &Document) -> BTreeMap<EntityId, AuthorId> {
+    doc.list(ENTITY_PREFIX)
+        .into_iter()
+        .filter_map(|(key, value)| {
+            let participant: Participant = from_cbor(&value).ok()?;
+            Some((key[ENTITY_PREFIX.len()..].parse().ok()?, participant.owner))
+        })
+        .collect()
+}
+
+fn parse_edge(edge: &str) -> Option<(EntityId, EntityId)> {
+    let (from, to) = edge.split_once('/')?;
+    Some((from.parse().ok()?, to.parse().ok()?))
+}
+
+fn encode<T: Serialize>(value: &T) -> Result<Vec<u8>, DocError> {
+    to_cbor(value).map_err(|e| DocError::Codec(e.to_string()))
+}
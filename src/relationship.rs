@@ -0,0 +1,88 @@
+// RelationshipDocs: one document per entity, at an id anyone can derive from
+// the entity's, where every author records how entities relate to it
+use crate::codec::{from_cbor, to_cbor};
+use crate::docs::{AuthorId, DocError, DocId, Docs, Document};
+use crate::entity::{AttrValue, EntityId};
+use crate::rails::RailId;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+/// Prefix of the DHT provider keys announcing who holds a RelationshipDoc
+pub const RELATIONSHIP_PROVIDER_PREFIX: &str = "/raggy/relationships/";
+
+/// How another entity relates to the one a RelationshipDoc is about. Stored
+/// under `<author>/<other entity>`, so each author keeps their own view and
+/// concurrent writers never replace each other's entries.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Relationship {
+    /// What sort of relationship it is, such as `colleague` or `derived-from`
+    pub kind: String,
+    /// The rail backing the relationship, if there is one
+    pub rail: Option<RailId>,
+    pub attrs: BTreeMap<String, AttrValue>,
+}
+
+impl Relationship {
+    pub fn new(kind: &str) -> Self {
+        Relationship { kind: kind.to_string(), rail: None, attrs: BTreeMap::new() }
+    }
+
+    pub fn rail(mut self, rail: RailId) -> Self {
+        self.rail = Some(rail);
+        self
+    }
+
+    pub fn attr(mut self, name: &str, value: AttrValue) -> Self {
+        self.attrs.insert(name.to_string(), value);
+        self
+    }
+}
+
+/// Id of the RelationshipDoc of `entity`
+pub fn doc_id(entity: &EntityId) -> DocId {
+    DocId::relationships(entity)
+}
+
+/// DHT key under which holders of the RelationshipDoc of `entity` provide it
+pub fn provider_key(entity: &EntityId) -> Vec<u8> {
+    format!("{RELATIONSHIP_PROVIDER_PREFIX}{}", doc_id(entity)).into_bytes()
+}
+
+/// Start replicating the RelationshipDoc of `entity`, or return it if we already do
+pub fn open(docs: &mut Docs, entity: &EntityId) -> Result<DocId, DocError> {
+    docs.add(Document::relationships(*entity))
+}
+
+/// Record how `other` relates to `entity`, replacing what we recorded before
+pub fn set(docs: &mut Docs, entity: &EntityId, other: &EntityId, relationship: &Relationship) -> Result<(), DocError> {
+    let id = open(docs, entity)?;
+    let value = to_cbor(relationship).map_err(|e| DocError::Codec(e.to_string()))?;
+    let key = key(&docs.author(), other);
+    docs.set(&id, &key, value)?;
+    Ok(())
+}
+
+/// Remove what we recorded about how `other` relates to `entity`
+pub fn remove(docs: &mut Docs, entity: &EntityId, other: &EntityId) -> Result<(), DocError> {
+    let id = open(docs, entity)?;
+    let key = key(&docs.author(), other);
+    docs.delete(&id, &key)?;
+    Ok(())
+}
+
+/// Every relationship recorded about `entity` in our replica, keyed by who
+/// recorded it and the other entity. Empty if we don't hold the document.
+pub fn get(docs: &Docs, entity: &EntityId) -> BTreeMap<(AuthorId, EntityId), Relationship> {
+    let Ok(entries) = docs.list(&doc_id(entity), "") else { return BTreeMap::new() };
+    entries
+        .into_iter()
+        .filter_map(|(key, value)| {
+            let (author, other) = key.split_once('/')?;
+            Some(((author.parse().ok()?, other.parse().ok()?), from_cbor(&value).ok()?))
+        })
+        .collect()
+}
+
+fn key(author: &AuthorId, other: &EntityId) -> String {
+    format!("{author}/{other}")
+}
@@ -0,0 +1,63 @@
+// Direct peer-to-peer requests over /raggy/rpc/1.0.0
+use libp2p::{
+    request_response::{self, ProtocolSupport},
+    PeerId, StreamProtocol,
+};
+use serde::{Deserialize, Serialize};
+use std::{collections::HashMap, sync::Arc, time::Duration};
+
+pub const RPC_PROTOCOL: StreamProtocol = StreamProtocol::new("/raggy/rpc/1.0.0");
+pub const RPC_TIMEOUT: Duration = Duration::from_secs(30);
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RpcRequest {
+    /// Name of the handler that should serve the request
+    pub method: String,
+    #[serde(with = "serde_bytes")]
+    pub body: Vec<u8>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum RpcResponse {
+    Ok(#[serde(with = "serde_bytes")] Vec<u8>),
+    Err(String),
+}
+
+pub type Behaviour = request_response::cbor::Behaviour<RpcRequest, RpcResponse>;
+
+pub fn new_behaviour() -> Behaviour {
+    request_response::cbor::Behaviour::new(
+        [(RPC_PROTOCOL, ProtocolSupport::Full)],
+        request_response::Config::default().with_request_timeout(RPC_TIMEOUT),
+    )
+}
+
+/// Serves one RPC method. Handlers run on the node's event loop, so they
+/// should return quickly.
+pub type RpcHandler = Arc<dyn Fn(PeerId, &[u8]) -> Result<Vec<u8>, String> + Send + Sync>;
+
+#[derive(Default, Clone)]
+pub struct HandlerRegistry {
+    handlers: HashMap<String, RpcHandler>,
+}
+
+impl HandlerRegistry {
+    /// Register a handler, returning the one it replaced
+    pub fn register(&mut self, method: &str, handler: RpcHandler) -> Option<RpcHandler> {
+        self.handlers.insert(method.to_string(), handler)
+    }
+
+    pub fn unregister(&mut self, method: &str) -> bool {
+        self.handlers.remove(method).is_some()
+    }
+
+    pub fn handle(&self, peer: PeerId, request: &RpcRequest) -> RpcResponse {
+        match self.handlers.get(&request.method) {
+            Some(handler) => match handler(peer, &request.body) {
+                Ok(body) => RpcResponse::Ok(body),
+                Err(e) => RpcResponse::Err(e),
+            },
+            None => RpcResponse::Err(format!("unknown method '{}'", request.method)),
+        }
+    }
+}
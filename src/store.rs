@@ -0,0 +1,289 @@
+// Local content-addressed blob store
+use crate::blob::{Manifest, CHUNK_SIZE};
+use crate::codec::{from_cbor, to_cbor};
+use data_encoding::BASE32_NOPAD;
+use serde::{Deserialize, Serialize};
+use std::{
+    error::Error,
+    fmt,
+    fs::{self, File},
+    io::{self, Read, Seek, SeekFrom},
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+const CID_VERSION: u8 = 0x01;
+const RAW_CODEC: u8 = 0x55;
+const BLAKE3_MULTIHASH: u8 = 0x1e;
+const DIGEST_LEN: u8 = 32;
+
+/// CIDv1 of raw content with a BLAKE3 multihash, written as multibase base32
+#[derive(Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct Cid {
+    digest: [u8; 32],
+}
+
+impl Cid {
+    pub fn of(data: &[u8]) -> Self {
+        Cid { digest: *blake3::hash(data).as_bytes() }
+    }
+
+    pub fn from_digest(digest: [u8; 32]) -> Self {
+        Cid { digest }
+    }
+
+    pub fn digest(&self) -> [u8; 32] {
+        self.digest
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = vec![CID_VERSION, RAW_CODEC, BLAKE3_MULTIHASH, DIGEST_LEN];
+        bytes.extend_from_slice(&self.digest);
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, StoreError> {
+        match bytes {
+            [CID_VERSION, RAW_CODEC, BLAKE3_MULTIHASH, DIGEST_LEN, digest @ ..] => {
+                let digest = digest
+                    .try_into()
+                    .map_err(|_| StoreError::InvalidCid("wrong digest length".to_string()))?;
+                Ok(Cid { digest })
+            }
+            _ => Err(StoreError::InvalidCid("not a raw BLAKE3 CIDv1".to_string())),
+        }
+    }
+}
+
+impl fmt::Display for Cid {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "b{}", BASE32_NOPAD.encode(&self.to_bytes()).to_ascii_lowercase())
+    }
+}
+
+impl fmt::Debug for Cid {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Cid({self})")
+    }
+}
+
+impl FromStr for Cid {
+    type Err = StoreError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let encoded = s
+            .strip_prefix('b')
+            .ok_or_else(|| StoreError::InvalidCid(format!("unsupported multibase in '{s}'")))?;
+        let bytes = BASE32_NOPAD
+            .decode(encoded.to_ascii_uppercase().as_bytes())
+            .map_err(|e| StoreError::InvalidCid(e.to_string()))?;
+        Cid::from_bytes(&bytes)
+    }
+}
+
+#[derive(Debug)]
+pub enum StoreError {
+    Io(io::Error),
+    InvalidCid(String),
+    /// A stored blob no longer matches its CID
+    Corrupt(Cid),
+    NotFound(Cid),
+}
+
+impl fmt::Display for StoreError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StoreError::Io(e) => write!(f, "store i/o error: {e}"),
+            StoreError::InvalidCid(e) => write!(f, "invalid cid: {e}"),
+            StoreError::Corrupt(cid) => write!(f, "blob {cid} is corrupt"),
+            StoreError::NotFound(cid) => write!(f, "blob {cid} not found"),
+        }
+    }
+}
+
+impl Error for StoreError {}
+
+impl From<io::Error> for StoreError {
+    fn from(e: io::Error) -> Self {
+        StoreError::Io(e)
+    }
+}
+
+/// Blobs live under `<data-dir>/blobs/<first digest byte>/<cid>`, with their
+/// manifests at `<data-dir>/manifests/<cid>`, and a blob is pinned while
+/// `<data-dir>/pins/<cid>` exists. Unpinned blobs are removed by `gc`. Blobs
+/// being fetched are assembled under `<data-dir>/downloads`.
+#[derive(Debug, Clone)]
+pub struct BlobStore {
+    blobs: PathBuf,
+    manifests: PathBuf,
+    pins: PathBuf,
+    downloads: PathBuf,
+}
+
+impl BlobStore {
+    pub fn open(data_dir: &Path) -> Result<Self, StoreError> {
+        let store = BlobStore {
+            blobs: data_dir.join("blobs"),
+            manifests: data_dir.join("manifests"),
+            pins: data_dir.join("pins"),
+            downloads: data_dir.join("downloads"),
+        };
+        fs::create_dir_all(&store.blobs)?;
+        fs::create_dir_all(&store.manifests)?;
+        fs::create_dir_all(&store.pins)?;
+        fs::create_dir_all(&store.downloads)?;
+        Ok(store)
+    }
+
+    /// Where partial downloads are kept until they complete
+    pub fn downloads_dir(&self) -> &Path {
+        &self.downloads
+    }
+
+    fn blob_path(&self, cid: &Cid) -> PathBuf {
+        self.blobs.join(format!("{:02x}", cid.digest[0])).join(cid.to_string())
+    }
+
+    fn manifest_path(&self, cid: &Cid) -> PathBuf {
+        self.manifests.join(cid.to_string())
+    }
+
+    fn pin_path(&self, cid: &Cid) -> PathBuf {
+        self.pins.join(cid.to_string())
+    }
+
+    /// Store `data` along with its manifest, so serving it never rehashes the blob
+    pub fn put(&self, data: &[u8]) -> Result<Cid, StoreError> {
+        let cid = Cid::of(data);
+        if !self.manifest_path(&cid).exists() {
+            self.write_manifest(&cid, &Manifest::for_blob(data))?;
+        }
+        let path = self.blob_path(&cid);
+        if !path.exists() {
+            if let Some(shard) = path.parent() {
+                fs::create_dir_all(shard)?;
+            }
+            // Write then rename so readers never see a partial blob
+            let partial = path.with_extension("partial");
+            fs::write(&partial, data)?;
+            fs::rename(&partial, &path)?;
+        }
+        Ok(cid)
+    }
+
+    pub fn get(&self, cid: &Cid) -> Result<Option<Vec<u8>>, StoreError> {
+        match fs::read(self.blob_path(cid)) {
+            Ok(data) if Cid::of(&data) == *cid => Ok(Some(data)),
+            Ok(_) => Err(StoreError::Corrupt(*cid)),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Chunk `index` of a blob, read from disk on its own. It isn't checked
+    /// against the manifest here; whoever fetches it does that.
+    pub fn read_chunk(&self, cid: &Cid, index: u32) -> Result<Option<Vec<u8>>, StoreError> {
+        let mut file = match File::open(self.blob_path(cid)) {
+            Ok(file) => file,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+        let offset = index as u64 * CHUNK_SIZE as u64;
+        if offset >= file.metadata()?.len() {
+            return Ok(None);
+        }
+        file.seek(SeekFrom::Start(offset))?;
+        let mut chunk = Vec::with_capacity(CHUNK_SIZE);
+        file.take(CHUNK_SIZE as u64).read_to_end(&mut chunk)?;
+        Ok(Some(chunk))
+    }
+
+    /// Manifest of a stored blob, computed once when it was added. Blobs
+    /// stored without one get it on first use.
+    pub fn manifest(&self, cid: &Cid) -> Result<Option<Manifest>, StoreError> {
+        match fs::read(self.manifest_path(cid)) {
+            Ok(bytes) => return from_cbor(&bytes).map(Some).map_err(|_| StoreError::Corrupt(*cid)),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e.into()),
+        }
+        let Some(data) = self.get(cid)? else { return Ok(None) };
+        let manifest = Manifest::for_blob(&data);
+        self.write_manifest(cid, &manifest)?;
+        Ok(Some(manifest))
+    }
+
+    fn write_manifest(&self, cid: &Cid, manifest: &Manifest) -> Result<(), StoreError> {
+        let bytes = to_cbor(manifest).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        let path = self.manifest_path(cid);
+        let partial = path.with_extension("partial");
+        fs::write(&partial, bytes)?;
+        fs::rename(&partial, &path)?;
+        Ok(())
+    }
+
+    pub fn has(&self, cid: &Cid) -> bool {
+        self.blob_path(cid).exists()
+    }
+
+    /// Remove a blob, its manifest and its pin. Returns false if it wasn't stored.
+    pub fn delete(&self, cid: &Cid) -> Result<bool, StoreError> {
+        self.unpin(cid)?;
+        match fs::remove_file(self.manifest_path(cid)) {
+            Ok(()) => {}
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e.into()),
+        }
+        match fs::remove_file(self.blob_path(cid)) {
+            Ok(()) => Ok(true),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(false),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    pub fn pin(&self, cid: &Cid) -> Result<(), StoreError> {
+        if !self.has(cid) {
+            return Err(StoreError::NotFound(*cid));
+        }
+        fs::write(self.pin_path(cid), b"")?;
+        Ok(())
+    }
+
+    pub fn unpin(&self, cid: &Cid) -> Result<bool, StoreError> {
+        match fs::remove_file(self.pin_path(cid)) {
+            Ok(()) => Ok(true),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(false),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    pub fn is_pinned(&self, cid: &Cid) -> bool {
+        self.pin_path(cid).exists()
+    }
+
+    pub fn list(&self) -> Result<Vec<Cid>, StoreError> {
+        let mut cids = Vec::new();
+        for shard in fs::read_dir(&self.blobs)? {
+            for blob in fs::read_dir(shard?.path())? {
+                let name = blob?.file_name();
+                // Skip leftovers from interrupted writes
+                if let Some(cid) = name.to_str().and_then(|name| name.parse().ok()) {
+                    cids.push(cid);
+                }
+            }
+        }
+        cids.sort();
+        Ok(cids)
+    }
+
+    /// Delete every unpinned blob, returning what was removed
+    pub fn gc(&self) -> Result<Vec<Cid>, StoreError> {
+        let mut removed = Vec::new();
+        for cid in self.list()? {
+            if !self.is_pinned(&cid) && self.delete(&cid)? {
+                removed.push(cid);
+            }
+        }
+        Ok(removed)
+    }
+}
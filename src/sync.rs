@@ -0,0 +1,345 @@
+// Document replication over /raggy/doc-sync/1.0.0 using range-based set reconciliation
+use crate::docs::{DocId, Document, Entry};
+use crate::ticket::{find_tickets, DocTicket};
+use libp2p::{
+    request_response::{self, ProtocolSupport},
+    StreamProtocol,
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{hash_map, HashMap, HashSet},
+    time::Duration,
+};
+
+pub const DOC_SYNC_PROTOCOL: StreamProtocol = StreamProtocol::new("/raggy/doc-sync/1.0.0");
+pub const SYNC_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Gossip topic where nodes announce the heads of documents they changed
+pub const DOC_HEADS_TOPIC: &str = "raggy-doc-heads";
+
+/// Ranges holding at most this many entries are sent whole rather than split
+pub const MAX_RANGE_ENTRIES: usize = 16;
+
+/// Most-read entries a responder sends ahead of the first round, so that a
+/// new replica has what its peers use most before the full sync finishes
+pub const HOT_SET_SIZE: usize = 32;
+
+/// Number of sub-ranges a mismatched range is split into
+const SPLIT_FANOUT: usize = 4;
+
+/// XOR of the hashes of every entry in a range, plus how many there are
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Fingerprint {
+    pub hash: [u8; 32],
+    pub count: u64,
+}
+
+impl Fingerprint {
+    pub fn of<'a>(doc: &DocId, entries: impl IntoIterator<Item = &'a Entry>) -> Self {
+        let mut fingerprint = Fingerprint::default();
+        for entry in entries {
+            for (byte, other) in fingerprint.hash.iter_mut().zip(entry.hash(doc)) {
+                *byte ^= other;
+            }
+            fingerprint.count += 1;
+        }
+        fingerprint
+    }
+}
+
+/// Keys in `[lower, upper)`. No upper bound means the rest of the key space.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct KeyRange {
+    pub lower: String,
+    pub upper: Option<String>,
+}
+
+impl KeyRange {
+    pub fn all() -> Self {
+        KeyRange { lower: String::new(), upper: None }
+    }
+
+    /// The range holding `key` and nothing else
+    pub fn key(key: &str) -> Self {
+        KeyRange { lower: key.to_string(), upper: Some(format!("{key}\0")) }
+    }
+
+    fn entries<'a>(&self, doc: &'a Document) -> Vec<&'a Entry> {
+        doc.range(&self.lower, self.upper.as_deref()).collect()
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum RangePayload {
+    Fingerprint(Fingerprint),
+    /// Every entry the sender holds in the range. With `reply` set, the
+    /// receiver answers with the entries it holds that the sender lacks.
+    Entries { entries: Vec<Entry>, reply: bool },
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RangeMessage {
+    pub range: KeyRange,
+    pub payload: RangePayload,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SyncRequest {
+    pub doc: DocId,
+    pub ranges: Vec<RangeMessage>,
+    /// How many of its hottest entries the responder should send up front
+    #[serde(default)]
+    pub hot: usize,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum SyncResponse {
+    Ranges(Vec<RangeMessage>),
+    UnknownDoc,
+    /// The responder's most-read entries, hottest first, followed by the
+    /// usual reply to the request's ranges
+    Prioritised { hot: Vec<Entry>, ranges: Vec<RangeMessage> },
+}
+
+/// Announced on `DOC_HEADS_TOPIC` whenever a document changes
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DocHeads {
+    pub doc: DocId,
+    pub fingerprint: Fingerprint,
+}
+
+pub fn heads(doc: &Document) -> DocHeads {
+    DocHeads { doc: doc.id(), fingerprint: Fingerprint::of(&doc.id(), doc.entries()) }
+}
+
+pub type Behaviour = request_response::cbor::Behaviour<SyncRequest, SyncResponse>;
+
+pub fn new_behaviour() -> Behaviour {
+    request_response::cbor::Behaviour::new(
+        [(DOC_SYNC_PROTOCOL, ProtocolSupport::Full)],
+        request_response::Config::default().with_request_timeout(SYNC_TIMEOUT),
+    )
+}
+
+/// Opening message of a reconciliation: a fingerprint of the whole document
+pub fn start(doc: &Document) -> Vec<RangeMessage> {
+    let range = KeyRange::all();
+    let fingerprint = Fingerprint::of(&doc.id(), range.entries(doc));
+    vec![RangeMessage { range, payload: RangePayload::Fingerprint(fingerprint) }]
+}
+
+/// Ask for the entry under `key` alone, sending ours if we have one, so a
+/// read doesn't have to wait for the whole document to sync
+pub fn fetch(doc: &Document, key: &str) -> Vec<RangeMessage> {
+    let range = KeyRange::key(key);
+    let entries = range.entries(doc).into_iter().cloned().collect();
+    vec![RangeMessage { range, payload: RangePayload::Entries { entries, reply: true } }]
+}
+
+/// Our answer to one round of a peer's messages
+#[derive(Debug, Default)]
+pub struct Step {
+    /// Messages to send back. Empty once the replicas agree.
+    pub replies: Vec<RangeMessage>,
+    /// Entries the peer sent, still to be validated and applied
+    pub received: Vec<Entry>,
+}
+
+/// Answer a round of range messages against our replica. Matching ranges are
+/// dropped, small mismatched ones are answered with their entries and large
+/// ones are split so the next round narrows in on the difference.
+pub fn respond(doc: &Document, incoming: Vec<RangeMessage>) -> Step {
+    let id = doc.id();
+    let mut step = Step::default();
+    for RangeMessage { range, payload } in incoming {
+        let ours = range.entries(doc);
+        match payload {
+            RangePayload::Fingerprint(theirs) => {
+                if Fingerprint::of(&id, ours.iter().copied()) == theirs {
+                    continue;
+                }
+                if ours.len() <= MAX_RANGE_ENTRIES {
+                    let entries = ours.into_iter().cloned().collect();
+                    step.replies.push(RangeMessage { range, payload: RangePayload::Entries { entries, reply: true } });
+                    continue;
+                }
+                step.replies.extend(split(&id, &range, &ours));
+            }
+            RangePayload::Entries { entries, reply } => {
+                if reply {
+                    let theirs: HashSet<[u8; 32]> = entries.iter().map(|e| e.hash(&id)).collect();
+                    let missing: Vec<Entry> = ours.into_iter().filter(|e| !theirs.contains(&e.hash(&id))).cloned().collect();
+                    if !missing.is_empty() {
+                        step.replies.push(RangeMessage {
+                            range,
+                            payload: RangePayload::Entries { entries: missing, reply: false },
+                        });
+                    }
+                }
+                step.received.extend(entries);
+            }
+        }
+    }
+    step
+}
+
+/// Answer a peer's request against our replica, leading with up to
+/// `request.hot` of our most-read entries unless we're already in sync.
+/// Also returns the entries the peer sent, still to be validated and applied.
+pub fn answer(doc: &Document, request: SyncRequest, now: u64) -> (SyncResponse, Vec<Entry>) {
+    let step = respond(doc, request.ranges);
+    let hot = if step.replies.is_empty() { Vec::new() } else { doc.hot_entries(request.hot, now) };
+    let response = if hot.is_empty() {
+        SyncResponse::Ranges(step.replies)
+    } else {
+        SyncResponse::Prioritised { hot, ranges: step.replies }
+    };
+    (response, step.received)
+}
+
+/// Split `range` at our own keys into sub-ranges holding roughly equal
+/// numbers of our entries, each described by its fingerprint
+fn split(id: &DocId, range: &KeyRange, ours: &[&Entry]) -> Vec<RangeMessage> {
+    let chunk = ours.len().div_ceil(SPLIT_FANOUT);
+    let chunks: Vec<&[&Entry]> = ours.chunks(chunk).collect();
+    chunks
+        .iter()
+        .enumerate()
+        .map(|(i, entries)| {
+            let lower = if i == 0 { range.lower.clone() } else { entries[0].key.clone() };
+            let upper = match chunks.get(i + 1) {
+                Some(next) => Some(next[0].key.clone()),
+                None => range.upper.clone(),
+            };
+            RangeMessage {
+                range: KeyRange { lower, upper },
+                payload: RangePayload::Fingerprint(Fingerprint::of(id, entries.iter().copied())),
+            }
+        })
+        .collect()
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Reconciled {
+    /// Messages exchanged, counting each direction separately
+    pub rounds: usize,
+    /// Entries carried in those messages
+    pub transferred: usize,
+    pub applied: usize,
+    pub rejected: usize,
+}
+
+/// Run a full reconciliation between two in-memory replicas of the same
+/// document, as two nodes would over the wire
+pub fn reconcile(a: &mut Document, b: &mut Document) -> Reconciled {
+    let mut stats = Reconciled::default();
+    let mut messages = start(a);
+    let mut to_b = true;
+    while !messages.is_empty() {
+        let receiver = if to_b { &mut *b } else { &mut *a };
+        let step = respond(receiver, messages);
+        stats.transferred += step.received.len();
+        for entry in step.received {
+            match receiver.insert(entry) {
+                Ok(true) => stats.applied += 1,
+                Ok(false) => {}
+                Err(_) => stats.rejected += 1,
+            }
+        }
+        messages = step.replies;
+        to_b = !to_b;
+        stats.rounds += 1;
+    }
+    stats
+}
+
+/// How far a recursive sync has got
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WalkProgress {
+    /// Document the walk started from
+    pub root: DocId,
+    /// Hops from the root to the document that just finished syncing
+    pub depth: usize,
+    pub synced: usize,
+    /// Documents given up on, as no peer we asked could sync them
+    pub failed: usize,
+    /// Documents found so far, synced or not
+    pub discovered: usize,
+}
+
+/// A transitive sync starting from one document. Tickets found in each synced
+/// document are followed up to `max_depth` hops from the root, and every
+/// document is visited once, so reference cycles end the walk. Documents
+/// that can't be synced finish with an error, and the walk completes without
+/// what they reference.
+#[derive(Debug, Clone)]
+pub struct SyncWalk {
+    root: DocId,
+    max_depth: usize,
+    depths: HashMap<DocId, usize>,
+    synced: HashSet<DocId>,
+    failed: HashSet<DocId>,
+}
+
+impl SyncWalk {
+    pub fn new(root: DocId, max_depth: usize) -> Self {
+        SyncWalk {
+            root,
+            max_depth,
+            depths: HashMap::from([(root, 0)]),
+            synced: HashSet::new(),
+            failed: HashSet::new(),
+        }
+    }
+
+    pub fn root(&self) -> DocId {
+        self.root
+    }
+
+    /// Whether the walk has reached `doc`
+    pub fn contains(&self, doc: &DocId) -> bool {
+        self.depths.contains_key(doc)
+    }
+
+    /// Mark `doc` as synced and return the tickets it references that the walk
+    /// should follow next. `None` if the walk wasn't waiting on `doc`. A
+    /// document given up on that syncs after all counts as synced.
+    pub fn synced(&mut self, doc: &Document) -> Option<Vec<DocTicket>> {
+        let depth = *self.depths.get(&doc.id())?;
+        if !self.synced.insert(doc.id()) {
+            return None;
+        }
+        self.failed.remove(&doc.id());
+        if depth >= self.max_depth {
+            return Some(Vec::new());
+        }
+        let mut follow = Vec::new();
+        for ticket in find_tickets(doc) {
+            if let hash_map::Entry::Vacant(slot) = self.depths.entry(ticket.doc) {
+                slot.insert(depth + 1);
+                follow.push(ticket);
+            }
+        }
+        Some(follow)
+    }
+
+    /// Give up on `doc`, such as when no peer has it. Returns whether the
+    /// walk was waiting on it.
+    pub fn failed(&mut self, doc: &DocId) -> bool {
+        self.depths.contains_key(doc) && !self.synced.contains(doc) && self.failed.insert(*doc)
+    }
+
+    pub fn progress(&self, doc: &DocId) -> WalkProgress {
+        WalkProgress {
+            root: self.root,
+            depth: self.depths.get(doc).copied().unwrap_or_default(),
+            synced: self.synced.len(),
+            failed: self.failed.len(),
+            discovered: self.depths.len(),
+        }
+    }
+
+    pub fn is_complete(&self) -> bool {
+        self.synced.len() + self.failed.len() == self.depths.len()
+    }
+}
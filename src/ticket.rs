@@ -0,0 +1,89 @@
+// Shareable capability tickets for documents
+use crate::codec::{from_cbor, to_cbor};
+use crate::docs::{DocError, DocId, Document, Namespace};
+use data_encoding::BASE32_NOPAD;
+use libp2p::{Multiaddr, PeerId};
+use serde::{Deserialize, Serialize};
+use std::{fmt, str::FromStr};
+
+const TICKET_PREFIX: &str = "doc";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ShareMode {
+    Read,
+    Write,
+}
+
+/// What holding a ticket lets you do with the document
+#[derive(Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Capability {
+    Read,
+    /// Carries the namespace secret, so anyone holding the ticket can write
+    Write(#[serde(with = "serde_bytes")] Vec<u8>),
+}
+
+impl fmt::Debug for Capability {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Capability::Read => write!(f, "Read"),
+            Capability::Write(_) => write!(f, "Write(..)"),
+        }
+    }
+}
+
+/// A peer that holds a replica, and where to reach it
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TicketPeer {
+    pub peer: PeerId,
+    pub addrs: Vec<Multiaddr>,
+}
+
+/// Everything needed to join a document: its id, a capability and peers to
+/// sync from. Written as `doc` followed by the base32 of its CBOR encoding.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DocTicket {
+    pub doc: DocId,
+    /// Lets the joiner check who may write before accepting any entries
+    pub namespace: Namespace,
+    pub capability: Capability,
+    pub peers: Vec<TicketPeer>,
+}
+
+impl DocTicket {
+    pub fn mode(&self) -> ShareMode {
+        match self.capability {
+            Capability::Read => ShareMode::Read,
+            Capability::Write(_) => ShareMode::Write,
+        }
+    }
+}
+
+impl fmt::Display for DocTicket {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let bytes = to_cbor(self).map_err(|_| fmt::Error)?;
+        write!(f, "{TICKET_PREFIX}{}", BASE32_NOPAD.encode(&bytes).to_ascii_lowercase())
+    }
+}
+
+impl FromStr for DocTicket {
+    type Err = DocError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let encoded = s
+            .strip_prefix(TICKET_PREFIX)
+            .ok_or_else(|| DocError::InvalidTicket(format!("missing '{TICKET_PREFIX}' prefix")))?;
+        let bytes = BASE32_NOPAD
+            .decode(encoded.to_ascii_uppercase().as_bytes())
+            .map_err(|e| DocError::InvalidTicket(e.to_string()))?;
+        from_cbor(&bytes).map_err(|e| DocError::InvalidTicket(e.to_string()))
+    }
+}
+
+/// Tickets stored as values in `doc`, such as the ones a TicketReadDoc holds
+pub fn find_tickets(doc: &Document) -> Vec<DocTicket> {
+    doc.list("")
+        .into_iter()
+        .filter(|(_, value)| value.starts_with(TICKET_PREFIX.as_bytes()))
+        .filter_map(|(_, value)| String::from_utf8(value).ok()?.parse().ok())
+        .collect()
+}
@@ -0,0 +1,145 @@
+// Views of authored content weighted by how much the viewer trusts each author
+use crate::docs::{AuthorId, Entry};
+use crate::entity::EntityId;
+use crate::graph::{Propagation, TrustGraph};
+use crate::message::RaggyMessage;
+use libp2p::PeerId;
+use std::collections::{BTreeMap, HashMap};
+
+/// Anything whose author's trust decides how much it counts
+pub trait Authored {
+    /// `None` if the author can't be told
+    fn author(&self) -> Option<AuthorId>;
+}
+
+impl Authored for Entry {
+    fn author(&self) -> Option<AuthorId> {
+        Some(self.author)
+    }
+}
+
+/// A gossip message and the peer that signed it, as `NodeEvent::Message`
+/// reports them
+impl Authored for (Option<PeerId>, RaggyMessage) {
+    fn author(&self) -> Option<AuthorId> {
+        AuthorId::from_peer(self.0.as_ref()?)
+    }
+}
+
+/// How trust in entities is worked out over the rail graph
+#[derive(Debug, Clone, PartialEq)]
+pub enum TrustMeasure {
+    /// Personalised PageRank from the viewer, scaled so the most trusted
+    /// entity other than the viewer scores 1
+    PageRank(Propagation),
+    /// Strongest path from the viewer within `max_hops`, see `TrustGraph::decayed_trust`
+    Decayed { max_hops: usize, decay: f64 },
+}
+
+impl Default for TrustMeasure {
+    fn default() -> Self {
+        TrustMeasure::Decayed { max_hops: 3, decay: 0.5 }
+    }
+}
+
+/// How an author's score turns into the weight of what they wrote
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Weighting {
+    /// The weight is the score
+    Linear,
+    /// Everything kept weighs 1
+    Flat,
+    /// The score raised to this power. Above 1 favours the most trusted.
+    Power(f64),
+}
+
+/// Which items a view keeps, and how it weighs them
+#[derive(Debug, Clone, PartialEq)]
+pub struct TrustFilter {
+    /// Items are kept when their author scores above this. The default, 0,
+    /// keeps everyone trusted at all; a negative one keeps everything.
+    pub threshold: f64,
+    pub weighting: Weighting,
+}
+
+impl Default for TrustFilter {
+    fn default() -> Self {
+        TrustFilter { threshold: 0.0, weighting: Weighting::Linear }
+    }
+}
+
+/// An item that passed a `TrustFilter`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Weighted<T> {
+    pub item: T,
+    pub author: Option<AuthorId>,
+    /// The viewer's trust in the author, 0 if unknown
+    pub score: f64,
+    pub weight: f64,
+}
+
+/// One viewer's trust in every author it reaches over the rail graph
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TrustView {
+    scores: HashMap<AuthorId, f64>,
+}
+
+impl TrustView {
+    /// Score authors by the trust `viewer` places in the entities they own,
+    /// taking the best where an author owns several. The viewer's own
+    /// author scores 1. `owners` says who owns each entity.
+    pub fn new(graph: &TrustGraph, viewer: &EntityId, owners: &BTreeMap<EntityId, AuthorId>, measure: &TrustMeasure) -> TrustView {
+        let entity_scores = match measure {
+            TrustMeasure::PageRank(params) => {
+                let mut scores = graph.personalised_trust(viewer, params);
+                scores.remove(viewer);
+                let top = scores.values().copied().fold(0.0, f64::max);
+                if top > 0.0 {
+                    scores.values_mut().for_each(|score| *score /= top);
+                }
+                scores
+            }
+            TrustMeasure::Decayed { max_hops, decay } => graph.decayed_trust(viewer, *max_hops, *decay),
+        };
+
+        let mut scores: HashMap<AuthorId, f64> = HashMap::new();
+        for (entity, score) in entity_scores {
+            let Some(owner) = owners.get(&entity) else { continue };
+            let best = scores.entry(*owner).or_default();
+            *best = best.max(score);
+        }
+        if let Some(owner) = owners.get(viewer) {
+            scores.insert(*owner, 1.0);
+        }
+        TrustView { scores }
+    }
+
+    /// The viewer's trust in `author`, 0 if it doesn't reach them
+    pub fn score(&self, author: &AuthorId) -> f64 {
+        self.scores.get(author).copied().unwrap_or_default()
+    }
+
+    /// Weigh `items` by their authors' scores, dropping those `filter`
+    /// doesn't keep. Items come out in the order they went in, as they are
+    /// pulled, so this works on streams too.
+    pub fn apply<'a, T, I>(&'a self, items: I, filter: &'a TrustFilter) -> impl Iterator<Item = Weighted<T>> + 'a
+    where
+        T: Authored + 'a,
+        I: IntoIterator<Item = T>,
+        I::IntoIter: 'a,
+    {
+        items.into_iter().filter_map(move |item| {
+            let author = item.author();
+            let score = author.map(|author| self.score(&author)).unwrap_or_default();
+            if score <= filter.threshold {
+                return None;
+            }
+            let weight = match filter.weighting {
+                Weighting::Linear => score,
+                Weighting::Flat => 1.0,
+                Weighting::Power(power) => score.max(0.0).powf(power),
+            };
+            Some(Weighted { item, author, score, weight })
+        })
+    }
+}
@@ -0,0 +1,162 @@
+use std::collections::BTreeMap;
+use std::time::{Duration, Instant};
+
+use futures::StreamExt;
+use libp2p::identity::{self, ed25519};
+use libp2p::swarm::dial_opts::DialOpts;
+use libp2p::swarm::{DialError, ListenError, SwarmEvent};
+use libp2p::{noise, tcp, yamux, Multiaddr, PeerId, Swarm, SwarmBuilder};
+use raggy_p2p::access_list::{self, AccessLists, List, Scope, Subject};
+use raggy_p2p::docs::{Docs, ACCESS_LISTS_DOC};
+use raggy_p2p::entity;
+use raggy_p2p::policy::{Action, PolicyConfig, PolicyEngine, Rejection, Rule};
+use raggy_p2p::relationship;
+
+fn peer() -> PeerId {
+    PeerId::from_public_key(&identity::PublicKey::from(ed25519::Keypair::generate().public()))
+}
+
+/// A swarm that only runs the access list gate, listening on a local port
+async fn gated(lists: AccessLists) -> (Swarm<access_list::Behaviour>, Multiaddr) {
+    let mut swarm = SwarmBuilder::with_new_identity()
+        .with_tokio()
+        .with_tcp(tcp::Config::default(), noise::Config::new, yamux::Config::default)
+        .unwrap()
+        .with_behaviour(|_| access_list::Behaviour::new(lists))
+        .unwrap()
+        .with_swarm_config(|config| config.with_idle_connection_timeout(Duration::from_secs(30)))
+        .build();
+    swarm.listen_on("/ip4/127.0.0.1/tcp/0".parse().unwrap()).unwrap();
+    loop {
+        if let SwarmEvent::NewListenAddr { address, .. } = swarm.select_next_some().await {
+            return (swarm, address);
+        }
+    }
+}
+
+fn drive(mut swarm: Swarm<access_list::Behaviour>) {
+    tokio::spawn(async move {
+        loop {
+            swarm.select_next_some().await;
+        }
+    });
+}
+
+#[test]
+fn test_lists_persist_and_judge_peers_and_docs() {
+    let dir = std::env::temp_dir().join(format!("raggy-access-lists-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    let key = ed25519::Keypair::generate();
+    let mut docs = Docs::open(&dir, key.clone()).unwrap();
+    let (friend, spammer, stranger) = (peer(), peer(), peer());
+    let shared = entity::create(&mut docs, "dataset", "weather", BTreeMap::new()).unwrap();
+    let spam = entity::create(&mut docs, "dataset", "spam", BTreeMap::new()).unwrap();
+    let spam_relationships = relationship::open(&mut docs, &spam).unwrap();
+    let plain = docs.create().unwrap();
+
+    let mut lists = AccessLists::load(&docs);
+    assert_eq!(lists, AccessLists::default());
+    lists.set(&mut docs, List::Allow, Subject::Peer(friend), true).unwrap();
+    lists.set(&mut docs, List::Deny, Subject::Peer(spammer), true).unwrap();
+    lists.set(&mut docs, List::Deny, Subject::Entity(spam), true).unwrap();
+    assert!(docs.is_private(&docs.owned_id(ACCESS_LISTS_DOC)));
+
+    assert!(lists.peer_allowed(&friend) && lists.peer_allowed(&stranger));
+    assert!(!lists.peer_allowed(&spammer));
+    assert!(lists.peer_trusted(&friend) && !lists.peer_trusted(&stranger));
+    assert_eq!(lists.denied_peers().collect::<Vec<_>>(), vec![spammer]);
+    assert!(lists.doc_allowed(&docs, &shared) && lists.doc_allowed(&docs, &plain));
+    assert!(!lists.doc_allowed(&docs, &spam));
+    assert!(!lists.doc_allowed(&docs, &spam_relationships), "an entity's relationships go with it");
+
+    // Exclusive allow lists refuse everyone not on them
+    lists.set_exclusive(&mut docs, Scope::Peers, true).unwrap();
+    assert!(lists.peer_allowed(&friend) && !lists.peer_allowed(&stranger));
+    assert!(lists.doc_allowed(&docs, &shared), "entities aren't exclusive");
+
+    // Everything survives a restart
+    drop(docs);
+    let mut docs = Docs::open(&dir, key).unwrap();
+    let mut reloaded = AccessLists::load(&docs);
+    assert_eq!(reloaded, lists);
+    assert!(reloaded.is_exclusive(Scope::Peers) && !reloaded.is_exclusive(Scope::Entities));
+
+    reloaded.set(&mut docs, List::Deny, Subject::Peer(spammer), false).unwrap();
+    reloaded.set_exclusive(&mut docs, Scope::Peers, false).unwrap();
+    assert!(reloaded.subjects(List::Deny).iter().all(|subject| matches!(subject, Subject::Entity(_))));
+    assert_eq!(AccessLists::load(&docs), reloaded);
+}
+
+#[test]
+fn test_policy_applies_access_lists() {
+    let dir = std::env::temp_dir().join(format!("raggy-access-policy-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    let mut docs = Docs::open(&dir, ed25519::Keypair::generate()).unwrap();
+    let (us, friend, spammer, stranger) = (peer(), peer(), peer(), peer());
+
+    let mut lists = AccessLists::default();
+    lists.set(&mut docs, List::Allow, Subject::Peer(friend), true).unwrap();
+    lists.set(&mut docs, List::Deny, Subject::Peer(spammer), true).unwrap();
+    let config = PolicyConfig { gossip: Rule { min_trust: None, min_age: Duration::from_secs(60) }, ..Default::default() };
+    let mut policy = PolicyEngine::new(us, config);
+    policy.set_access_lists(lists.clone());
+    let now = Instant::now();
+
+    // Allowed peers skip the rules; denied ones are refused outright
+    assert_eq!(policy.decide(&friend, Action::Gossip, now), Ok(()));
+    assert!(matches!(policy.decide(&stranger, Action::Gossip, now), Err(Rejection::TooNew { .. })));
+    assert_eq!(policy.decide(&spammer, Action::Write, now), Err(Rejection::Blocked));
+    assert!(policy.is_cut_off(&spammer) && !policy.is_cut_off(&stranger));
+
+    lists.set_exclusive(&mut docs, Scope::Peers, true).unwrap();
+    policy.set_access_lists(lists);
+    assert_eq!(policy.cut_off(&stranger), Some(Rejection::NotAllowed));
+    assert_eq!(policy.cut_off(&us), None, "we are never cut off");
+    assert_eq!(policy.decide(&friend, Action::Write, now), Ok(()));
+}
+
+#[tokio::test]
+async fn test_exclusive_peers_never_connect_to_strangers() {
+    let dir = std::env::temp_dir().join(format!("raggy-access-gate-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    let mut docs = Docs::open(&dir, ed25519::Keypair::generate()).unwrap();
+    let (mut friend, _) = gated(AccessLists::default()).await;
+    let (mut stranger, stranger_addr) = gated(AccessLists::default()).await;
+    let friend_id = *friend.local_peer_id();
+    let stranger_id = *stranger.local_peer_id();
+
+    let mut lists = AccessLists::default();
+    lists.set(&mut docs, List::Allow, Subject::Peer(friend_id), true).unwrap();
+    lists.set_exclusive(&mut docs, Scope::Peers, true).unwrap();
+    let (mut gate, gate_addr) = gated(lists).await;
+
+    // We don't dial strangers...
+    let dial = gate.dial(DialOpts::peer_id(stranger_id).addresses(vec![stranger_addr]).build());
+    assert!(matches!(dial, Err(DialError::Denied { .. })));
+
+    // ...and turn them away before their connection is established
+    stranger.dial(gate_addr.clone()).unwrap();
+    drive(stranger);
+    let denied = tokio::time::timeout(Duration::from_secs(10), async {
+        loop {
+            match gate.select_next_some().await {
+                SwarmEvent::IncomingConnectionError { error: ListenError::Denied { cause }, .. } => return cause,
+                SwarmEvent::ConnectionEstablished { peer_id, .. } => panic!("{peer_id} connected"),
+                _ => {}
+            }
+        }
+    });
+    assert!(matches!(denied.await.unwrap().downcast::<Rejection>(), Ok(Rejection::NotAllowed)));
+
+    // Peers on the allow list connect as usual
+    friend.dial(gate_addr).unwrap();
+    drive(friend);
+    let connected = tokio::time::timeout(Duration::from_secs(10), async {
+        loop {
+            if let SwarmEvent::ConnectionEstablished { peer_id, .. } = gate.select_next_some().await {
+                return peer_id;
+            }
+        }
+    });
+    assert_eq!(connected.await.unwrap(), friend_id);
+}
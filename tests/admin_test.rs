@@ -0,0 +1,54 @@
+use std::collections::HashSet;
+use std::sync::Arc;
+
+use libp2p::PeerId;
+use raggy_p2p::access_list::{List, Scope, Subject};
+use raggy_p2p::{admin, NodeHandle};
+use tokio::sync::Mutex;
+
+fn spawn_node(name: &str) -> (NodeHandle, tokio::task::JoinHandle<()>) {
+    let (handle, inbox) = NodeHandle::new();
+    let dir = std::env::temp_dir().join(format!("raggy-admin-{name}-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    let args = vec!["test", "--name", name, "--data-dir", dir.to_str().unwrap()];
+    let args = args.into_iter().map(String::from).collect();
+    let task = tokio::spawn(async move {
+        raggy_p2p::run_node_with_handle(args, Arc::new(Mutex::new(HashSet::new())), inbox).await.unwrap();
+    });
+    (handle, task)
+}
+
+#[tokio::test]
+async fn test_console_edits_the_access_lists() {
+    let (node, task) = spawn_node("console");
+    let (friend, stranger) = (PeerId::random(), PeerId::random());
+    let script = format!("allow peer/{friend}\n\ndeny peer/{stranger}\nexclusive peers on\nundeny peer/{stranger}\nlists\n");
+    let mut output = Vec::new();
+    admin::serve(node.clone(), script.as_bytes(), &mut output).await.unwrap();
+
+    let output = String::from_utf8(output).unwrap();
+    let answers: Vec<&str> = output.lines().collect();
+    assert_eq!(answers[..4], ["ok"; 4], "blank lines aren't answered");
+    assert_eq!(answers[4..], [format!("allow peer/{friend}"), "exclusive peers".to_string()]);
+
+    let lists = node.access_lists().await.unwrap();
+    assert!(lists.subjects(List::Allow).contains(&Subject::Peer(friend)));
+    assert!(lists.subjects(List::Deny).is_empty());
+    assert!(lists.is_exclusive(Scope::Peers) && !lists.is_exclusive(Scope::Entities));
+
+    task.abort();
+}
+
+#[tokio::test]
+async fn test_console_reports_bad_commands() {
+    let (node, task) = spawn_node("usage");
+    for line in ["block", "deny someone", "exclusive docs on", "exclusive peers maybe", "restore not-a-peer"] {
+        let result = admin::execute(&node, line).await;
+        assert!(matches!(result, Err(admin::AdminError::Usage(_))), "{line} should be refused");
+    }
+    assert_eq!(admin::execute(&node, "lists").await.unwrap(), "lists are empty");
+    let cut_off = admin::execute(&node, &format!("defederate {} subtree spam", PeerId::random())).await.unwrap();
+    assert_eq!(cut_off, "cut off 1 peer(s)");
+
+    task.abort();
+}
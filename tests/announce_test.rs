@@ -0,0 +1,47 @@
+use libp2p::{identity::ed25519, PeerId};
+use raggy_p2p::announce::{join_all, join_peers, JoinAnnouncement};
+use raggy_p2p::codec::{from_cbor, to_cbor};
+use raggy_p2p::docs::{DocError, Docs, MAIN_DOC, TICKET_READ_DOC};
+use raggy_p2p::ticket::{DocTicket, ShareMode};
+
+fn owned_ticket(node: &str, key: &ed25519::Keypair, label: &str) -> DocTicket {
+    let dir = std::env::temp_dir().join(format!("raggy-announce-{node}-{label}-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    let mut docs = Docs::open(&dir, key.clone()).unwrap();
+    let doc = docs.create_owned(label).unwrap();
+    docs.share(&doc, ShareMode::Read, Vec::new()).unwrap()
+}
+
+#[test]
+fn test_announcement_verifies_after_round_trip() {
+    let key = ed25519::Keypair::generate();
+    let announcement = JoinAnnouncement::new(&key, "node1", owned_ticket("node1", &key, TICKET_READ_DOC));
+    let decoded: JoinAnnouncement = from_cbor(&to_cbor(&announcement).unwrap()).unwrap();
+    assert_eq!(decoded, announcement);
+    assert!(decoded.verify().is_ok());
+    assert!(join_all()(&decoded));
+    assert!(join_peers([decoded.peer_id])(&decoded));
+    assert!(!join_peers([PeerId::random()])(&decoded));
+}
+
+#[test]
+fn test_tampered_announcements_are_rejected() {
+    let key = ed25519::Keypair::generate();
+    let announcement = JoinAnnouncement::new(&key, "node1", owned_ticket("tampered", &key, TICKET_READ_DOC));
+
+    let mut renamed = announcement.clone();
+    renamed.name = "node2".to_string();
+    assert!(matches!(renamed.verify(), Err(DocError::InvalidSignature)));
+
+    // Claiming to be another peer doesn't work without its key
+    let mut impersonated = announcement.clone();
+    impersonated.peer_id = PeerId::random();
+    assert!(matches!(impersonated.verify(), Err(DocError::InvalidSignature)));
+
+    // Only the announcer's own TicketReadDoc may be announced, even if signed
+    let other = ed25519::Keypair::generate();
+    let foreign = JoinAnnouncement::new(&key, "node1", owned_ticket("foreign", &other, TICKET_READ_DOC));
+    assert!(matches!(foreign.verify(), Err(DocError::InvalidTicket(_))));
+    let main_doc = JoinAnnouncement::new(&key, "node1", owned_ticket("main", &key, MAIN_DOC));
+    assert!(matches!(main_doc.verify(), Err(DocError::InvalidTicket(_))));
+}
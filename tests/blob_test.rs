@@ -0,0 +1,79 @@
+use std::path::PathBuf;
+
+use raggy_p2p::blob::{self, BlobError, BlobHash, BlobRequest, BlobResponse, Download, CHUNK_SIZE};
+use raggy_p2p::store::BlobStore;
+
+fn sample_blob() -> Vec<u8> {
+    (0..(CHUNK_SIZE * 2 + 100)).map(|i| (i % 251) as u8).collect()
+}
+
+fn data_dir(test: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("raggy-blob-{test}-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    dir
+}
+
+/// Answer `request` with the blob `hash` instead of the one asked for
+fn serve_other(store: &BlobStore, hash: BlobHash, request: &BlobRequest) -> BlobResponse {
+    let request = match request {
+        BlobRequest::Manifest { .. } => BlobRequest::Manifest { hash },
+        BlobRequest::Chunk { index, .. } => BlobRequest::Chunk { hash, index: *index },
+    };
+    blob::serve(store, &request).unwrap()
+}
+
+#[test]
+fn test_download_resumes_and_verifies_chunks() {
+    let data = sample_blob();
+    let store = BlobStore::open(&data_dir("resume")).unwrap();
+    store.put(&data).unwrap();
+    let downloads = data_dir("resume-downloads");
+    let hash = blob::blob_hash(&data);
+    let mut download = Download::open(&downloads, hash).unwrap();
+
+    // Manifest first, then the first chunk
+    let request = download.next_request().unwrap();
+    assert!(matches!(request, BlobRequest::Manifest { .. }));
+    download.on_response(blob::serve(&store, &request).unwrap()).unwrap();
+    let request = download.next_request().unwrap();
+    let progress = download.on_response(blob::serve(&store, &request).unwrap()).unwrap();
+    assert_eq!(progress.chunks_received, 1);
+    assert_eq!(progress.chunks_total, 3);
+    assert_eq!(progress.total_bytes, data.len() as u64);
+
+    // A corrupted chunk is rejected and asked for again
+    let request = download.next_request().unwrap();
+    assert!(matches!(request, BlobRequest::Chunk { index: 1, .. }));
+    let corrupted = BlobResponse::Chunk { index: 1, data: vec![0; CHUNK_SIZE] };
+    assert!(matches!(download.on_response(corrupted), Err(BlobError::ChunkHashMismatch { index: 1 })));
+    assert!(matches!(download.next_request(), Some(BlobRequest::Chunk { index: 1, .. })));
+
+    // What arrived is on disk, so a restarted node carries on where it stopped
+    drop(download);
+    let mut download = Download::open(&downloads, hash).unwrap();
+    assert_eq!(download.progress().chunks_received, 1);
+    assert_eq!(download.progress().received_bytes, CHUNK_SIZE as u64);
+    assert!(matches!(download.next_request(), Some(BlobRequest::Chunk { index: 1, .. })));
+
+    while let Some(request) = download.next_request() {
+        download.on_response(blob::serve(&store, &request).unwrap()).unwrap();
+    }
+    assert!(download.is_complete());
+    assert_eq!(download.finish().unwrap(), data);
+    download.discard().unwrap();
+    assert_eq!(std::fs::read_dir(&downloads).unwrap().count(), 0);
+}
+
+#[test]
+fn test_forged_manifest_is_detected() {
+    let data = sample_blob();
+    let store = BlobStore::open(&data_dir("forged")).unwrap();
+    let forged = store.put(b"not the blob we asked for").unwrap();
+    let mut download = Download::open(&data_dir("forged-downloads"), blob::blob_hash(&data)).unwrap();
+
+    while let Some(request) = download.next_request() {
+        download.on_response(serve_other(&store, forged.digest(), &request)).unwrap();
+    }
+    assert!(matches!(download.finish(), Err(BlobError::HashMismatch)));
+    assert!(matches!(download.next_request(), Some(BlobRequest::Manifest { .. })));
+}
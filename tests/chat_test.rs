@@ -0,0 +1,71 @@
+mod common;
+
+use std::time::{Duration, Instant};
+
+use raggy_p2p::{NodeError, RaggyMessage};
+
+use common::{gossiper, spawn_node, Messages};
+
+const CHAT_TOPIC: &str = "raggy-chat";
+
+/// Wait up to `timeout` for `text` to reach `messages`
+async fn received(messages: &Messages, text: &str, timeout: Duration) -> bool {
+    let deadline = Instant::now() + timeout;
+    while Instant::now() < deadline {
+        if messages.lock().await.contains(text) {
+            return true;
+        }
+        tokio::time::sleep(Duration::from_millis(200)).await;
+    }
+    false
+}
+
+#[tokio::test]
+async fn test_published_chat_reaches_peers() {
+    let _ = env_logger::try_init();
+
+    let (node1, _, task1) = spawn_node(8061, "talker", &[]);
+    let (_node2, messages2, task2) = spawn_node(8062, "listener", &[]);
+
+    // Publishing fails until the nodes share the topic
+    let deadline = Instant::now() + Duration::from_secs(20);
+    loop {
+        match node1.publish_chat("hello listener").await {
+            Ok(()) => break,
+            Err(NodeError::Gossip(e)) if Instant::now() < deadline => {
+                log::debug!("not published yet: {e}");
+                tokio::time::sleep(Duration::from_millis(300)).await;
+            }
+            Err(e) => panic!("chat was never published: {e}"),
+        }
+    }
+
+    assert!(received(&messages2, "hello listener", Duration::from_secs(10)).await, "the peer should receive the chat");
+
+    task1.abort();
+    task2.abort();
+}
+
+#[tokio::test]
+async fn test_unsupported_messages_are_ignored() {
+    let _ = env_logger::try_init();
+
+    let (_node, messages, node_task) = spawn_node(8063, "reader", &[]);
+    tokio::time::sleep(Duration::from_secs(1)).await;
+    let (_, future_task) = gossiper(8063, CHAT_TOPIC, |n| {
+        let mut message = RaggyMessage::chat("future", &format!("from the future {n}"));
+        message.version += 1;
+        message.encode().unwrap()
+    });
+    let (_, current_task) = gossiper(8063, CHAT_TOPIC, |n| RaggyMessage::chat("current", &format!("from now {n}")).encode().unwrap());
+
+    assert!(received(&messages, "from now 0", Duration::from_secs(20)).await, "supported messages should be handled");
+    // Give the newer build's messages the same head start
+    tokio::time::sleep(Duration::from_secs(2)).await;
+    let messages = messages.lock().await;
+    assert!(!messages.iter().any(|text| text.starts_with("from the future")), "unsupported messages should be ignored: {messages:?}");
+
+    node_task.abort();
+    future_task.abort();
+    current_task.abort();
+}
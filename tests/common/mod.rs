@@ -0,0 +1,123 @@
+// Helpers shared by the integration tests. Each test binary uses its own
+// subset, so unused ones are expected.
+#![allow(dead_code)]
+
+use std::collections::HashSet;
+use std::sync::Arc;
+use std::time::Duration;
+
+use futures::StreamExt;
+use libp2p::gossipsub::{self, IdentTopic, MessageAuthenticity};
+use libp2p::identity::ed25519;
+use libp2p::swarm::SwarmEvent;
+use libp2p::{noise, tcp, yamux, PeerId, SwarmBuilder};
+use raggy_p2p::docs::{DocId, Docs};
+use raggy_p2p::rails;
+use raggy_p2p::sync::reconcile;
+use raggy_p2p::ticket::ShareMode;
+use raggy_p2p::NodeHandle;
+use tokio::sync::Mutex;
+use tokio::task::JoinHandle;
+
+/// Open an empty store for `node` in `test`, under a temp dir named after the
+/// test file's `prefix`, with the rail validators if `register` is set
+pub fn open_docs(prefix: &str, test: &str, node: &str, register: bool) -> Docs {
+    open_docs_with_key(prefix, test, node, &ed25519::Keypair::generate(), register)
+}
+
+/// [`open_docs`], writing as `key`
+pub fn open_docs_with_key(prefix: &str, test: &str, node: &str, key: &ed25519::Keypair, register: bool) -> Docs {
+    let dir = std::env::temp_dir().join(format!("raggy-{prefix}-{test}-{node}-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    let mut docs = Docs::open(&dir, key.clone()).unwrap();
+    if register {
+        rails::register(&mut docs);
+    }
+    docs
+}
+
+/// Join `to` to `from`'s replica of `id` if it hasn't joined it yet
+fn join(from: &Docs, to: &mut Docs, id: &DocId) {
+    if to.doc(id).is_none() {
+        to.join(&from.share(id, ShareMode::Read, Vec::new()).unwrap()).unwrap();
+    }
+}
+
+/// Bring `to`'s replica of `id` up to date with `from`'s, joining it first if
+/// needed. `to` may not reject anything.
+pub fn propagate(from: &Docs, to: &mut Docs, id: &DocId) {
+    join(from, to, id);
+    let mut source = from.doc(id).unwrap().clone();
+    let mut replica = to.doc(id).unwrap().clone();
+    reconcile(&mut replica, &mut source);
+    let (_, rejected) = to.apply(id, replica.entries().cloned().collect()).unwrap();
+    assert!(rejected.is_empty(), "rejected: {rejected:?}");
+}
+
+/// Reconcile both nodes' replicas of `id` as a sync session between them
+/// would, joining `b` to it first if needed. Neither side may reject anything.
+pub fn sync(a: &mut Docs, b: &mut Docs, id: &DocId) {
+    join(a, b, id);
+    let mut replica_a = a.doc(id).unwrap().clone();
+    let mut replica_b = b.doc(id).unwrap().clone();
+    reconcile(&mut replica_a, &mut replica_b);
+    for (docs, replica) in [(a, replica_a), (b, replica_b)] {
+        let (_, rejected) = docs.apply(id, replica.entries().cloned().collect()).unwrap();
+        assert!(rejected.is_empty(), "rejected: {rejected:?}");
+    }
+}
+
+/// Chat messages a node received, through its message callback
+pub type Messages = Arc<Mutex<HashSet<String>>>;
+
+/// Start a node on `port`, with `extra` command line arguments
+pub fn spawn_node(port: u16, name: &str, extra: &[&str]) -> (NodeHandle, Messages, JoinHandle<()>) {
+    let (handle, inbox) = NodeHandle::new();
+    let messages = Messages::default();
+    let mut args = vec!["test".to_string(), "--port".to_string(), port.to_string(), "--name".to_string(), name.to_string()];
+    args.extend(extra.iter().map(|arg| arg.to_string()));
+    let callback = messages.clone();
+    let task = tokio::spawn(async move {
+        raggy_p2p::run_node_with_handle(args, callback, inbox).await.unwrap();
+    });
+    (handle, messages, task)
+}
+
+/// A bare gossipsub peer that dials the node on `port` and, once the node is
+/// subscribed to `topic`, publishes `message(n)` every 300ms
+pub fn gossiper<F>(port: u16, topic: &str, message: F) -> (PeerId, JoinHandle<()>)
+where
+    F: Fn(u64) -> Vec<u8> + Send + 'static,
+{
+    let mut swarm = SwarmBuilder::with_new_identity()
+        .with_tokio()
+        .with_tcp(tcp::Config::default(), noise::Config::new, yamux::Config::default)
+        .unwrap()
+        .with_behaviour(|key| <gossipsub::Behaviour>::new(MessageAuthenticity::Signed(key.clone()), gossipsub::Config::default()).unwrap())
+        .unwrap()
+        .with_swarm_config(|config| config.with_idle_connection_timeout(Duration::from_secs(60)))
+        .build();
+    let peer = *swarm.local_peer_id();
+    let topic = IdentTopic::new(topic);
+    swarm.behaviour_mut().subscribe(&topic).unwrap();
+    swarm.dial(format!("/ip4/127.0.0.1/tcp/{port}").parse::<libp2p::Multiaddr>().unwrap()).unwrap();
+    let task = tokio::spawn(async move {
+        let mut publish = tokio::time::interval(Duration::from_millis(300));
+        let mut ready = false;
+        let mut n = 0;
+        loop {
+            tokio::select! {
+                event = swarm.select_next_some() => {
+                    if let SwarmEvent::Behaviour(gossipsub::Event::Subscribed { topic: subscribed, .. }) = event {
+                        ready |= subscribed == topic.hash();
+                    }
+                }
+                _ = publish.tick(), if ready => {
+                    let _ = swarm.behaviour_mut().publish(topic.clone(), message(n));
+                    n += 1;
+                }
+            }
+        }
+    });
+    (peer, task)
+}
@@ -0,0 +1,109 @@
+use std::path::PathBuf;
+
+use libp2p::identity::ed25519;
+use raggy_p2p::docs::{AuthorId, DocError, DocId, Docs, Document, Entry};
+use raggy_p2p::message::now_millis;
+
+fn data_dir(test: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("raggy-docs-{test}-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    dir
+}
+
+/// A new document and the namespace keypair that can write to it
+fn writable_doc() -> (ed25519::Keypair, Document) {
+    let namespace = ed25519::Keypair::generate();
+    let doc = Document::with_namespace(namespace.secret().as_ref()).unwrap();
+    (namespace, doc)
+}
+
+#[test]
+fn test_last_writer_wins_regardless_of_arrival_order() {
+    let (namespace, doc) = writable_doc();
+    let alice = ed25519::Keypair::generate();
+    let bob = ed25519::Keypair::generate();
+    let older = Entry::new(&namespace, "name", Some(b"alice".to_vec()), &alice, 1_000);
+    let newer = Entry::new(&namespace, "name", Some(b"bob".to_vec()), &bob, 2_000);
+
+    let mut first = doc.clone();
+    assert!(first.insert(older.clone()).unwrap());
+    assert!(first.insert(newer.clone()).unwrap());
+    let mut second = doc.clone();
+    assert!(second.insert(newer).unwrap());
+    assert!(!second.insert(older).unwrap(), "An older write must not replace a newer one");
+
+    assert_eq!(first.get("name"), Some(&b"bob"[..]));
+    assert_eq!(second.get("name"), Some(&b"bob"[..]));
+
+    // Equal timestamps are broken by author so every replica agrees
+    let a = Entry::new(&namespace, "tie", Some(b"a".to_vec()), &alice, 5_000);
+    let b = Entry::new(&namespace, "tie", Some(b"b".to_vec()), &bob, 5_000);
+    let winner = if AuthorId::of(&alice) > AuthorId::of(&bob) { "a" } else { "b" };
+    first.insert(a.clone()).unwrap();
+    first.insert(b.clone()).unwrap();
+    second.insert(b).unwrap();
+    second.insert(a).unwrap();
+    assert_eq!(first.get("tie"), Some(winner.as_bytes()));
+    assert_eq!(second.get("tie"), Some(winner.as_bytes()));
+}
+
+#[test]
+fn test_tampered_entries_are_rejected() {
+    let (namespace, mut doc) = writable_doc();
+    let author = ed25519::Keypair::generate();
+    let mut entry = Entry::new(&namespace, "key", Some(b"value".to_vec()), &author, 1);
+    entry.value = Some(b"forged".to_vec());
+    assert!(matches!(doc.insert(entry), Err(DocError::InvalidSignature)));
+
+    // An entry signed for one document doesn't verify in another
+    let (other, _) = writable_doc();
+    let entry = Entry::new(&other, "key", Some(b"value".to_vec()), &author, 1);
+    assert!(matches!(doc.insert(entry), Err(DocError::InvalidSignature)));
+}
+
+#[test]
+fn test_entries_from_the_future_are_rejected() {
+    let (namespace, mut doc) = writable_doc();
+    let author = ed25519::Keypair::generate();
+    let now = now_millis();
+    let far = Entry::new(&namespace, "key", Some(b"forever".to_vec()), &author, now + 3_600_000);
+    assert!(matches!(doc.insert(far), Err(DocError::InvalidTimestamp(_))));
+    let last = Entry::new(&namespace, "key", Some(b"forever".to_vec()), &author, u64::MAX);
+    assert!(matches!(doc.insert(last), Err(DocError::InvalidTimestamp(u64::MAX))));
+
+    // A little clock skew is tolerated, and a local write still lands after it
+    let ahead = Entry::new(&namespace, "key", Some(b"ahead".to_vec()), &author, now + 60_000);
+    assert!(doc.insert(ahead).unwrap());
+    let written = doc.write(&author, "key", Some(b"local".to_vec())).unwrap();
+    assert_eq!(written.timestamp, now + 60_001);
+    assert_eq!(doc.get("key"), Some(&b"local"[..]));
+}
+
+#[test]
+fn test_docs_crud_and_persistence() {
+    let dir = data_dir("crud");
+    let author = ed25519::Keypair::generate();
+    let mut docs = Docs::open(&dir, author.clone()).unwrap();
+    let id = docs.create().unwrap();
+
+    docs.set(&id, "peer/name", b"node1".to_vec()).unwrap();
+    docs.set(&id, "peer/port", b"4001".to_vec()).unwrap();
+    docs.set(&id, "tickets/read", b"ticket".to_vec()).unwrap();
+    docs.set(&id, "peer/name", b"node1-renamed".to_vec()).unwrap();
+    docs.delete(&id, "peer/port").unwrap();
+
+    assert_eq!(docs.get(&id, "peer/name").unwrap(), Some(b"node1-renamed".to_vec()));
+    assert_eq!(docs.get(&id, "peer/port").unwrap(), None);
+    assert_eq!(docs.list(&id, "peer/").unwrap(), vec![("peer/name".to_string(), b"node1-renamed".to_vec())]);
+    assert_eq!(docs.take_changes().len(), 5);
+
+    let missing = Document::new().id();
+    assert!(matches!(docs.get(&missing, "peer/name"), Err(DocError::UnknownDoc(_))));
+
+    // Documents survive a restart under the same id
+    drop(docs);
+    let docs = Docs::open(&dir, author).unwrap();
+    assert_eq!(docs.ids(), vec![id]);
+    assert_eq!(docs.get(&id, "peer/name").unwrap(), Some(b"node1-renamed".to_vec()));
+    assert_eq!(id.to_string().parse::<DocId>().unwrap(), id);
+}
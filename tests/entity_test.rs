@@ -0,0 +1,69 @@
+mod common;
+
+use std::collections::BTreeMap;
+
+use raggy_p2p::docs::{DocError, TICKET_READ_DOC};
+use raggy_p2p::entity::{self, AttrValue, EntityUpdate};
+use raggy_p2p::ticket::find_tickets;
+
+use common::{open_docs, propagate};
+
+const PREFIX: &str = "entity";
+
+fn attrs(pairs: &[(&str, AttrValue)]) -> BTreeMap<String, AttrValue> {
+    pairs.iter().map(|(name, value)| (name.to_string(), value.clone())).collect()
+}
+
+#[test]
+fn test_create_update_and_query_entities() {
+    let mut docs = open_docs(PREFIX, "crud", "node1", false);
+    docs.create_owned(TICKET_READ_DOC).unwrap();
+    let alice = entity::create(&mut docs, "person", "Alice", attrs(&[("city", AttrValue::Text("Oslo".into()))])).unwrap();
+    let bob = entity::create(&mut docs, "person", "Bob", attrs(&[("city", AttrValue::Text("Rome".into()))])).unwrap();
+    assert_ne!(alice, bob);
+
+    let update = EntityUpdate::default()
+        .name("Alice Smith")
+        .set_attr("age", AttrValue::Int(41))
+        .link("friend", bob);
+    entity::update(&mut docs, &alice, update).unwrap();
+
+    let entity = entity::get(&docs, &alice).unwrap().unwrap();
+    assert_eq!(entity.owner, docs.author());
+    assert_eq!((entity.meta.kind.as_str(), entity.meta.name.as_str()), ("person", "Alice Smith"));
+    assert_eq!(entity.attrs.get("age"), Some(&AttrValue::Int(41)));
+    assert_eq!(entity.links.get("friend"), Some(&bob));
+
+    let in_rome = entity::query(&docs, "city", &AttrValue::Text("Rome".into()));
+    assert_eq!(in_rome.iter().map(|e| e.id).collect::<Vec<_>>(), vec![bob]);
+
+    entity::update(&mut docs, &alice, EntityUpdate::default().remove_attr("city").unlink("friend")).unwrap();
+    let entity = entity::get(&docs, &alice).unwrap().unwrap();
+    assert!(!entity.attrs.contains_key("city") && entity.links.is_empty());
+
+    // Both entities are listed in the TicketReadDoc; plain documents aren't entities
+    let tickets = find_tickets(docs.doc(&docs.owned_id(TICKET_READ_DOC)).unwrap());
+    assert_eq!(tickets.len(), 2);
+    let plain = docs.create().unwrap();
+    assert_eq!(entity::get(&docs, &plain).unwrap(), None);
+}
+
+#[test]
+fn test_entities_propagate_to_another_node() {
+    let mut node1 = open_docs(PREFIX, "propagate", "node1", false);
+    let mut node2 = open_docs(PREFIX, "propagate", "node2", false);
+    let id = entity::create(&mut node1, "dataset", "weather", attrs(&[("public", AttrValue::Bool(true))])).unwrap();
+
+    propagate(&node1, &mut node2, &id);
+    let replicated = entity::get(&node2, &id).unwrap().unwrap();
+    assert_eq!(Some(replicated.clone()), entity::get(&node1, &id).unwrap());
+    assert_eq!(replicated.owner, node1.author());
+    assert_eq!(entity::query(&node2, "public", &AttrValue::Bool(true)).len(), 1);
+
+    // Only the owner can change it, and its updates follow
+    let update = EntityUpdate::default().set_attr("rows", AttrValue::Int(1_000));
+    assert!(matches!(entity::update(&mut node2, &id, update.clone()), Err(DocError::NotOwner(_))));
+    entity::update(&mut node1, &id, update).unwrap();
+    propagate(&node1, &mut node2, &id);
+    assert_eq!(entity::get(&node2, &id).unwrap().unwrap().attrs.get("rows"), Some(&AttrValue::Int(1_000)));
+}
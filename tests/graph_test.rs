@@ -0,0 +1,107 @@
+use std::collections::BTreeMap;
+
+use libp2p::identity::ed25519;
+use raggy_p2p::docs::{DocId, Docs};
+use raggy_p2p::entity::{self, EntityId};
+use raggy_p2p::graph::{Propagation, TrustGraph};
+use raggy_p2p::rail_index::RailIndex;
+use raggy_p2p::rail_kind::{KindSpec, RailKind, RailKinds};
+use raggy_p2p::rails::{self, RailMetadata};
+
+fn id(n: u8) -> EntityId {
+    DocId::from_bytes([n; 32])
+}
+
+fn graph(edges: &[(u8, u8, f64)]) -> TrustGraph {
+    let mut graph = TrustGraph::default();
+    for (from, to, weight) in edges {
+        graph.add_edge(id(*from), id(*to), *weight);
+    }
+    graph
+}
+
+fn max(weights: &[f64]) -> f64 {
+    weights.iter().copied().fold(f64::MIN, f64::max)
+}
+
+fn assert_close(actual: f64, expected: f64) {
+    assert!((actual - expected).abs() < 1e-6, "{actual} != {expected}");
+}
+
+#[test]
+fn test_personalised_trust() {
+    // With one edge out of the source and nothing out of its target, the
+    // walker bounces between the two: 1 / (1 + d) and d / (1 + d)
+    let scores = graph(&[(1, 2, 0.5)]).personalised_trust(&id(1), &Propagation::default());
+    assert_close(scores[&id(1)], 1.0 / 1.85);
+    assert_close(scores[&id(2)], 0.85 / 1.85);
+
+    let trust = graph(&[(1, 2, 0.9), (1, 3, 0.1), (2, 4, 1.0), (5, 1, 1.0)]);
+    let scores = trust.personalised_trust(&id(1), &Propagation::default());
+    assert!(scores[&id(2)] > scores[&id(3)]);
+    assert!(scores[&id(4)] > scores[&id(3)]);
+    assert!(!scores.contains_key(&id(5)), "nobody the source trusts trusts 5");
+    assert_close(scores.values().sum(), 1.0);
+    assert_eq!(scores, trust.personalised_trust(&id(1), &Propagation::default()));
+}
+
+#[test]
+fn test_neighbourhood() {
+    let trust = graph(&[(1, 2, 1.0), (2, 3, 0.5), (1, 4, 0.2), (3, 5, 0.0), (6, 1, 1.0)]);
+    assert_eq!(trust.neighbourhood(&id(1), 0), [(id(1), 0)].into());
+    assert_eq!(trust.neighbourhood(&id(1), 1), [(id(1), 0), (id(2), 1), (id(4), 1)].into());
+    assert_eq!(
+        trust.neighbourhood(&id(1), 3),
+        [(id(1), 0), (id(2), 1), (id(4), 1), (id(3), 2), (id(5), 3)].into()
+    );
+}
+
+#[test]
+fn test_strongest_path_and_decay() {
+    let trust = graph(&[(1, 2, 1.0), (2, 3, 0.5), (1, 4, 0.2), (4, 3, 1.0), (1, 3, 0.3)]);
+
+    // Without decay, going through 2 keeps the most trust...
+    let path = trust.strongest_path(&id(1), &id(3), 1.0).unwrap();
+    assert_eq!(path.entities, vec![id(1), id(2), id(3)]);
+    assert_close(path.strength, 0.5);
+
+    // ...but halving trust at every further hop makes the direct edge stronger
+    let path = trust.strongest_path(&id(1), &id(3), 0.5).unwrap();
+    assert_eq!(path.entities, vec![id(1), id(3)]);
+    assert_close(path.strength, 0.3);
+
+    let to_self = trust.strongest_path(&id(1), &id(1), 0.5).unwrap();
+    assert_eq!(to_self.entities, vec![id(1)]);
+    assert_eq!(trust.strongest_path(&id(3), &id(1), 1.0), None);
+
+    let reach = trust.decayed_trust(&id(1), 1, 0.5);
+    assert_eq!(reach, [(id(2), 1.0), (id(3), 0.3), (id(4), 0.2)].into());
+    let reach = trust.decayed_trust(&id(1), 2, 1.0);
+    assert_close(reach[&id(3)], 0.5);
+}
+
+#[test]
+fn test_graph_from_rails() {
+    let dir = std::env::temp_dir().join(format!("raggy-graph-rails-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    let mut docs = Docs::open(&dir, ed25519::Keypair::generate()).unwrap();
+    rails::register(&mut docs);
+    let alice = entity::create(&mut docs, "person", "Alice", BTreeMap::new()).unwrap();
+    let bob = entity::create(&mut docs, "person", "Bob", BTreeMap::new()).unwrap();
+
+    // Two rails of a kind that keeps the strongest weight, and one of a kind that keeps the mean
+    let strongest: RailKind = "test.raggy/strongest".parse().unwrap();
+    let mean: RailKind = "test.raggy/mean".parse().unwrap();
+    let mut kinds = RailKinds::default();
+    kinds.register(strongest.clone(), KindSpec { schema: None, combine: Some(max) });
+    kinds.register(mean.clone(), KindSpec::default());
+    for (kind, weight) in [(&strongest, 0.2), (&strongest, 0.6), (&mean, 0.4)] {
+        let rail = rails::create_rail(&mut docs, kind, &[alice, bob], RailMetadata::new()).unwrap();
+        rails::set_weight(&mut docs, &rail, &alice, &bob, weight).unwrap();
+    }
+
+    let trust = TrustGraph::from_index(&RailIndex::build(&docs), &kinds);
+    assert_close(trust.weight(&alice, &bob).unwrap(), 0.5);
+    assert_eq!(trust.weight(&bob, &alice), None);
+    assert_eq!(trust.entities().len(), 2);
+}
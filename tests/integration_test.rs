@@ -1,66 +1,68 @@
 use std::collections::HashSet;
 use std::sync::Arc;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 use tokio::sync::Mutex;
 use tokio::time::sleep;
 
 // Import the main binary as a module
-use raggy_p2p;
+use raggy_p2p::docs::{DocId, Namespace};
+use raggy_p2p::policy::{Action, PolicyConfig, Rejection, Rule};
+use raggy_p2p::ticket::{Capability, DocTicket, ShareMode};
+use raggy_p2p::{self, NodeEvent, NodeHandle};
+
+fn spawn_node(port: u16, name: &str) -> (NodeHandle, tokio::task::JoinHandle<()>) {
+    spawn_node_with(port, name, &[])
+}
+
+fn spawn_node_with(port: u16, name: &str, extra: &[&str]) -> (NodeHandle, tokio::task::JoinHandle<()>) {
+    let (handle, inbox) = NodeHandle::new();
+    let messages = Arc::new(Mutex::new(HashSet::new()));
+    let mut args = vec!["test".to_string(), "--port".to_string(), port.to_string(), "--name".to_string(), name.to_string()];
+    args.extend(extra.iter().map(|arg| arg.to_string()));
+    let task = tokio::spawn(async move {
+        raggy_p2p::run_node_with_handle(args, messages, inbox).await.unwrap();
+    });
+    (handle, task)
+}
+
+async fn roster_names(node: &NodeHandle) -> HashSet<String> {
+    node.roster()
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|entry| entry.presence.name)
+        .collect()
+}
 
 #[tokio::test]
 async fn test_three_node_communication() {
     // Initialize logging for tests
     let _ = env_logger::try_init();
 
-    // Store received messages for verification
-    let node1_messages = Arc::new(Mutex::new(HashSet::new()));
-    let node2_messages = Arc::new(Mutex::new(HashSet::new()));
-    let node3_messages = Arc::new(Mutex::new(HashSet::new()));
-
     // Spawn three nodes with different ports and names
-    let node1_handle = tokio::spawn({
-        let messages = node1_messages.clone();
-        async move {
-            let args = vec!["test".to_string(), "--port".to_string(), "8001".to_string(), "--name".to_string(), "node1".to_string()];
-            raggy_p2p::run_node(args, messages).await.unwrap();
-        }
-    });
-
-    let node2_handle = tokio::spawn({
-        let messages = node2_messages.clone();
-        async move {
-            let args = vec!["test".to_string(), "--port".to_string(), "8002".to_string(), "--name".to_string(), "node2".to_string()];
-            raggy_p2p::run_node(args, messages).await.unwrap();
-        }
-    });
+    let (node1, node1_handle) = spawn_node(8001, "node1");
+    let (node2, node2_handle) = spawn_node(8002, "node2");
+    let (node3, node3_handle) = spawn_node(8003, "node3");
 
-    let node3_handle = tokio::spawn({
-        let messages = node3_messages.clone();
-        async move {
-            let args = vec!["test".to_string(), "--port".to_string(), "8003".to_string(), "--name".to_string(), "node3".to_string()];
-            raggy_p2p::run_node(args, messages).await.unwrap();
-        }
-    });
-
-    // Wait for nodes to discover each other and exchange messages
+    // Wait for nodes to discover each other and exchange heartbeats
     sleep(Duration::from_secs(30)).await;
 
-    // Verify that each node received messages from the other nodes
-    let node1_received = node1_messages.lock().await;
-    let node2_received = node2_messages.lock().await;
-    let node3_received = node3_messages.lock().await;
+    // Verify that each node sees the other nodes on its roster
+    let node1_roster = roster_names(&node1).await;
+    let node2_roster = roster_names(&node2).await;
+    let node3_roster = roster_names(&node3).await;
 
-    assert!(node1_received.len() >= 2, "Node 1 should receive messages from nodes 2 and 3");
-    assert!(node2_received.len() >= 2, "Node 2 should receive messages from nodes 1 and 3");
-    assert!(node3_received.len() >= 2, "Node 3 should receive messages from nodes 1 and 2");
+    assert!(node1_roster.len() >= 2, "Node 1 should see nodes 2 and 3");
+    assert!(node2_roster.len() >= 2, "Node 2 should see nodes 1 and 3");
+    assert!(node3_roster.len() >= 2, "Node 3 should see nodes 1 and 2");
 
-    // Verify specific messages
-    assert!(node1_received.contains("HELO FROM node2"));
-    assert!(node1_received.contains("HELO FROM node3"));
-    assert!(node2_received.contains("HELO FROM node1"));
-    assert!(node2_received.contains("HELO FROM node3"));
-    assert!(node3_received.contains("HELO FROM node1"));
-    assert!(node3_received.contains("HELO FROM node2"));
+    // Verify specific peers
+    assert!(node1_roster.contains("node2"));
+    assert!(node1_roster.contains("node3"));
+    assert!(node2_roster.contains("node1"));
+    assert!(node2_roster.contains("node3"));
+    assert!(node3_roster.contains("node1"));
+    assert!(node3_roster.contains("node2"));
 
     // Clean up
     node1_handle.abort();
@@ -73,65 +75,129 @@ async fn test_node_disconnect_reconnect() {
     // Initialize logging for tests
     let _ = env_logger::try_init();
 
-    // Store received messages for verification
-    let node1_messages = Arc::new(Mutex::new(HashSet::new()));
-    let node2_messages = Arc::new(Mutex::new(HashSet::new()));
-
     // Spawn two nodes initially
-    let node1_handle = tokio::spawn({
-        let messages = node1_messages.clone();
-        async move {
-            let args = vec!["test".to_string(), "--port".to_string(), "8001".to_string(), "--name".to_string(), "node1".to_string()];
-            raggy_p2p::run_node(args, messages).await.unwrap();
-        }
-    });
+    let (node1, node1_handle) = spawn_node(8001, "node1");
+    let (node2, node2_handle) = spawn_node(8002, "node2");
 
-    let node2_handle = tokio::spawn({
-        let messages = node2_messages.clone();
-        async move {
-            let args = vec!["test".to_string(), "--port".to_string(), "8002".to_string(), "--name".to_string(), "node2".to_string()];
-            raggy_p2p::run_node(args, messages).await.unwrap();
-        }
-    });
-
-    // Wait for initial connection and message exchange
+    // Wait for initial connection and heartbeat exchange
     sleep(Duration::from_secs(15)).await;
 
-    // Verify initial messages were exchanged
-    {
-        let node1_received = node1_messages.lock().await;
-        let node2_received = node2_messages.lock().await;
-        
-        assert!(node1_received.contains("HELO FROM node2"), "Node 1 should receive messages from node 2");
-        assert!(node2_received.contains("HELO FROM node1"), "Node 2 should receive messages from node 1");
-    }
+    // Verify the nodes see each other
+    assert!(roster_names(&node1).await.contains("node2"), "Node 1 should see node 2");
+    assert!(roster_names(&node2).await.contains("node1"), "Node 2 should see node 1");
 
     // Disconnect node2 by aborting its task
     node2_handle.abort();
     sleep(Duration::from_secs(5)).await;
 
-    // Clear node1's message set to verify new messages after reconnection
-    node1_messages.lock().await.clear();
-
     // Reconnect node2 with the same configuration
-    let node2_handle = tokio::spawn({
-        let messages = node2_messages.clone();
-        async move {
-            let args = vec!["test".to_string(), "--port".to_string(), "8002".to_string(), "--name".to_string(), "node2".to_string()];
-            raggy_p2p::run_node(args, messages).await.unwrap();
-        }
-    });
+    let reconnected_at = Instant::now();
+    let (node2, node2_handle) = spawn_node(8002, "node2");
 
-    // Wait for reconnection and new message exchange
+    // Wait for reconnection and new heartbeat exchange
     sleep(Duration::from_secs(15)).await;
 
-    // Verify messages were exchanged after reconnection
-    {
-        let node1_received = node1_messages.lock().await;
-        assert!(node1_received.contains("HELO FROM node2"), "Node 1 should receive messages from reconnected node 2");
-    }
+    // Verify heartbeats were exchanged after reconnection
+    assert!(roster_names(&node2).await.contains("node1"), "Reconnected node 2 should see node 1");
+    let heard_from_node2 = node1
+        .roster()
+        .await
+        .unwrap()
+        .into_iter()
+        .any(|entry| entry.presence.name == "node2" && entry.last_seen > reconnected_at);
+    assert!(heard_from_node2, "Node 1 should hear from reconnected node 2");
 
     // Clean up
     node1_handle.abort();
     node2_handle.abort();
-} 
\ No newline at end of file
+}
+
+#[tokio::test]
+async fn test_young_peer_stays_connected() {
+    let _ = env_logger::try_init();
+
+    // node1 only relays gossip from peers it has known for an hour
+    let (node1, node1_handle) = spawn_node(8011, "young1");
+    let mut events = node1.subscribe();
+    let config = PolicyConfig { gossip: Rule { min_trust: None, min_age: Duration::from_secs(3600) }, ..Default::default() };
+    node1.set_policy(config).unwrap();
+    let (_node2, node2_handle) = spawn_node(8012, "young2");
+
+    // node2's presence heartbeats are refused, but not held against it
+    let mut young = None;
+    let deadline = Instant::now() + Duration::from_secs(30);
+    while Instant::now() < deadline {
+        match tokio::time::timeout(Duration::from_secs(1), events.recv()).await {
+            Ok(Ok(NodeEvent::PolicyRejected { peer, action: Action::Gossip, reason: Rejection::TooNew { .. } })) => {
+                young = Some(peer)
+            }
+            Ok(Ok(NodeEvent::PeerScoreDisconnect { peer, .. })) => panic!("{peer} was disconnected for being young"),
+            _ => {}
+        }
+    }
+    let young = young.expect("node1 should refuse node2's gossip");
+    let score = node1.peer_scores().await.unwrap().get(&young).copied();
+    assert!(score.is_some_and(|score| score >= 0.0), "node2 should stay connected in good standing, scored {score:?}");
+    assert!(!roster_names(&node1).await.contains("young2"), "node2's presence shouldn't be relayed yet");
+
+    node1_handle.abort();
+    node2_handle.abort();
+}
+
+#[tokio::test]
+async fn test_join_completes_past_unknown_docs() {
+    let _ = env_logger::try_init();
+
+    // node1's document references one nobody has
+    let (node1, node1_handle) = spawn_node(8021, "walker1");
+    let (node2, node2_handle) = spawn_node(8022, "walker2");
+    let missing = DocId::from_bytes([0x5a; 32]);
+    let reference = DocTicket { doc: missing, namespace: Namespace::Keyed, capability: Capability::Read, peers: Vec::new() };
+    let root = node1.create_doc().await.unwrap();
+    node1.doc_set(root, "tickets/missing", reference.to_string().into_bytes()).await.unwrap();
+    sleep(Duration::from_secs(2)).await;
+    let ticket = node1.share_doc(root, ShareMode::Read).await.unwrap();
+
+    let mut events = node2.subscribe();
+    node2.join_doc(ticket).await.unwrap();
+    let mut finished = Vec::new();
+    let deadline = Instant::now() + Duration::from_secs(30);
+    while Instant::now() < deadline && finished.len() < 2 {
+        if let Ok(Ok(NodeEvent::DocSyncProgress { doc, progress, error })) =
+            tokio::time::timeout(Duration::from_secs(1), events.recv()).await
+        {
+            if progress.root == root {
+                finished.push((doc, progress, error));
+            }
+        }
+    }
+
+    assert_eq!(finished.len(), 2, "the root and the missing document should both finish");
+    let (doc, progress, error) = &finished[1];
+    assert_eq!(*doc, missing);
+    assert!(error.is_some(), "the missing document finishes with an error");
+    assert_eq!((progress.synced, progress.failed, progress.discovered), (1, 1, 2));
+
+    node1_handle.abort();
+    node2_handle.abort();
+}
+
+#[tokio::test]
+async fn test_nodes_sharing_a_name_keep_their_own_identity() {
+    // Without a data directory, every node makes up its own
+    let (twin1, twin1_handle) = spawn_node(8031, "twin");
+    let (twin2, twin2_handle) = spawn_node(8032, "twin");
+    assert_ne!(twin1.main_doc().await.unwrap(), twin2.main_doc().await.unwrap());
+    twin1_handle.abort();
+    twin2_handle.abort();
+
+    // Asking for the name-keyed directory keeps the identity across restarts
+    let name = format!("restarted-{}", std::process::id());
+    let (node, node_handle) = spawn_node_with(8033, &name, &["--data-dir-by-name"]);
+    let main_doc = node.main_doc().await.unwrap();
+    node_handle.abort();
+    let _ = node_handle.await;
+    let (node, node_handle) = spawn_node_with(8033, &name, &["--data-dir-by-name"]);
+    assert_eq!(node.main_doc().await.unwrap(), main_doc);
+    node_handle.abort();
+}
@@ -0,0 +1,69 @@
+mod common;
+
+use libp2p::{identity::ed25519, PeerId};
+use raggy_p2p::docs::{AuthorId, DocError, Entry, Namespace, MAIN_DOC};
+use raggy_p2p::sync::{self, KeyRange, RangeMessage, RangePayload};
+use raggy_p2p::ticket::{ShareMode, TicketPeer};
+
+use common::{open_docs, open_docs_with_key};
+
+const PREFIX: &str = "main-doc";
+
+fn peers() -> Vec<TicketPeer> {
+    vec![TicketPeer { peer: PeerId::random(), addrs: Vec::new() }]
+}
+
+#[test]
+fn test_second_node_cannot_write_main_doc() {
+    let node1_key = ed25519::Keypair::generate();
+    let node2_key = ed25519::Keypair::generate();
+    let mut node1 = open_docs_with_key(PREFIX, "main", "node1", &node1_key, false);
+    let mut node2 = open_docs_with_key(PREFIX, "main", "node2", &node2_key, false);
+
+    let main_doc = node1.create_owned(MAIN_DOC).unwrap();
+    assert_eq!(main_doc, node1.owned_id(MAIN_DOC));
+    node1.set(&main_doc, "node/name", b"node1".to_vec()).unwrap();
+    assert!(matches!(node1.share(&main_doc, ShareMode::Write, peers()), Err(DocError::ReadOnly(_))));
+
+    // node2 replicates node1's MainDoc but can't write to it locally...
+    node2.join(&node1.share(&main_doc, ShareMode::Read, peers()).unwrap()).unwrap();
+    assert!(matches!(node2.set(&main_doc, "node/name", b"node2".to_vec()), Err(DocError::NotOwner(_))));
+
+    // ...and entries it signs itself, or passes off as node1's, are rejected
+    let later = node1.doc(&main_doc).unwrap().entry("node/name").unwrap().timestamp + 1;
+    let own = Entry::owned(&main_doc, "node/name", Some(b"node2".to_vec()), &node2_key, later);
+    let mut impersonated = own.clone();
+    impersonated.author = AuthorId::of(&node1_key);
+    let forged = vec![own, impersonated];
+    let incoming = vec![RangeMessage {
+        range: KeyRange::all(),
+        payload: RangePayload::Entries { entries: forged, reply: true },
+    }];
+    let step = sync::respond(node1.doc(&main_doc).unwrap(), incoming);
+    let (applied, rejected) = node1.apply(&main_doc, step.received).unwrap();
+    assert_eq!(applied, 0);
+    assert!(matches!(rejected[0].1, DocError::NotOwner(author) if author == AuthorId::of(&node2_key)));
+    assert!(matches!(rejected[1].1, DocError::InvalidSignature));
+    assert_eq!(node1.get(&main_doc, "node/name").unwrap(), Some(b"node1".to_vec()));
+
+    // node1's genuine write still reaches node2, so nothing forged propagates onwards
+    let mut node1_replica = node1.doc(&main_doc).unwrap().clone();
+    let mut node2_replica = node2.doc(&main_doc).unwrap().clone();
+    let stats = sync::reconcile(&mut node2_replica, &mut node1_replica);
+    assert_eq!(stats.rejected, 0);
+    assert_eq!(node2_replica.get("node/name"), Some(&b"node1"[..]));
+}
+
+#[test]
+fn test_ticket_for_owned_doc_must_match_its_owner() {
+    let owner_key = ed25519::Keypair::generate();
+    let mut owner = open_docs_with_key(PREFIX, "main", "owner", &owner_key, false);
+    let main_doc = owner.create_owned(MAIN_DOC).unwrap();
+
+    // Claiming someone else owns the document doesn't produce the same id
+    let mut ticket = owner.share(&main_doc, ShareMode::Read, peers()).unwrap();
+    let impostor = AuthorId::of(&ed25519::Keypair::generate());
+    ticket.namespace = Namespace::Owned { owner: impostor, label: MAIN_DOC.to_string() };
+    let mut joiner = open_docs(PREFIX, "main", "joiner", false);
+    assert!(matches!(joiner.join(&ticket), Err(DocError::InvalidTicket(_))));
+}
@@ -0,0 +1,37 @@
+use std::collections::HashSet;
+
+use libp2p::gossipsub::{Message, TopicHash};
+use libp2p::PeerId;
+use raggy_p2p::node::message_id;
+
+fn heartbeat(source: PeerId, sequence_number: u64, topic: &str) -> Message {
+    Message {
+        source: Some(source),
+        data: b"HELO FROM node1".to_vec(),
+        sequence_number: Some(sequence_number),
+        topic: TopicHash::from_raw(topic),
+    }
+}
+
+#[test]
+fn test_repeated_heartbeats_get_distinct_ids() {
+    let source = PeerId::random();
+    let none = HashSet::new();
+
+    let first = message_id(&heartbeat(source, 1, "raggy-chat"), &none);
+    let second = message_id(&heartbeat(source, 2, "raggy-chat"), &none);
+
+    assert_ne!(first, second, "Heartbeats with different sequence numbers must not collide");
+    assert_eq!(first, message_id(&heartbeat(source, 1, "raggy-chat"), &none), "Ids must be stable");
+}
+
+#[test]
+fn test_content_addressed_topics_ignore_source() {
+    let topic = TopicHash::from_raw("raggy-content");
+    let content_addressed = HashSet::from([topic]);
+
+    let first = message_id(&heartbeat(PeerId::random(), 1, "raggy-content"), &content_addressed);
+    let second = message_id(&heartbeat(PeerId::random(), 7, "raggy-content"), &content_addressed);
+
+    assert_eq!(first, second);
+}
@@ -0,0 +1,29 @@
+use raggy_p2p::codec::to_cbor;
+use raggy_p2p::message::{MessageKind, RaggyMessage, MESSAGE_VERSION};
+
+#[test]
+fn test_chat_message_round_trip() {
+    let message = RaggyMessage::chat("node1", "HELO FROM node1");
+    let decoded = RaggyMessage::decode(&message.encode().unwrap()).unwrap();
+
+    assert_eq!(decoded, message);
+    assert_eq!(decoded.kind, MessageKind::Chat);
+    assert_eq!(decoded.version, MESSAGE_VERSION);
+    assert_eq!(decoded.text(), Some("HELO FROM node1"));
+    assert!(decoded.is_supported());
+}
+
+#[test]
+fn test_unknown_kind_is_preserved() {
+    let mut message = RaggyMessage::chat("node2", "hello");
+    message.kind = MessageKind::Unknown("telemetry".to_string());
+    let decoded = RaggyMessage::decode(&to_cbor(&message).unwrap()).unwrap();
+
+    assert_eq!(decoded.kind, MessageKind::Unknown("telemetry".to_string()));
+    assert!(!decoded.is_supported());
+}
+
+#[test]
+fn test_raw_bytes_are_rejected() {
+    assert!(RaggyMessage::decode(b"HELO FROM node3").is_err());
+}
@@ -0,0 +1,48 @@
+mod common;
+
+use std::time::{Duration, Instant};
+
+use raggy_p2p::message::CONTENT_TYPE_TEXT;
+use raggy_p2p::{MessageKind, NodeEvent, RaggyMessage};
+
+use common::{gossiper, spawn_node};
+
+const CHAT_TOPIC: &str = "raggy-chat";
+
+#[tokio::test]
+async fn test_peers_sending_invalid_messages_are_disconnected() {
+    let _ = env_logger::try_init();
+
+    let (node, _, node_task) = spawn_node(8051, "scorer", &[]);
+    let mut events = node.subscribe();
+    tokio::time::sleep(Duration::from_secs(1)).await;
+    let (good, good_task) = gossiper(8051, CHAT_TOPIC, |n| {
+        let text = format!("hello {n}").into_bytes();
+        RaggyMessage::new(MessageKind::Chat, "gossiper", CONTENT_TYPE_TEXT, text).encode().unwrap()
+    });
+    let (bad, bad_task) = gossiper(8051, CHAT_TOPIC, |n| format!("not a raggy message {n}").into_bytes());
+
+    let mut chatted = false;
+    let mut disconnected = Vec::new();
+    let deadline = Instant::now() + Duration::from_secs(30);
+    while Instant::now() < deadline && !(chatted && disconnected.contains(&bad)) {
+        match tokio::time::timeout(Duration::from_secs(1), events.recv()).await {
+            Ok(Ok(NodeEvent::PeerScoreDisconnect { peer, score })) => {
+                assert!(score < -80.0, "only graylisted peers are disconnected");
+                disconnected.push(peer);
+            }
+            Ok(Ok(NodeEvent::Message { source, .. })) => chatted |= source == Some(good),
+            _ => {}
+        }
+    }
+
+    assert!(disconnected.contains(&bad), "the peer sending garbage should be graylisted and disconnected");
+    assert!(chatted, "the well-behaved peer's messages should be delivered");
+    assert!(!disconnected.contains(&good), "the well-behaved peer should stay connected");
+    let scores = node.peer_scores().await.unwrap();
+    assert!(scores.get(&good).is_some_and(|score| *score >= 0.0), "scores: {scores:?}");
+
+    node_task.abort();
+    good_task.abort();
+    bad_task.abort();
+}
@@ -0,0 +1,129 @@
+use std::collections::BTreeMap;
+use std::time::{Duration, Instant};
+
+use libp2p::identity::{self, ed25519};
+use libp2p::PeerId;
+use raggy_p2p::docs::{AuthorId, DocId};
+use raggy_p2p::entity::EntityId;
+use raggy_p2p::graph::TrustGraph;
+use raggy_p2p::policy::{Action, Defederation, DefederationNotice, PolicyConfig, PolicyEngine, RateLimit, Rejection, Rule};
+
+const HOUR: Duration = Duration::from_secs(3600);
+
+fn id(n: u8) -> EntityId {
+    DocId::from_bytes([n; 32])
+}
+
+fn peer(key: &ed25519::Keypair) -> PeerId {
+    PeerId::from_public_key(&identity::PublicKey::from(key.public()))
+}
+
+/// Our entity 1 trusts Alice's 2, who trusts Bob's 3, who trusts us back.
+/// Nobody trusts Mallory's 4.
+struct World {
+    us: PeerId,
+    alice: PeerId,
+    bob: PeerId,
+    mallory: PeerId,
+    graph: TrustGraph,
+    owners: BTreeMap<EntityId, AuthorId>,
+}
+
+fn world() -> World {
+    let keys: Vec<ed25519::Keypair> = (0..4).map(|_| ed25519::Keypair::generate()).collect();
+    let mut graph = TrustGraph::default();
+    graph.add_edge(id(1), id(2), 0.8);
+    graph.add_edge(id(2), id(3), 0.5);
+    graph.add_edge(id(3), id(1), 1.0);
+    let owners = keys.iter().enumerate().map(|(n, key)| (id(n as u8 + 1), AuthorId::of(key))).collect();
+    let [us, alice, bob, mallory] = [0, 1, 2, 3].map(|n| peer(&keys[n]));
+    World { us, alice, bob, mallory, graph, owners }
+}
+
+fn engine(world: &World, config: PolicyConfig) -> PolicyEngine {
+    let mut engine = PolicyEngine::new(world.us, config);
+    engine.refresh(world.graph.clone(), world.owners.clone());
+    engine
+}
+
+#[test]
+fn test_new_peers_build_up_rails_slowly() {
+    let world = world();
+    let config = PolicyConfig {
+        rails: Rule { min_trust: None, min_age: HOUR },
+        rail_rate: Some(RateLimit { max: 2, window: HOUR }),
+        ..Default::default()
+    };
+    let mut policy = engine(&world, config);
+    let start = Instant::now();
+
+    // A peer we just heard of may write and gossip, but not create rails
+    assert_eq!(policy.decide(&world.mallory, Action::Write, start), Ok(()));
+    assert!(matches!(policy.decide(&world.mallory, Action::Rail, start), Err(Rejection::TooNew { .. })));
+    assert_eq!(policy.decide(&world.us, Action::Rail, start), Ok(()));
+
+    // Once old enough, only so many rails an hour
+    let later = start + HOUR;
+    assert_eq!(policy.decide(&world.mallory, Action::Rail, later), Ok(()));
+    assert_eq!(policy.decide(&world.mallory, Action::Rail, later), Ok(()));
+    let limited = policy.decide(&world.mallory, Action::Rail, later + Duration::from_secs(1));
+    assert!(matches!(limited, Err(Rejection::RailRate(RateLimit { max: 2, .. }))));
+    assert_eq!(policy.decide(&world.mallory, Action::Rail, later + HOUR), Ok(()));
+}
+
+#[test]
+fn test_trust_thresholds() {
+    let world = world();
+    let config = PolicyConfig {
+        viewer: Some(id(1)),
+        writes: Rule { min_trust: Some(0.3), min_age: Duration::ZERO },
+        ..Default::default()
+    };
+    let mut policy = engine(&world, config.clone());
+    let now = Instant::now();
+    assert_eq!(policy.trust(&world.alice), Some(0.8));
+
+    assert_eq!(policy.decide(&world.alice, Action::Write, now), Ok(()));
+    // Bob is only reached through Alice, with trust halved at the second hop
+    assert!(matches!(policy.decide(&world.bob, Action::Write, now), Err(Rejection::Untrusted { score, .. }) if score < 0.3));
+    assert_eq!(
+        policy.decide(&world.mallory, Action::Write, now),
+        Err(Rejection::Untrusted { score: 0.0, required: 0.3 })
+    );
+    assert_eq!(policy.decide(&world.mallory, Action::Gossip, now), Ok(()));
+
+    // Without a viewer there is nobody to trust, so trust rules don't apply
+    let mut policy = engine(&world, PolicyConfig { viewer: None, ..config });
+    assert_eq!(policy.trust(&world.mallory), None);
+    assert_eq!(policy.decide(&world.mallory, Action::Write, now), Ok(()));
+}
+
+#[test]
+fn test_defederate_subtrees_and_follow_trusted_notices() {
+    let world = world();
+    let config = PolicyConfig { viewer: Some(id(1)), follow_notices: Some(0.5), ..Default::default() };
+    let mut policy = engine(&world, config);
+    let now = Instant::now();
+
+    // Alice vouches for Bob, who vouches for us; we are never cut off
+    assert_eq!(policy.defederate(world.alice, true, None), [world.alice, world.bob].into());
+    assert!(policy.is_cut_off(&world.bob) && !policy.is_cut_off(&world.us));
+    assert_eq!(
+        policy.decide(&world.bob, Action::Gossip, now),
+        Err(Rejection::Defederated(Box::new(Defederation { root: world.alice, by: None })))
+    );
+    assert_eq!(policy.defederate(world.bob, false, None), [].into(), "already cut off");
+
+    assert!(!policy.is_cut_off(&world.mallory));
+
+    assert_eq!(policy.restore(&world.alice), [world.alice, world.bob].into());
+    assert!(policy.defederated().is_empty());
+
+    // Notices are followed from peers we trust enough, and never against us
+    let notice = |by: PeerId, peer: PeerId| DefederationNotice { by, peer, subtree: false, reason: "spam".to_string() };
+    assert!(policy.follows(&notice(world.alice, world.mallory)));
+    assert!(!policy.follows(&notice(world.bob, world.mallory)));
+    assert!(!policy.follows(&notice(world.alice, world.us)));
+    assert_eq!(policy.defederate(world.mallory, true, Some(world.alice)), [world.mallory].into());
+    assert!(matches!(policy.cut_off(&world.mallory), Some(Rejection::Defederated(defederation)) if defederation.by == Some(world.alice)));
+}
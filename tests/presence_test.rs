@@ -0,0 +1,39 @@
+use std::time::{Duration, Instant};
+
+use libp2p::PeerId;
+use raggy_p2p::presence::{Presence, Roster};
+
+fn presence(name: &str, peer_id: PeerId) -> Presence {
+    Presence {
+        name: name.to_string(),
+        peer_id,
+        version: "0.1.0".to_string(),
+        capabilities: vec!["presence".to_string()],
+        uptime_secs: 0,
+        load: 0.0,
+    }
+}
+
+#[test]
+fn test_roster_detects_join_and_leave() {
+    let start = Instant::now();
+    let mut roster = Roster::new(Duration::from_secs(10), 3);
+    let node1 = PeerId::random();
+    let node2 = PeerId::random();
+
+    assert!(roster.observe(presence("node1", node1), start), "First heartbeat is a join");
+    assert!(roster.observe(presence("node2", node2), start));
+    assert!(!roster.observe(presence("node1", node1), start + Duration::from_secs(10)), "Later heartbeats are not joins");
+
+    // node2 has missed three heartbeats, node1 only two
+    let left = roster.expire(start + Duration::from_secs(31));
+    assert_eq!(left.len(), 1);
+    assert_eq!(left[0].presence.peer_id, node2);
+    assert!(roster.contains(&node1));
+    assert!(!roster.contains(&node2));
+
+    // A node that comes back is seen as joining again
+    assert!(roster.observe(presence("node2", node2), start + Duration::from_secs(40)));
+    let names: Vec<String> = roster.entries().into_iter().map(|e| e.presence.name).collect();
+    assert_eq!(names, vec!["node1", "node2"]);
+}
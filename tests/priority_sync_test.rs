@@ -0,0 +1,79 @@
+use libp2p::identity::ed25519;
+use raggy_p2p::docs::{Document, Entry};
+use raggy_p2p::sync::{self, RangePayload, SyncRequest, SyncResponse};
+
+const HOUR: u64 = 3_600_000;
+
+/// A writable document holding `key/0` .. `key/<n - 1>`
+fn document(n: usize) -> Document {
+    let author = ed25519::Keypair::generate();
+    let mut doc = Document::new();
+    for i in 0..n {
+        doc.write(&author, &format!("key/{i}"), Some(vec![i as u8])).unwrap();
+    }
+    doc
+}
+
+fn keys(entries: &[Entry]) -> Vec<&str> {
+    entries.iter().map(|e| e.key.as_str()).collect()
+}
+
+#[test]
+fn test_hot_entries_rank_frequent_and_recent_reads_first() {
+    let mut doc = document(5);
+    let now = 10 * HOUR;
+    doc.record_access("key/1", now - 2 * HOUR);
+    doc.record_access("key/2", now);
+    for _ in 0..3 {
+        doc.record_access("key/3", now);
+    }
+    // Reads of keys we don't hold are counted but never offered
+    doc.record_access("missing", now);
+
+    assert_eq!(doc.access("key/3").unwrap().count, 3);
+    assert_eq!(keys(&doc.hot_entries(10, now)), vec!["key/3", "key/2", "key/1"]);
+    assert_eq!(keys(&doc.hot_entries(2, now)), vec!["key/3", "key/2"]);
+}
+
+#[test]
+fn test_new_replica_gets_hot_set_in_first_response() {
+    let mut remote = document(200);
+    let now = 10 * HOUR;
+    remote.record_access("key/150", now);
+    remote.record_access("key/7", now);
+    remote.record_access("key/7", now);
+
+    let replica = Document::replica(remote.id());
+    let request = SyncRequest { doc: remote.id(), ranges: sync::start(&replica), hot: sync::HOT_SET_SIZE };
+    let (response, _) = sync::answer(&remote, request, now);
+    let SyncResponse::Prioritised { hot, ranges } = response else { panic!("expected the hot set first") };
+    assert_eq!(keys(&hot), vec!["key/7", "key/150"]);
+    assert!(!ranges.is_empty(), "The rest of the sync carries on as usual");
+
+    // Replicas already in sync get nothing extra
+    let request = SyncRequest { doc: remote.id(), ranges: sync::start(&remote), hot: sync::HOT_SET_SIZE };
+    assert!(matches!(sync::answer(&remote, request, now).0, SyncResponse::Ranges(ranges) if ranges.is_empty()));
+}
+
+#[test]
+fn test_fetch_transfers_only_the_requested_key() {
+    let remote = document(20);
+    let mut replica = Document::replica(remote.id());
+
+    let step = sync::respond(&remote, sync::fetch(&replica, "key/1"));
+    let sent: Vec<&Entry> = step
+        .replies
+        .iter()
+        .flat_map(|message| match &message.payload {
+            RangePayload::Entries { entries, .. } => entries.iter().collect(),
+            RangePayload::Fingerprint(_) => Vec::new(),
+        })
+        .collect();
+    assert_eq!(sent.len(), 1, "key/10 .. key/19 share the prefix but aren't sent");
+
+    for entry in sync::respond(&replica, step.replies).received {
+        replica.insert(entry).unwrap();
+    }
+    assert_eq!(replica.get("key/1"), Some(&[1u8][..]));
+    assert_eq!(replica.entries().count(), 1);
+}
@@ -0,0 +1,72 @@
+mod common;
+
+use std::collections::BTreeMap;
+
+use raggy_p2p::docs::Docs;
+use raggy_p2p::entity;
+use raggy_p2p::rail_index::{RailFilter, RailIndex};
+use raggy_p2p::rail_kind::RailKind;
+use raggy_p2p::rails::{self, RailMetadata};
+
+use common::open_docs;
+
+const PREFIX: &str = "rail-index";
+
+/// Re-index whatever changed since the last call, as the node does on every batch
+fn catch_up(index: &mut RailIndex, docs: &mut Docs) {
+    for (doc, _) in docs.take_changes() {
+        index.update(docs, &doc);
+    }
+}
+
+#[test]
+fn test_lookups_by_entity_pair_kind_and_weight() {
+    let mut docs = open_docs(PREFIX, "lookups", "node", true);
+    let alice = entity::create(&mut docs, "person", "Alice", BTreeMap::new()).unwrap();
+    let bob = entity::create(&mut docs, "person", "Bob", BTreeMap::new()).unwrap();
+    let carol = entity::create(&mut docs, "person", "Carol", BTreeMap::new()).unwrap();
+    let closeness: RailKind = "test.raggy/closeness".parse().unwrap();
+    let history: RailKind = "test.raggy/history".parse().unwrap();
+    let close = rails::create_rail(&mut docs, &closeness, &[alice, bob], RailMetadata::new()).unwrap();
+    let shared = rails::create_rail(&mut docs, &history, &[alice, bob, carol], RailMetadata::new()).unwrap();
+    rails::set_weight(&mut docs, &close, &alice, &bob, 0.9).unwrap();
+    rails::set_weight(&mut docs, &shared, &bob, &carol, 0.2).unwrap();
+
+    let index = RailIndex::build(&docs);
+    let mut both = vec![close, shared];
+    both.sort();
+    assert_eq!(index.rails_for(&alice, &RailFilter::default()), both);
+    assert_eq!(index.rails_for(&carol, &RailFilter::default()), vec![shared]);
+    assert_eq!(index.rails_between(&bob, &alice, &RailFilter::default()), both);
+    assert_eq!(index.rails_between(&alice, &carol, &RailFilter::default()), vec![shared]);
+
+    assert_eq!(index.rails_for(&alice, &RailFilter::default().kind(closeness.clone())), vec![close]);
+    assert_eq!(index.rails_for(&bob, &RailFilter::default().weight(0.5..=1.0)), vec![close]);
+    assert_eq!(index.rails_for(&bob, &RailFilter::default().weight(0.0..=0.5)), vec![shared]);
+    // Bob and Carol's edge is on a history rail, not a closeness one
+    assert!(index.rails_between(&bob, &carol, &RailFilter::default().kind(closeness).weight(0.0..=1.0)).is_empty());
+    assert!(index.rails_between(&alice, &carol, &RailFilter::default().weight(0.0..=1.0)).is_empty());
+}
+
+#[test]
+fn test_incremental_updates_match_a_rebuild() {
+    let mut docs = open_docs(PREFIX, "incremental", "node", true);
+    docs.take_changes();
+    let mut index = RailIndex::build(&docs);
+    let kind: RailKind = "test.raggy/closeness".parse().unwrap();
+
+    let alice = entity::create(&mut docs, "person", "Alice", BTreeMap::new()).unwrap();
+    let bob = entity::create(&mut docs, "person", "Bob", BTreeMap::new()).unwrap();
+    let rail = rails::create_rail(&mut docs, &kind, &[alice, bob], RailMetadata::new()).unwrap();
+    rails::set_weight(&mut docs, &rail, &alice, &bob, 0.7).unwrap();
+    catch_up(&mut index, &mut docs);
+    assert_eq!(index, RailIndex::build(&docs));
+    assert_eq!(index.rails_between(&alice, &bob, &RailFilter::default().weight(0.5..=1.0)), vec![rail]);
+
+    rails::remove_participant(&mut docs, &rail, &bob).unwrap();
+    catch_up(&mut index, &mut docs);
+    assert_eq!(index, RailIndex::build(&docs));
+    assert!(index.rails_for(&bob, &RailFilter::default()).is_empty());
+    assert_eq!(index.rails_for(&alice, &RailFilter::default()), vec![rail]);
+    assert_eq!(index.rail(&rail).unwrap().entities, vec![alice]);
+}
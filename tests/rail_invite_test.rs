@@ -0,0 +1,86 @@
+mod common;
+
+use std::collections::BTreeMap;
+
+use raggy_p2p::docs::{DocError, Docs, RAIL_INVITES_DOC};
+use raggy_p2p::entity::{self, EntityId};
+use raggy_p2p::rail_kind::RailKind;
+use raggy_p2p::rails::{self, BackRefStatus, HalfLink, MissingLink, RailId, RailMetadata};
+use raggy_p2p::ticket::ShareMode;
+
+use common::{open_docs, propagate};
+
+const PREFIX: &str = "invite";
+
+/// node1 creates a rail linking its Alice and node2's Bob
+fn one_sided_rail(test: &str) -> (Docs, Docs, EntityId, EntityId, RailId) {
+    let mut node1 = open_docs(PREFIX, test, "node1", true);
+    let mut node2 = open_docs(PREFIX, test, "node2", true);
+    let alice = entity::create(&mut node1, "person", "Alice", BTreeMap::new()).unwrap();
+    let bob = entity::create(&mut node2, "person", "Bob", BTreeMap::new()).unwrap();
+    propagate(&node2, &mut node1, &bob);
+    let kind: RailKind = "test.raggy/colleague".parse().unwrap();
+    let rail = rails::create_rail(&mut node1, &kind, &[alice, bob], RailMetadata::new()).unwrap();
+    (node1, node2, alice, bob, rail)
+}
+
+#[test]
+fn test_accepted_invite_links_both_sides() {
+    let (mut node1, mut node2, alice, bob, rail) = one_sided_rail("accepted");
+    let missing = HalfLink { rail, entity: bob, owner: node2.author(), missing: MissingLink::BackReference };
+    assert_eq!(rails::half_linked(&node1), vec![missing]);
+
+    // The invitation waits for node2 to answer it
+    let invite = rails::invite(&node1, &rail, &bob).unwrap();
+    assert_eq!(rails::receive_invite(&mut node2, invite.clone(), BackRefStatus::Pending).unwrap(), BackRefStatus::Pending);
+    assert!(rails::rails_of(&node2, &bob).unwrap().is_empty());
+    assert_eq!(rails::invites(&node2), vec![(invite.clone(), BackRefStatus::Pending)]);
+    assert!(node2.is_private(&node2.owned_id(RAIL_INVITES_DOC)));
+
+    rails::answer_invite(&mut node2, &rail, &bob, true).unwrap();
+    assert!(rails::rails_of(&node2, &bob).unwrap().contains_key(&rail));
+    assert_eq!(invite.ticket.mode(), ShareMode::Read, "no secret is handed on");
+    propagate(&node1, &mut node2, &rail);
+    rails::set_weight(&mut node2, &rail, &bob, &alice, 0.4).unwrap();
+
+    // Once Bob's back-reference reaches node1, the rail is linked from both sides
+    propagate(&node2, &mut node1, &bob);
+    assert!(rails::half_linked(&node1).is_empty());
+    assert!(rails::rails_of(&node1, &bob).unwrap().contains_key(&rail));
+
+    // Sending the invitation again doesn't undo the answer
+    assert_eq!(rails::receive_invite(&mut node2, invite, BackRefStatus::Declined).unwrap(), BackRefStatus::Accepted);
+}
+
+#[test]
+fn test_declined_invite_stays_half_linked() {
+    let (node1, mut node2, _, bob, rail) = one_sided_rail("declined");
+    let invite = rails::invite(&node1, &rail, &bob).unwrap();
+    assert_eq!(rails::receive_invite(&mut node2, invite.clone(), BackRefStatus::Declined).unwrap(), BackRefStatus::Declined);
+    assert_eq!(rails::receive_invite(&mut node2, invite, BackRefStatus::Accepted).unwrap(), BackRefStatus::Declined);
+    assert_eq!(rails::invite_status(&node2, &rail, &bob).unwrap(), Some(BackRefStatus::Declined));
+    assert!(rails::rails_of(&node2, &bob).unwrap().is_empty());
+    assert_eq!(rails::half_linked(&node1).len(), 1);
+}
+
+#[test]
+fn test_invites_for_entities_we_dont_own_are_refused() {
+    let (node1, mut node2, alice, _, rail) = one_sided_rail("foreign");
+    propagate(&node1, &mut node2, &alice);
+    let invite = rails::invite(&node1, &rail, &alice).unwrap();
+    let refused = rails::receive_invite(&mut node2, invite, BackRefStatus::Accepted);
+    assert!(matches!(refused, Err(DocError::Rejected(_))));
+    assert!(rails::invites(&node2).is_empty());
+}
+
+#[test]
+fn test_entity_left_on_a_rail_it_was_taken_off() {
+    let (mut node1, mut node2, _, bob, rail) = one_sided_rail("removed");
+    let invite = rails::invite(&node1, &rail, &bob).unwrap();
+    rails::receive_invite(&mut node2, invite, BackRefStatus::Accepted).unwrap();
+    propagate(&node2, &mut node1, &bob);
+    rails::remove_participant(&mut node1, &rail, &bob).unwrap();
+
+    let missing = HalfLink { rail, entity: bob, owner: node2.author(), missing: MissingLink::Participant };
+    assert_eq!(rails::half_linked(&node1), vec![missing]);
+}
@@ -0,0 +1,80 @@
+mod common;
+
+use std::collections::BTreeMap;
+
+use raggy_p2p::docs::DocError;
+use raggy_p2p::entity::{self, AttrType, AttrValue};
+use raggy_p2p::rail_kind::{KindSpec, RailKind, RailKinds};
+use raggy_p2p::rails::{self, RailMetadata};
+
+use common::open_docs;
+
+const PREFIX: &str = "rail-kind";
+
+fn max(weights: &[f64]) -> f64 {
+    weights.iter().copied().fold(f64::MIN, f64::max)
+}
+
+#[test]
+fn test_kind_tags_are_namespaced() {
+    let kind: RailKind = "org.example/semantic-closeness".parse().unwrap();
+    assert_eq!(kind.namespace(), "org.example");
+    assert_eq!(kind.name(), "semantic-closeness");
+    assert_eq!(kind.to_string(), "org.example/semantic-closeness");
+
+    for bad in ["closeness", "/closeness", "org.example/", "Org/closeness", "org/close ness"] {
+        assert!(matches!(bad.parse::<RailKind>(), Err(DocError::InvalidId(_))), "{bad} should not parse");
+    }
+}
+
+#[test]
+fn test_kinds_are_checked_against_their_schema() {
+    let mut kinds = RailKinds::default();
+    assert!(kinds.kinds().is_empty());
+
+    let history: RailKind = "org.example/shared-history".parse().unwrap();
+    let schema = [("since".to_string(), AttrType::Int)].into();
+    kinds.register(history.clone(), KindSpec { schema: Some(schema), combine: None });
+    let free: RailKind = "org.example/anything".parse().unwrap();
+    kinds.register(free.clone(), KindSpec::default());
+    assert_eq!(kinds.kinds(), vec![free.clone(), history.clone()]);
+
+    let good: RailMetadata = [("since".to_string(), AttrValue::Int(2019))].into();
+    let wrong_type: RailMetadata = [("since".to_string(), AttrValue::Text("2019".into()))].into();
+    assert!(kinds.check(&history, &good).is_ok());
+    assert!(matches!(kinds.check(&history, &wrong_type), Err(DocError::Rejected(_))));
+    assert!(matches!(kinds.check(&history, &RailMetadata::new()), Err(DocError::Rejected(_))));
+    assert!(kinds.check(&free, &RailMetadata::new()).is_ok());
+
+    let unknown: RailKind = "org.example/unknown".parse().unwrap();
+    assert!(matches!(kinds.check(&unknown, &good), Err(DocError::Rejected(_))));
+}
+
+#[test]
+fn test_weights_combine_per_kind() {
+    let mut kinds = RailKinds::default();
+    let strongest: RailKind = "org.example/strongest".parse().unwrap();
+    let mean: RailKind = "org.example/mean".parse().unwrap();
+    kinds.register(strongest.clone(), KindSpec { schema: None, combine: Some(max) });
+    kinds.register(mean.clone(), KindSpec::default());
+
+    assert_eq!(kinds.combine(&strongest, &[0.2, 0.9, 0.4]), Some(0.9));
+    assert_eq!(kinds.combine(&mean, &[0.25, 0.75]), Some(0.5));
+    assert_eq!(kinds.combine(&mean, &[]), None);
+}
+
+#[test]
+fn test_rails_are_tagged_with_their_kind() {
+    let mut docs = open_docs(PREFIX, "tagged", "node", true);
+    let alice = entity::create(&mut docs, "person", "Alice", BTreeMap::new()).unwrap();
+    let bob = entity::create(&mut docs, "person", "Bob", BTreeMap::new()).unwrap();
+    let closeness: RailKind = "org.example/semantic-closeness".parse().unwrap();
+    let history: RailKind = "org.example/shared-history".parse().unwrap();
+    let close = rails::create_rail(&mut docs, &closeness, &[alice, bob], RailMetadata::new()).unwrap();
+    let shared = rails::create_rail(&mut docs, &history, &[alice, bob], RailMetadata::new()).unwrap();
+
+    assert_eq!(rails::get_rail(&docs, &close).unwrap().unwrap().kind, Some(closeness.clone()));
+    let found: Vec<_> = rails::find_rails(&docs, Some(&history)).into_iter().map(|rail| rail.id).collect();
+    assert_eq!(found, vec![shared]);
+    assert_eq!(rails::find_rails(&docs, None).len(), 2);
+}
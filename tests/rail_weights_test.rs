@@ -0,0 +1,130 @@
+mod common;
+
+use std::collections::BTreeMap;
+
+use libp2p::identity::ed25519;
+use raggy_p2p::codec::{from_cbor, to_cbor};
+use raggy_p2p::docs::{DocError, Entry};
+use raggy_p2p::entity::{self, AttrValue};
+use raggy_p2p::rail_kind::RailKind;
+use raggy_p2p::rails::{self, Creator, RailMetadata};
+use raggy_p2p::ticket::ShareMode;
+
+use common::{open_docs, open_docs_with_key, sync};
+
+const PREFIX: &str = "weights";
+
+fn kind() -> RailKind {
+    "test.raggy/colleague".parse().unwrap()
+}
+
+#[test]
+fn test_participants_weight_only_their_own_edges() {
+    let (node1_key, node2_key) = (ed25519::Keypair::generate(), ed25519::Keypair::generate());
+    let mut node1 = open_docs_with_key(PREFIX, "edges", "node1", &node1_key, true);
+    let mut node2 = open_docs_with_key(PREFIX, "edges", "node2", &node2_key, true);
+    let alice = entity::create(&mut node1, "person", "Alice", BTreeMap::new()).unwrap();
+    let bob = entity::create(&mut node2, "person", "Bob", BTreeMap::new()).unwrap();
+    sync(&mut node2, &mut node1, &bob);
+
+    let rail = rails::create_rail(&mut node1, &kind(), &[alice, bob], RailMetadata::new()).unwrap();
+    sync(&mut node1, &mut node2, &rail);
+
+    rails::set_weight(&mut node1, &rail, &alice, &bob, 0.8).unwrap();
+    rails::set_weight(&mut node2, &rail, &bob, &alice, 0.3).unwrap();
+    let refused = rails::set_weight(&mut node2, &rail, &alice, &bob, 1.0);
+    assert!(matches!(refused, Err(DocError::Rejected(_))));
+    sync(&mut node1, &mut node2, &rail);
+
+    for docs in [&node1, &node2] {
+        let rail = rails::get_rail(docs, &rail).unwrap().unwrap();
+        assert_eq!(rail.weight(&alice, &bob), Some(0.8));
+        assert_eq!(rail.weight(&bob, &alice), Some(0.3));
+    }
+
+    // An entry node2 signs for someone else's edge is refused by node1 too
+    let key = format!("weight/{alice}/{bob}");
+    let later = node1.doc(&rail).unwrap().entry(&key).unwrap().timestamp + 1;
+    let forged = Entry::owned(&rail, &key, Some(to_cbor(&0.0f64).unwrap()), &node2_key, later);
+    let (applied, rejected) = node1.apply(&rail, vec![forged]).unwrap();
+    assert_eq!(applied, 0);
+    assert!(matches!(rejected[0].1, DocError::Rejected(_)));
+    assert_eq!(rails::get_rail(&node1, &rail).unwrap().unwrap().weight(&alice, &bob), Some(0.8));
+}
+
+#[test]
+fn test_add_and_remove_participants() {
+    let (node1_key, node2_key) = (ed25519::Keypair::generate(), ed25519::Keypair::generate());
+    let mut node1 = open_docs_with_key(PREFIX, "participants", "node1", &node1_key, true);
+    let mut node2 = open_docs_with_key(PREFIX, "participants", "node2", &node2_key, true);
+    let alice = entity::create(&mut node1, "person", "Alice", BTreeMap::new()).unwrap();
+    let bob = entity::create(&mut node2, "person", "Bob", BTreeMap::new()).unwrap();
+    let rail = rails::create_rail(&mut node1, &kind(), &[alice], RailMetadata::new()).unwrap();
+
+    // Bob's owner adds Bob to a rail it replicates
+    sync(&mut node1, &mut node2, &rail);
+    rails::add_participant(&mut node2, &rail, &bob).unwrap();
+    assert!(rails::rails_of(&node2, &bob).unwrap().contains_key(&rail));
+    rails::set_weight(&mut node2, &rail, &bob, &alice, 0.5).unwrap();
+    sync(&mut node1, &mut node2, &rail);
+    assert_eq!(rails::get_rail(&node1, &rail).unwrap().unwrap().entities.len(), 2);
+
+    // node2 can't take Alice off, but can take Bob off, and his edges go with him
+    assert!(matches!(rails::remove_participant(&mut node2, &rail, &alice), Err(DocError::Rejected(_))));
+    rails::remove_participant(&mut node2, &rail, &bob).unwrap();
+    assert!(rails::rails_of(&node2, &bob).unwrap().is_empty());
+    sync(&mut node1, &mut node2, &rail);
+    let on_node1 = rails::get_rail(&node1, &rail).unwrap().unwrap();
+    assert_eq!(on_node1.entities, vec![alice]);
+    assert!(on_node1.weights.is_empty());
+
+    // Weights can't point at entities that aren't on the rail
+    let refused = rails::set_weight(&mut node1, &rail, &alice, &bob, 0.1);
+    assert!(matches!(refused, Err(DocError::Rejected(_))));
+}
+
+#[test]
+fn test_invitee_cannot_take_the_rail_over() {
+    let (node1_key, node2_key) = (ed25519::Keypair::generate(), ed25519::Keypair::generate());
+    let mut node1 = open_docs_with_key(PREFIX, "takeover", "node1", &node1_key, true);
+    let mut node2 = open_docs_with_key(PREFIX, "takeover", "node2", &node2_key, true);
+    let alice = entity::create(&mut node1, "person", "Alice", BTreeMap::new()).unwrap();
+    let bob = entity::create(&mut node2, "person", "Bob", BTreeMap::new()).unwrap();
+    sync(&mut node2, &mut node1, &bob);
+    let rail = rails::create_rail(&mut node1, &kind(), &[alice, bob], RailMetadata::new()).unwrap();
+    let invite = rails::invite(&node1, &rail, &bob).unwrap();
+    assert_eq!(invite.ticket.mode(), ShareMode::Read);
+    sync(&mut node1, &mut node2, &rail);
+
+    // node2 claims the rail with node1's seed, and rewrites what only the
+    // creator may, each entry later than node1's
+    let held = node1.doc(&rail).unwrap().clone();
+    let forge = |key: &str, value: Vec<u8>| {
+        let later = held.entry(key).unwrap().timestamp + 1;
+        Entry::owned(&rail, key, Some(value), &node2_key, later)
+    };
+    let node1_creator: Creator = from_cbor(held.get("creator").unwrap()).unwrap();
+    let claim = Creator { author: node2.author(), seed: node1_creator.seed };
+    let forged = vec![
+        forge("creator", to_cbor(&claim).unwrap()),
+        forge("meta", to_cbor(&RailMetadata::from([("owner".to_string(), AttrValue::Text("node2".into()))])).unwrap()),
+        forge(&format!("entity/{alice}"), Vec::new()),
+    ];
+
+    // A fresh replica that hears from node2 first still ends up with node1's rail
+    let mut node3 = open_docs(PREFIX, "takeover", "node3", true);
+    node3.join(&invite.ticket).unwrap();
+    let (applied, rejected) = node3.apply(&rail, forged.clone()).unwrap();
+    assert_eq!(applied, 0);
+    assert_eq!(rejected.len(), 3);
+    let (_, rejected) = node3.apply(&rail, held.entries().cloned().collect()).unwrap();
+    assert!(rejected.is_empty());
+    let (applied, rejected) = node3.apply(&rail, forged.clone()).unwrap();
+    assert_eq!(applied, 0);
+    assert!(rejected.iter().all(|(_, e)| matches!(e, DocError::Rejected(_))));
+    assert_eq!(rails::get_rail(&node3, &rail).unwrap(), rails::get_rail(&node1, &rail).unwrap());
+
+    // node2 can't write them locally either
+    assert!(matches!(node2.set(&rail, "meta", Vec::new()), Err(DocError::Rejected(_))));
+    assert!(matches!(node2.set(&rail, "creator", to_cbor(&claim).unwrap()), Err(DocError::Rejected(_))));
+}
@@ -0,0 +1,74 @@
+mod common;
+
+use std::collections::BTreeMap;
+
+use raggy_p2p::docs::{DocError, Entry, TICKET_READ_DOC, TICKET_WRITE_DOC};
+use raggy_p2p::entity::{self, AttrValue};
+use raggy_p2p::rail_kind::RailKind;
+use raggy_p2p::rails::{self, RailMetadata};
+use raggy_p2p::ticket::{find_tickets, ShareMode};
+
+use common::{open_docs, propagate};
+
+const PREFIX: &str = "rails";
+
+fn kind() -> RailKind {
+    "test.raggy/colleague".parse().unwrap()
+}
+
+#[test]
+fn test_both_entities_can_look_up_and_open_the_rail() {
+    let mut node1 = open_docs(PREFIX, "lookup", "node1", true);
+    let alice = entity::create(&mut node1, "person", "Alice", BTreeMap::new()).unwrap();
+    let bob = entity::create(&mut node1, "person", "Bob", BTreeMap::new()).unwrap();
+    let metadata: RailMetadata = [("relation".to_string(), AttrValue::Text("colleague".into()))].into();
+    let rail = rails::create_rail(&mut node1, &kind(), &[alice, bob], metadata.clone()).unwrap();
+
+    // Tickets for the rail are kept as ABOUT.md describes
+    let read_tickets = find_tickets(node1.doc(&node1.owned_id(TICKET_READ_DOC)).unwrap());
+    assert!(read_tickets.iter().any(|ticket| ticket.doc == rail && ticket.mode() == ShareMode::Read));
+    assert!(node1.doc(&node1.owned_id(TICKET_WRITE_DOC)).is_none(), "rails have no write ticket to keep");
+
+    // Another node replicating either entity finds the rail in it...
+    let mut node2 = open_docs(PREFIX, "lookup", "node2", true);
+    for entity in [alice, bob] {
+        propagate(&node1, &mut node2, &entity);
+        assert_eq!(rails::rails_of(&node2, &entity).unwrap(), [(rail, metadata.clone())].into());
+    }
+
+    // ...and can open it to evaluate. It only turns into a rail once the
+    // kind arrives, which names the creator for the policy to judge.
+    assert_eq!(rails::open_rail(&mut node2, &rail).unwrap(), rail);
+    let entries: Vec<Entry> = node1.doc(&rail).unwrap().entries().cloned().collect();
+    assert_eq!(rails::arriving_creator(node2.doc(&rail).unwrap(), &entries), Some(node1.author()));
+    propagate(&node1, &mut node2, &rail);
+    assert_eq!(rails::arriving_creator(node2.doc(&rail).unwrap(), &entries), None);
+    let opened = rails::get_rail(&node2, &rail).unwrap().unwrap();
+    assert_eq!(opened.metadata, metadata);
+    let mut linked = opened.entities.clone();
+    linked.sort();
+    let mut expected = vec![alice, bob];
+    expected.sort();
+    assert_eq!(linked, expected);
+
+    // Anyone may write to a rail, but only what the rules give them
+    assert!(matches!(node2.set(&rail, "note", b"hi".to_vec()), Err(DocError::Rejected(_))));
+    assert!(node1.set(&rail, "note", b"hi".to_vec()).is_ok());
+
+    // Plain entity documents aren't rails
+    assert_eq!(rails::get_rail(&node1, &alice).unwrap(), None);
+}
+
+#[test]
+fn test_rail_only_writes_to_entities_we_own() {
+    let mut node1 = open_docs(PREFIX, "foreign", "node1", true);
+    let mut node2 = open_docs(PREFIX, "foreign", "node2", true);
+    let ours = entity::create(&mut node1, "person", "Alice", BTreeMap::new()).unwrap();
+    let theirs = entity::create(&mut node2, "person", "Bob", BTreeMap::new()).unwrap();
+    propagate(&node2, &mut node1, &theirs);
+
+    let rail = rails::create_rail(&mut node1, &kind(), &[ours, theirs], RailMetadata::new()).unwrap();
+    assert!(rails::rails_of(&node1, &ours).unwrap().contains_key(&rail));
+    assert!(rails::rails_of(&node1, &theirs).unwrap().is_empty());
+    assert_eq!(rails::get_rail(&node1, &rail).unwrap().unwrap().entities.len(), 2);
+}
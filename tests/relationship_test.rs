@@ -0,0 +1,126 @@
+mod common;
+
+use std::collections::BTreeMap;
+
+use libp2p::identity::ed25519;
+use raggy_p2p::codec::to_cbor;
+use raggy_p2p::docs::{AuthorId, DocError, Docs, Entry};
+use raggy_p2p::entity::{self, AttrValue, EntityId};
+use raggy_p2p::relationship::{self, Relationship};
+use raggy_p2p::ticket::ShareMode;
+
+use common::open_docs;
+
+const PREFIX: &str = "relationship";
+
+/// Reconcile both nodes' replicas of the RelationshipDoc of `entity`, as a
+/// sync session between them would
+fn sync(a: &mut Docs, b: &mut Docs, entity: &EntityId) {
+    let id = relationship::open(a, entity).unwrap();
+    relationship::open(b, entity).unwrap();
+    common::sync(a, b, &id);
+}
+
+#[test]
+fn test_relationship_doc_ids_are_derived_from_the_entity() {
+    let mut alice = open_docs(PREFIX, "ids", "alice", false);
+    let mut bob = open_docs(PREFIX, "ids", "bob", false);
+    let dataset = entity::create(&mut alice, "dataset", "weather", BTreeMap::new()).unwrap();
+    let station = entity::create(&mut alice, "place", "station", BTreeMap::new()).unwrap();
+
+    // Anyone can work out where an entity's relationships live without asking its owner
+    let id = relationship::doc_id(&dataset);
+    assert_eq!(relationship::open(&mut alice, &dataset).unwrap(), id);
+    assert_eq!(relationship::open(&mut bob, &dataset).unwrap(), id);
+    assert_ne!(id, dataset);
+    assert_ne!(id, relationship::doc_id(&station));
+    assert!(String::from_utf8(relationship::provider_key(&dataset)).unwrap().ends_with(&id.to_string()));
+    assert!(matches!(alice.share(&id, ShareMode::Write, Vec::new()), Err(DocError::ReadOnly(_))));
+
+    let measured_at = Relationship::new("measured-at").attr("since", AttrValue::Int(2019));
+    relationship::set(&mut bob, &dataset, &station, &measured_at).unwrap();
+    assert_eq!(relationship::get(&bob, &dataset), [((bob.author(), station), measured_at.clone())].into());
+
+    relationship::remove(&mut bob, &dataset, &station).unwrap();
+    assert!(relationship::get(&bob, &dataset).is_empty());
+    assert!(relationship::get(&bob, &station).is_empty(), "documents we don't hold have no relationships");
+}
+
+#[test]
+fn test_authors_only_write_their_own_relationships() {
+    let mut alice = open_docs(PREFIX, "authors", "alice", false);
+    let mut mallory = open_docs(PREFIX, "authors", "mallory", false);
+    let mallory_key = ed25519::Keypair::generate();
+    let dataset = entity::create(&mut alice, "dataset", "weather", BTreeMap::new()).unwrap();
+    let station = entity::create(&mut alice, "place", "station", BTreeMap::new()).unwrap();
+    let id = relationship::open(&mut alice, &dataset).unwrap();
+    relationship::open(&mut mallory, &dataset).unwrap();
+
+    // Writing under someone else's keys is refused locally...
+    let alice_key = format!("{}/{station}", alice.author());
+    let value = to_cbor(&Relationship::new("fake")).unwrap();
+    assert!(matches!(mallory.set(&id, &alice_key, value.clone()), Err(DocError::NotOwner(_))));
+
+    // ...and forged entries are rejected when they arrive
+    let forged = Entry::owned(&id, &alice_key, Some(value), &mallory_key, 1);
+    let (applied, rejected) = alice.apply(&id, vec![forged]).unwrap();
+    assert_eq!(applied, 0);
+    assert!(matches!(rejected[0].1, DocError::NotOwner(author) if author == AuthorId::of(&mallory_key)));
+    assert!(relationship::get(&alice, &dataset).is_empty());
+}
+
+#[test]
+fn test_concurrent_updates_lose_no_entries() {
+    let mut nodes: Vec<Docs> = ["a", "b", "c"].iter().map(|node| open_docs(PREFIX, "concurrent", node, false)).collect();
+    let dataset = entity::create(&mut nodes[0], "dataset", "weather", BTreeMap::new()).unwrap();
+    let others: Vec<EntityId> = (0..4)
+        .map(|n| entity::create(&mut nodes[0], "place", &format!("station {n}"), BTreeMap::new()).unwrap())
+        .collect();
+
+    // Every node writes about the same entities between partial syncs, and
+    // rewrites some of what it wrote before
+    for round in 0..3 {
+        for (n, docs) in nodes.iter_mut().enumerate() {
+            for other in &others[(n + round) % 2..] {
+                let relationship = Relationship::new("near").attr("round", AttrValue::Int(round as i64));
+                relationship::set(docs, &dataset, other, &relationship).unwrap();
+            }
+        }
+        let (a, rest) = nodes.split_at_mut(1);
+        let (b, c) = rest.split_at_mut(1);
+        match round {
+            0 => sync(&mut a[0], &mut b[0], &dataset),
+            1 => sync(&mut b[0], &mut c[0], &dataset),
+            _ => sync(&mut c[0], &mut a[0], &dataset),
+        }
+    }
+    relationship::remove(&mut nodes[1], &dataset, &others[3]).unwrap();
+
+    // Once everyone has synced, every replica holds every author's latest entries
+    for _ in 0..2 {
+        let (a, rest) = nodes.split_at_mut(1);
+        let (b, c) = rest.split_at_mut(1);
+        sync(&mut a[0], &mut b[0], &dataset);
+        sync(&mut b[0], &mut c[0], &dataset);
+    }
+    let authors: Vec<AuthorId> = nodes.iter().map(|docs| docs.author()).collect();
+    let expected: BTreeMap<(AuthorId, EntityId), Relationship> = authors
+        .iter()
+        .enumerate()
+        .flat_map(|(n, author)| {
+            others
+                .iter()
+                .enumerate()
+                .filter(move |(o, _)| !(n == 1 && *o == 3))
+                .map(move |(o, other)| {
+                    // The last round each node wrote this one in
+                    let round = (0..3).rev().find(|round| o >= (n + round) % 2).unwrap();
+                    ((*author, *other), Relationship::new("near").attr("round", AttrValue::Int(round as i64)))
+                })
+        })
+        .collect();
+    assert_eq!(expected.len(), 11);
+    for docs in &nodes {
+        assert_eq!(relationship::get(docs, &dataset), expected);
+    }
+}
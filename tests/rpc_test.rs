@@ -0,0 +1,104 @@
+use std::collections::HashSet;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use libp2p::PeerId;
+use raggy_p2p::rpc::{HandlerRegistry, RpcRequest, RpcResponse};
+use raggy_p2p::ticket::ShareMode;
+use raggy_p2p::{NodeError, NodeHandle};
+use tokio::sync::Mutex;
+use tokio::time::sleep;
+
+fn spawn_node(port: u16, name: &str) -> (NodeHandle, tokio::task::JoinHandle<()>) {
+    let (handle, inbox) = NodeHandle::new();
+    let args = vec!["test".to_string(), "--port".to_string(), port.to_string(), "--name".to_string(), name.to_string()];
+    let task = tokio::spawn(async move {
+        raggy_p2p::run_node_with_handle(args, Arc::new(Mutex::new(HashSet::new())), inbox).await.unwrap();
+    });
+    (handle, task)
+}
+
+/// Start two nodes and wait until the first is connected to the second,
+/// returning the second's peer id
+async fn connected_pair(ports: (u16, u16), name: &str) -> (NodeHandle, NodeHandle, PeerId, [tokio::task::JoinHandle<()>; 2]) {
+    let (client, client_task) = spawn_node(ports.0, &format!("{name}-client"));
+    let (server, server_task) = spawn_node(ports.1, &format!("{name}-server"));
+    // Nodes list themselves in the tickets they hand out
+    let main_doc = server.main_doc().await.unwrap();
+    let server_id = server.share_doc(main_doc, ShareMode::Read).await.unwrap().peers[0].peer;
+    let deadline = Instant::now() + Duration::from_secs(30);
+    while !client.peer_scores().await.unwrap().contains_key(&server_id) {
+        assert!(Instant::now() < deadline, "nodes should discover each other");
+        sleep(Duration::from_millis(250)).await;
+    }
+    (client, server, server_id, [client_task, server_task])
+}
+
+#[test]
+fn test_registry_routes_by_method() {
+    let mut registry = HandlerRegistry::default();
+    registry.register("echo", Arc::new(|_peer: PeerId, body: &[u8]| -> Result<Vec<u8>, String> { Ok(body.to_vec()) }));
+    registry.register("fail", Arc::new(|_peer: PeerId, _body: &[u8]| -> Result<Vec<u8>, String> {
+        Err("no such document".to_string())
+    }));
+
+    let peer = PeerId::random();
+    let request = |method: &str| RpcRequest { method: method.to_string(), body: b"ticket".to_vec() };
+
+    assert!(matches!(registry.handle(peer, &request("echo")), RpcResponse::Ok(body) if body == b"ticket"));
+    assert!(matches!(registry.handle(peer, &request("fail")), RpcResponse::Err(e) if e == "no such document"));
+    assert!(matches!(registry.handle(peer, &request("missing")), RpcResponse::Err(_)));
+
+    assert!(registry.unregister("echo"));
+    assert!(matches!(registry.handle(peer, &request("echo")), RpcResponse::Err(_)));
+}
+
+#[tokio::test]
+async fn test_request_round_trip() {
+    let (client, server, server_id, tasks) = connected_pair((8041, 8042), "round-trip").await;
+    server
+        .register_handler("echo", |_peer, body| {
+            let mut reply = b"echo: ".to_vec();
+            reply.extend_from_slice(body);
+            Ok(reply)
+        })
+        .unwrap();
+    server.register_handler("fail", |_peer, _body| Err("no such document".to_string())).unwrap();
+
+    assert_eq!(client.request(server_id, "echo", b"hello".to_vec()).await.unwrap(), b"echo: hello");
+    let reply = client.request_with_timeout(server_id, "echo", b"again".to_vec(), Duration::from_secs(5)).await;
+    assert_eq!(reply.unwrap(), b"echo: again");
+    let failed = client.request(server_id, "fail", Vec::new()).await;
+    assert!(matches!(failed, Err(NodeError::Rpc(e)) if e == "no such document"));
+
+    tasks.iter().for_each(|task| task.abort());
+}
+
+#[tokio::test]
+async fn test_unknown_method_is_an_error() {
+    let (client, _server, server_id, tasks) = connected_pair((8043, 8044), "unknown").await;
+    let result = client.request(server_id, "missing", Vec::new()).await;
+    assert!(matches!(result, Err(NodeError::Rpc(e)) if e.contains("unknown method 'missing'")));
+    tasks.iter().for_each(|task| task.abort());
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_request_times_out() {
+    let (client, server, server_id, tasks) = connected_pair((8045, 8046), "timeout").await;
+    // Handlers run on the node's event loop, so this one holds the reply back
+    server
+        .register_handler("slow", |_peer, _body| {
+            std::thread::sleep(Duration::from_secs(2));
+            Ok(Vec::new())
+        })
+        .unwrap();
+
+    let result = client.request_with_timeout(server_id, "slow", Vec::new(), Duration::from_millis(500)).await;
+    assert!(matches!(result, Err(NodeError::Timeout)), "got {result:?}");
+
+    // The node keeps serving once the handler returns
+    let result = client.request_with_timeout(server_id, "slow", Vec::new(), Duration::from_secs(10)).await;
+    assert!(result.is_ok(), "got {result:?}");
+
+    tasks.iter().for_each(|task| task.abort());
+}
@@ -0,0 +1,76 @@
+use std::path::PathBuf;
+
+use raggy_p2p::blob::{Manifest, CHUNK_SIZE};
+use raggy_p2p::store::{BlobStore, Cid, StoreError};
+
+fn data_dir(test: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("raggy-store-{test}-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    dir
+}
+
+#[test]
+fn test_cid_round_trips_through_string_and_bytes() {
+    let cid = Cid::of(b"hello raggy");
+    let text = cid.to_string();
+    assert!(text.starts_with("bafkr4i"), "unexpected cid {text}");
+    assert_eq!(text.parse::<Cid>().unwrap(), cid);
+    assert_eq!(Cid::from_bytes(&cid.to_bytes()).unwrap(), cid);
+    assert!(matches!("zQmNotBase32".parse::<Cid>(), Err(StoreError::InvalidCid(_))));
+}
+
+#[test]
+fn test_put_get_has_delete() {
+    let store = BlobStore::open(&data_dir("crud")).unwrap();
+    let cid = store.put(b"some blob").unwrap();
+    assert_eq!(cid, Cid::of(b"some blob"));
+    assert!(store.has(&cid));
+    assert_eq!(store.get(&cid).unwrap().as_deref(), Some(&b"some blob"[..]));
+    assert_eq!(store.list().unwrap(), vec![cid]);
+
+    assert!(store.delete(&cid).unwrap());
+    assert!(!store.has(&cid));
+    assert_eq!(store.get(&cid).unwrap(), None);
+    assert!(!store.delete(&cid).unwrap());
+}
+
+#[test]
+fn test_gc_keeps_pinned_blobs() {
+    let dir = data_dir("gc");
+    let store = BlobStore::open(&dir).unwrap();
+    let kept = store.put(b"pinned").unwrap();
+    let dropped = store.put(b"unpinned").unwrap();
+    store.pin(&kept).unwrap();
+    assert!(matches!(store.pin(&Cid::of(b"missing")), Err(StoreError::NotFound(_))));
+
+    assert_eq!(store.gc().unwrap(), vec![dropped]);
+    assert!(store.has(&kept));
+    assert!(!store.has(&dropped));
+
+    // Reopening sees the same blobs and pins
+    let reopened = BlobStore::open(&dir).unwrap();
+    assert!(reopened.is_pinned(&kept));
+    assert!(reopened.unpin(&kept).unwrap());
+    assert_eq!(reopened.gc().unwrap(), vec![kept]);
+}
+
+#[test]
+fn test_chunks_and_manifests_are_read_on_their_own() {
+    let dir = data_dir("chunks");
+    let store = BlobStore::open(&dir).unwrap();
+    let data: Vec<u8> = (0..(CHUNK_SIZE * 2 + 100)).map(|i| (i % 251) as u8).collect();
+    let cid = store.put(&data).unwrap();
+
+    assert_eq!(store.manifest(&cid).unwrap(), Some(Manifest::for_blob(&data)));
+    assert_eq!(store.read_chunk(&cid, 1).unwrap().as_deref(), Some(&data[CHUNK_SIZE..CHUNK_SIZE * 2]));
+    assert_eq!(store.read_chunk(&cid, 2).unwrap().as_deref(), Some(&data[CHUNK_SIZE * 2..]));
+    assert_eq!(store.read_chunk(&cid, 3).unwrap(), None);
+    assert_eq!(store.read_chunk(&Cid::of(b"missing"), 0).unwrap(), None);
+
+    // Blobs stored before manifests were kept get one when first asked
+    std::fs::remove_dir_all(dir.join("manifests")).unwrap();
+    let reopened = BlobStore::open(&dir).unwrap();
+    assert_eq!(reopened.manifest(&cid).unwrap(), Some(Manifest::for_blob(&data)));
+    assert!(reopened.delete(&cid).unwrap());
+    assert_eq!(reopened.manifest(&cid).unwrap(), None);
+}
@@ -0,0 +1,74 @@
+use libp2p::identity::ed25519;
+use raggy_p2p::docs::{Document, Entry};
+use raggy_p2p::sync::{self, reconcile, Fingerprint};
+
+/// Two writable replicas of one document, each with the same `shared` entries
+fn replicas(shared: usize) -> (Document, Document, ed25519::Keypair) {
+    let namespace = ed25519::Keypair::generate();
+    let mut a = Document::with_namespace(namespace.secret().as_ref()).unwrap();
+    let mut b = a.clone();
+    for i in 0..shared {
+        let entry = Entry::new(&namespace, &format!("key/{i:04}"), Some(vec![i as u8]), &namespace, 1);
+        a.insert(entry.clone()).unwrap();
+        b.insert(entry).unwrap();
+    }
+    (a, b, namespace)
+}
+
+fn assert_converged(a: &Document, b: &Document) {
+    assert_eq!(sync::heads(a), sync::heads(b));
+    let a_entries: Vec<&Entry> = a.entries().collect();
+    let b_entries: Vec<&Entry> = b.entries().collect();
+    assert_eq!(a_entries, b_entries);
+}
+
+#[test]
+fn test_identical_replicas_agree_in_one_round() {
+    let (mut a, mut b, _) = replicas(100);
+    let stats = reconcile(&mut a, &mut b);
+    assert_eq!(stats.rounds, 1);
+    assert_eq!(stats.transferred, 0);
+}
+
+#[test]
+fn test_divergent_replicas_converge() {
+    let (mut a, mut b, namespace) = replicas(50);
+    let alice = ed25519::Keypair::generate();
+    let bob = ed25519::Keypair::generate();
+    a.write(&alice, "only/a", Some(b"a".to_vec())).unwrap();
+    b.write(&bob, "only/b", Some(b"b".to_vec())).unwrap();
+    // Both sides changed the same key; the later write must win everywhere
+    a.insert(Entry::new(&namespace, "key/0007", Some(b"old".to_vec()), &alice, 10)).unwrap();
+    b.insert(Entry::new(&namespace, "key/0007", Some(b"new".to_vec()), &bob, 20)).unwrap();
+    b.write(&bob, "key/0010", None).unwrap();
+
+    let stats = reconcile(&mut a, &mut b);
+    assert_converged(&a, &b);
+    assert_eq!(a.get("only/b"), Some(&b"b"[..]));
+    assert_eq!(b.get("only/a"), Some(&b"a"[..]));
+    assert_eq!(a.get("key/0007"), Some(&b"new"[..]));
+    assert_eq!(a.get("key/0010"), None);
+    assert_eq!(stats.rejected, 0);
+}
+
+#[test]
+fn test_small_difference_in_large_document_is_not_a_full_dump() {
+    let (mut a, mut b, namespace) = replicas(2_000);
+    b.insert(Entry::new(&namespace, "key/1234", Some(b"changed".to_vec()), &namespace, 2)).unwrap();
+
+    let stats = reconcile(&mut a, &mut b);
+    assert_converged(&a, &b);
+    assert_eq!(a.get("key/1234"), Some(&b"changed"[..]));
+    assert!(stats.transferred < 100, "transferred {} entries", stats.transferred);
+}
+
+#[test]
+fn test_empty_replica_receives_everything() {
+    let (mut a, _, _) = replicas(300);
+    let mut empty = Document::replica(a.id());
+    assert_eq!(Fingerprint::of(&a.id(), empty.entries()), Fingerprint::default());
+
+    let stats = reconcile(&mut empty, &mut a);
+    assert_converged(&a, &empty);
+    assert_eq!(stats.applied, 300);
+}
@@ -0,0 +1,82 @@
+mod common;
+
+use libp2p::{identity::ed25519, PeerId};
+use raggy_p2p::docs::{DocError, Entry};
+use raggy_p2p::sync::reconcile;
+use raggy_p2p::ticket::{Capability, DocTicket, ShareMode, TicketPeer};
+
+use common::open_docs;
+
+const PREFIX: &str = "ticket";
+
+fn peers() -> Vec<TicketPeer> {
+    vec![TicketPeer { peer: PeerId::random(), addrs: vec!["/ip4/127.0.0.1/tcp/4001".parse().unwrap()] }]
+}
+
+#[test]
+fn test_ticket_round_trips_through_string() {
+    let mut docs = open_docs(PREFIX, "string", "owner", false);
+    let id = docs.create().unwrap();
+    for mode in [ShareMode::Read, ShareMode::Write] {
+        let ticket = docs.share(&id, mode, peers()).unwrap();
+        let text = ticket.to_string();
+        assert!(text.starts_with("doc"));
+        let parsed: DocTicket = text.parse().unwrap();
+        assert_eq!(parsed, ticket);
+        assert_eq!(parsed.mode(), mode);
+    }
+    assert!(matches!("notaticket".parse::<DocTicket>(), Err(DocError::InvalidTicket(_))));
+}
+
+#[test]
+fn test_read_ticket_holders_cannot_write() {
+    let mut owner = open_docs(PREFIX, "read", "owner", false);
+    let id = owner.create().unwrap();
+    owner.set(&id, "name", b"owner".to_vec()).unwrap();
+
+    let mut reader = open_docs(PREFIX, "read", "reader", false);
+    reader.join(&owner.share(&id, ShareMode::Read, peers()).unwrap()).unwrap();
+    assert!(matches!(reader.set(&id, "name", b"reader".to_vec()), Err(DocError::ReadOnly(_))));
+    // Without the namespace secret there's nothing to re-share as writable either
+    assert!(matches!(reader.share(&id, ShareMode::Write, peers()), Err(DocError::ReadOnly(_))));
+
+    // Reading still works once synced
+    let mut owner_replica = owner.doc(&id).unwrap().clone();
+    let mut reader_replica = reader.doc(&id).unwrap().clone();
+    reconcile(&mut reader_replica, &mut owner_replica);
+    assert_eq!(reader_replica.get("name"), Some(&b"owner"[..]));
+
+    // An entry signed with some other namespace key is refused by the owner
+    let forger = ed25519::Keypair::generate();
+    let later = owner.doc(&id).unwrap().entry("name").unwrap().timestamp + 1;
+    let forged = Entry::new(&forger, "name", Some(b"reader".to_vec()), &forger, later);
+    let (applied, rejected) = owner.apply(&id, vec![forged]).unwrap();
+    assert_eq!((applied, rejected.len()), (0, 1));
+    assert_eq!(owner.get(&id, "name").unwrap(), Some(b"owner".to_vec()));
+}
+
+#[test]
+fn test_write_ticket_grants_accepted_writes() {
+    let mut owner = open_docs(PREFIX, "write", "owner", false);
+    let id = owner.create().unwrap();
+    let ticket = owner.share(&id, ShareMode::Write, peers()).unwrap();
+
+    let mut writer = open_docs(PREFIX, "write", "writer", false);
+    assert_eq!(writer.join(&ticket).unwrap(), id);
+    let entry = writer.set(&id, "name", b"writer".to_vec()).unwrap();
+    let (applied, rejected) = owner.apply(&id, vec![entry]).unwrap();
+    assert_eq!((applied, rejected.len()), (1, 0));
+    assert_eq!(owner.get(&id, "name").unwrap(), Some(b"writer".to_vec()));
+
+    // A read replica is upgraded by a later write ticket
+    let mut upgraded = open_docs(PREFIX, "write", "upgraded", false);
+    upgraded.join(&owner.share(&id, ShareMode::Read, peers()).unwrap()).unwrap();
+    upgraded.join(&ticket).unwrap();
+    assert!(upgraded.set(&id, "name", b"upgraded".to_vec()).is_ok());
+
+    // A write ticket whose secret belongs to another namespace is refused
+    let other = owner.create().unwrap();
+    let mismatched = DocTicket { doc: id, ..owner.share(&other, ShareMode::Write, peers()).unwrap() };
+    assert!(matches!(writer.join(&mismatched), Err(DocError::InvalidTicket(_))));
+    assert!(matches!(mismatched.capability, Capability::Write(_)));
+}
@@ -0,0 +1,107 @@
+use std::collections::BTreeMap;
+
+use libp2p::identity::{self, ed25519};
+use libp2p::PeerId;
+use raggy_p2p::docs::{AuthorId, DocId, Entry};
+use raggy_p2p::entity::EntityId;
+use raggy_p2p::graph::{Propagation, TrustGraph};
+use raggy_p2p::message::RaggyMessage;
+use raggy_p2p::trust::{TrustFilter, TrustMeasure, TrustView, Weighting};
+
+fn id(n: u8) -> EntityId {
+    DocId::from_bytes([n; 32])
+}
+
+/// The viewer's entity 1 trusts Alice's 2, who trusts Bob's 3. Mallory's 4
+/// trusts the viewer, which doesn't make the viewer trust Mallory.
+struct World {
+    viewer: ed25519::Keypair,
+    alice: ed25519::Keypair,
+    bob: ed25519::Keypair,
+    mallory: ed25519::Keypair,
+    graph: TrustGraph,
+    owners: BTreeMap<EntityId, AuthorId>,
+}
+
+fn world() -> World {
+    let keys: Vec<ed25519::Keypair> = (0..4).map(|_| ed25519::Keypair::generate()).collect();
+    let mut graph = TrustGraph::default();
+    graph.add_edge(id(1), id(2), 0.9);
+    graph.add_edge(id(2), id(3), 0.5);
+    graph.add_edge(id(4), id(1), 1.0);
+    let owners = keys.iter().enumerate().map(|(n, key)| (id(n as u8 + 1), AuthorId::of(key))).collect();
+    let [viewer, alice, bob, mallory] = keys.try_into().ok().unwrap();
+    World { viewer, alice, bob, mallory, graph, owners }
+}
+
+fn review(author: &ed25519::Keypair, text: &str) -> Entry {
+    Entry::new(&ed25519::Keypair::generate(), "review", Some(text.as_bytes().to_vec()), author, 1)
+}
+
+fn texts<T>(weighted: Vec<raggy_p2p::trust::Weighted<T>>, text: impl Fn(&T) -> String) -> Vec<(String, f64)> {
+    weighted.into_iter().map(|item| (text(&item.item), item.weight)).collect()
+}
+
+fn entry_text(entry: &Entry) -> String {
+    String::from_utf8(entry.value.clone().unwrap()).unwrap()
+}
+
+#[test]
+fn test_filter_reviews_down_to_who_the_viewer_trusts() {
+    let world = world();
+    let decayed = TrustMeasure::Decayed { max_hops: 3, decay: 0.5 };
+    let view = TrustView::new(&world.graph, &id(1), &world.owners, &decayed);
+    assert_eq!(view.score(&AuthorId::of(&world.viewer)), 1.0);
+    assert_eq!(view.score(&AuthorId::of(&world.alice)), 0.9);
+    assert!((view.score(&AuthorId::of(&world.bob)) - 0.225).abs() < 1e-9);
+    assert_eq!(view.score(&AuthorId::of(&world.mallory)), 0.0);
+
+    let reviews = vec![
+        review(&world.mallory, "five stars"),
+        review(&world.alice, "good"),
+        review(&world.viewer, "mine"),
+        review(&world.bob, "fine"),
+    ];
+
+    // Only who the viewer trusts, weighted by how much, in the order given
+    let trusted = view.apply(reviews.clone(), &TrustFilter::default()).collect();
+    let trusted = texts(trusted, entry_text);
+    assert_eq!(trusted.iter().map(|(text, _)| text.as_str()).collect::<Vec<_>>(), ["good", "mine", "fine"]);
+    assert_eq!(trusted[0].1, 0.9);
+
+    let close = TrustFilter { threshold: 0.5, weighting: Weighting::Flat };
+    assert_eq!(texts(view.apply(reviews.clone(), &close).collect(), entry_text), [("good".to_string(), 1.0), ("mine".to_string(), 1.0)]);
+
+    let squared = TrustFilter { threshold: 0.5, weighting: Weighting::Power(2.0) };
+    let weights: Vec<f64> = view.apply(reviews.iter().cloned(), &squared).map(|item| item.weight).collect();
+    assert!((weights[0] - 0.81).abs() < 1e-9);
+
+    let everyone = TrustFilter { threshold: -1.0, weighting: Weighting::Linear };
+    let all: Vec<_> = view.apply(reviews, &everyone).collect();
+    assert_eq!(all.len(), 4);
+    assert_eq!((all[0].score, all[0].weight), (0.0, 0.0));
+}
+
+#[test]
+fn test_page_rank_scores_and_gossip_authors() {
+    let world = world();
+    let view = TrustView::new(&world.graph, &id(1), &world.owners, &TrustMeasure::PageRank(Propagation::default()));
+    assert_eq!(view.score(&AuthorId::of(&world.alice)), 1.0);
+    let bob = view.score(&AuthorId::of(&world.bob));
+    assert!(bob > 0.0 && bob < 1.0);
+    assert_eq!(view.score(&AuthorId::of(&world.mallory)), 0.0);
+
+    // Gossip messages are attributed to the peer that signed them
+    let alice_peer = PeerId::from_public_key(&identity::PublicKey::from(world.alice.public()));
+    assert_eq!(AuthorId::from_peer(&alice_peer), Some(AuthorId::of(&world.alice)));
+    assert_eq!(AuthorId::of(&world.alice).peer_id(), Some(alice_peer));
+    let mallory_peer = PeerId::from_public_key(&identity::PublicKey::from(world.mallory.public()));
+    let messages = vec![
+        (Some(alice_peer), RaggyMessage::chat("alice", "hello")),
+        (Some(mallory_peer), RaggyMessage::chat("mallory", "buy now")),
+        (None, RaggyMessage::chat("anonymous", "hi")),
+    ];
+    let kept: Vec<_> = view.apply(messages, &TrustFilter::default()).collect();
+    assert_eq!(kept.len(), 1);
+    assert_eq!(kept[0].item.1.sender, "alice");
+}
@@ -0,0 +1,105 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+
+use libp2p::identity::ed25519;
+use raggy_p2p::docs::{DocId, Document, Namespace};
+use raggy_p2p::sync::{reconcile, SyncWalk, WalkProgress};
+use raggy_p2p::ticket::{Capability, DocTicket};
+
+fn read_ticket(doc: DocId) -> DocTicket {
+    DocTicket { doc, namespace: Namespace::Keyed, capability: Capability::Read, peers: Vec::new() }
+}
+
+/// Documents on a remote node: root -> a, b; a -> c; c -> root; b -> d; d -> e
+fn remote_docs() -> (HashMap<&'static str, DocId>, HashMap<DocId, Document>) {
+    let author = ed25519::Keypair::generate();
+    let names = ["root", "a", "b", "c", "d", "e"];
+    let mut docs: Vec<Document> = names.iter().map(|_| Document::new()).collect();
+    let ids: HashMap<&str, DocId> = names.iter().zip(&docs).map(|(name, doc)| (*name, doc.id())).collect();
+    let links = [("root", "a"), ("root", "b"), ("a", "c"), ("c", "root"), ("b", "d"), ("d", "e")];
+    for (from, to) in links {
+        let doc = &mut docs[names.iter().position(|name| *name == from).unwrap()];
+        let ticket = read_ticket(ids[to]).to_string().into_bytes();
+        doc.write(&author, &format!("tickets/{to}"), Some(ticket)).unwrap();
+        doc.write(&author, "name", Some(from.as_bytes().to_vec())).unwrap();
+    }
+    (ids, docs.into_iter().map(|doc| (doc.id(), doc)).collect())
+}
+
+/// Sync from `root` the way a joining node would, one document at a time
+fn walk(remote: &HashMap<DocId, Document>, root: DocId, max_depth: usize) -> (HashMap<DocId, Document>, Vec<WalkProgress>) {
+    let mut walk = SyncWalk::new(root, max_depth);
+    let mut queue = VecDeque::from([read_ticket(root)]);
+    let mut local = HashMap::new();
+    let mut progress = Vec::new();
+    while let Some(ticket) = queue.pop_front() {
+        // The remote node may not have every document it references
+        let Some(remote_doc) = remote.get(&ticket.doc) else {
+            assert!(walk.failed(&ticket.doc));
+            progress.push(walk.progress(&ticket.doc));
+            continue;
+        };
+        let mut replica = Document::from_ticket(&ticket).unwrap();
+        reconcile(&mut replica, &mut remote_doc.clone());
+        queue.extend(walk.synced(&replica).unwrap());
+        assert!(walk.synced(&replica).is_none(), "A document is only synced once per walk");
+        progress.push(walk.progress(&ticket.doc));
+        local.insert(ticket.doc, replica);
+    }
+    assert!(walk.is_complete());
+    (local, progress)
+}
+
+#[test]
+fn test_walk_follows_tickets_up_to_depth() {
+    let (ids, remote) = remote_docs();
+    let (local, progress) = walk(&remote, ids["root"], 2);
+
+    let expected: HashSet<DocId> = ["root", "a", "b", "c", "d"].iter().map(|name| ids[name]).collect();
+    assert_eq!(local.keys().copied().collect::<HashSet<_>>(), expected);
+    assert_eq!(local[&ids["c"]].get("name"), Some(&b"c"[..]), "Synced documents carry their contents");
+
+    let last = progress.last().unwrap();
+    assert_eq!((last.synced, last.failed, last.discovered, last.depth), (5, 0, 5, 2));
+    assert!(progress.iter().all(|p| p.root == ids["root"]));
+}
+
+#[test]
+fn test_walk_depth_bounds() {
+    let (ids, remote) = remote_docs();
+    let (local, _) = walk(&remote, ids["root"], 0);
+    assert_eq!(local.len(), 1);
+
+    // Deep enough to reach everything; the c -> root cycle doesn't revisit root
+    let (local, progress) = walk(&remote, ids["root"], 10);
+    assert_eq!(local.len(), 6);
+    assert_eq!(progress.len(), 6);
+    assert_eq!(progress.iter().map(|p| p.depth).max(), Some(3));
+}
+
+#[test]
+fn test_walk_completes_past_unknown_docs() {
+    let (ids, mut remote) = remote_docs();
+    let missing = remote.remove(&ids["b"]).unwrap();
+    let (local, progress) = walk(&remote, ids["root"], 10);
+
+    // b is given up on, and d and e behind it are never found
+    assert_eq!(local.len(), 3);
+    let failed: Vec<&WalkProgress> = progress.iter().filter(|p| p.failed > 0).collect();
+    assert_eq!(failed.len(), 2, "progress is reported for the failure and after it");
+    assert_eq!(failed[0].depth, 1);
+    let last = progress.last().unwrap();
+    assert_eq!((last.synced, last.failed, last.discovered), (3, 1, 4));
+
+    // A document given up on that syncs after all counts as synced
+    let mut walk = SyncWalk::new(ids["root"], 1);
+    walk.synced(&remote[&ids["root"]]).unwrap();
+    assert!(walk.failed(&ids["b"]));
+    assert!(!walk.failed(&ids["b"]), "already given up on");
+    assert!(!walk.failed(&ids["d"]), "never part of the walk");
+    assert!(!walk.is_complete());
+    walk.synced(&remote[&ids["a"]]).unwrap();
+    assert!(walk.is_complete());
+    assert_eq!(walk.synced(&missing), Some(Vec::new()));
+    let progress = walk.progress(&ids["b"]);
+    assert_eq!((progress.synced, progress.failed), (3, 0));
+}